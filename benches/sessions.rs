@@ -0,0 +1,172 @@
+//! Session throughput with several workers
+//!
+//! Compares one vault (or store) behind a `Mutex` shared by every worker with the sharded paths over
+//! `ShardedPersistence`: a vault per worker, the way actix-dynamic does, and a clone per request,
+//! the way actix-static does.
+//!
+//!     $ cargo bench --bench sessions > bench_output.txt
+//!
+//! The vault section needs the keys from `KEYS_DIR` (see `generate_certificates.sh`)
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use jwtvault::prelude::*;
+use jwtvault_examples::config::AppConfig;
+use jwtvault_examples::stores::memory::MemoryPersistence;
+use jwtvault_examples::stores::sharded::{ShardedPersistence, DEFAULT_SHARDS};
+use jwtvault_examples::vault::{DynamicStoreVault, SharedUsers, VaultBuilder};
+
+const WORKERS: [usize; 4] = [1, 2, 4, 8];
+const STORE_REQUESTS: usize = 200_000;
+const VAULT_REQUESTS: usize = 2_000;
+const USERS: usize = 8;
+
+fn main() {
+    println!("{:<28} {:>8} {:>14}", "benchmark", "workers", "requests/sec");
+    bench_stores();
+    bench_vaults();
+}
+
+/// Run `requests` per worker on `workers` threads, returning requests per second overall
+fn throughput<F>(workers: usize, requests: usize, request: F) -> f64
+    where F: Fn(usize, usize) + Send + Sync + 'static {
+    let request = Arc::new(request);
+    let started = Instant::now();
+    let handles = (0..workers).map(|worker| {
+        let request = request.clone();
+        thread::spawn(move || {
+            for n in 0..requests {
+                request(worker, n);
+            }
+        })
+    }).collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    (workers * requests) as f64 / started.elapsed().as_secs_f64()
+}
+
+fn report(name: &str, workers: usize, rate: f64) {
+    println!("{:<28} {:>8} {:>14.0}", name, workers, rate);
+}
+
+/// Two loads per request, as when resolving a session, and a store every tenth, as on renew
+fn bench_stores() {
+    for &workers in WORKERS.iter() {
+        let store = Arc::new(Mutex::new(MemoryPersistence::new(None)));
+        seed(&mut *store.lock().unwrap());
+        let rate = throughput(workers, STORE_REQUESTS, move |worker, n| {
+            let mut store = store.lock().unwrap();
+            request(&mut *store, worker, n);
+        });
+        report("store: mutex", workers, rate);
+
+        let mut store = ShardedPersistence::new(None, DEFAULT_SHARDS);
+        seed(&mut store);
+        let rate = throughput(workers, STORE_REQUESTS, move |worker, n| {
+            request(&mut store.clone(), worker, n);
+        });
+        report("store: sharded", workers, rate);
+    }
+}
+
+fn seed<P: Persistence>(store: &mut P) {
+    for key in 0..1000u64 {
+        block_on(store.store(key, key.to_string()));
+    }
+}
+
+fn request<P: Persistence>(store: &mut P, worker: usize, n: usize) {
+    let key = ((worker * 7919 + n) % 1000) as u64;
+    assert!(block_on(store.load(key)).is_some());
+    assert!(block_on(store.load((key + 500) % 1000)).is_some());
+    if n % 10 == 0 {
+        block_on(store.store(key, key.to_string()));
+    };
+}
+
+/// `resolve_session_from_client_authentication_token` for users who logged in up front
+fn bench_vaults() {
+    dotenv::dotenv().ok();
+    let config = AppConfig::from_sources(|var| env::var(var).ok(), Vec::new());
+    if let Err(e) = &config {
+        eprintln!("Skipping vault benchmarks Reason: {}", e.to_string());
+        return;
+    };
+    let config = config.ok().unwrap();
+    let builder = VaultBuilder::new(config.keys.certificate_manager());
+    let hasher = builder.password_hasher();
+    let mut users = HashMap::new();
+    for n in 0..USERS {
+        let user = format!("bench_user_{}", n);
+        let hash = hasher.hash_user_password(user.as_str(), user.as_str()).unwrap();
+        users.insert(user, hash);
+    }
+    let users = SharedUsers::new(users, hasher);
+
+    // `LoginInfo` verifies with a hasher of its own
+    let hasher = ArgonPasswordHasher::default();
+    let mut dynamic_users = HashMap::new();
+    for n in 0..USERS {
+        let user = format!("bench_user_{}", n);
+        let hash = hasher.hash_user_password(user.as_str(), user.as_str()).unwrap();
+        dynamic_users.insert(user, hash);
+    }
+    let store = ShardedPersistence::new(None, DEFAULT_SHARDS);
+    let dynamic = (0..WORKERS[WORKERS.len() - 1]).map(|_| {
+        let info = LoginInfo::new(dynamic_users.clone());
+        let vault = DynamicVault::new(config.keys.certificate_manager(), false, Box::new(info), Box::new(DefaultIdentity));
+        Mutex::new(DynamicStoreVault::new(vault, Box::new(store.clone())))
+    }).collect::<Vec<_>>();
+    let dynamic_tokens = login(&mut *dynamic[0].lock().unwrap());
+    let dynamic = Arc::new(dynamic);
+
+    let mut locked = VaultBuilder::new(config.keys.certificate_manager()).build(users.clone(), MemoryPersistence::new(None));
+    let locked_tokens = login(&mut locked);
+    let locked = Arc::new(Mutex::new(locked));
+
+    let mut sharded = builder.build(users, ShardedPersistence::new(None, DEFAULT_SHARDS));
+    let sharded_tokens = login(&mut sharded);
+
+    for &workers in WORKERS.iter() {
+        let (vault, tokens) = (locked.clone(), locked_tokens.clone());
+        let rate = throughput(workers, VAULT_REQUESTS, move |worker, n| {
+            let (user, token) = &tokens[(worker + n) % USERS];
+            let mut vault = vault.lock().unwrap();
+            let session = block_on(resolve_session_from_client_authentication_token(&mut *vault, user.as_str(), token.as_str()));
+            assert!(session.is_ok());
+        });
+        report("vault: mutex", workers, rate);
+
+        let (vaults, tokens) = (dynamic.clone(), dynamic_tokens.clone());
+        let rate = throughput(workers, VAULT_REQUESTS, move |worker, n| {
+            let (user, token) = &tokens[(worker + n) % USERS];
+            let mut vault = vaults[worker].lock().unwrap();
+            let session = block_on(resolve_session_from_client_authentication_token(&mut *vault, user.as_str(), token.as_str()));
+            assert!(session.is_ok());
+        });
+        report("vault: dynamic per worker", workers, rate);
+
+        let (vault, tokens) = (sharded.clone(), sharded_tokens.clone());
+        let rate = throughput(workers, VAULT_REQUESTS, move |worker, n| {
+            let (user, token) = &tokens[(worker + n) % USERS];
+            let mut vault = vault.clone();
+            let session = block_on(resolve_session_from_client_authentication_token(&mut vault, user.as_str(), token.as_str()));
+            assert!(session.is_ok());
+        });
+        report("vault: sharded", workers, rate);
+    }
+}
+
+fn login<W: Workflow<DefaultHasher, ArgonPasswordHasher>>(vault: &mut W) -> Vec<(String, String)> {
+    (0..USERS).map(|n| {
+        let user = format!("bench_user_{}", n);
+        let token = block_on(vault.login(user.as_str(), user.as_str(), None, None)).unwrap();
+        (user, token.authentication().to_string())
+    }).collect()
+}
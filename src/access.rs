@@ -0,0 +1,183 @@
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+
+use actix_web::{dev::Payload, error::InternalError, web, FromRequest, HttpRequest};
+use jwtvault::prelude::*;
+
+use crate::errors::{error_response, AccessErrors::PermissionDenied};
+use crate::database::profiles::UserProfile;
+
+/// Permissions a route requires, all of them; declared on a marker type
+///
+/// ```ignore
+/// struct CanExecute;
+///
+/// impl Requirement for CanExecute {
+///     const PERMISSIONS: &'static [&'static str] = &["execute"];
+/// }
+/// ```
+pub trait Requirement {
+    const PERMISSIONS: &'static [&'static str];
+}
+
+/// Fails with `AccessErrors::PermissionDenied`, naming the permissions the profile lacks
+pub fn authorize(profile: &UserProfile, required: &[&str]) -> Result<(), Error> {
+    let missing = required.iter()
+        .filter(|permission| !profile.permissions.iter().any(|granted| granted == *permission))
+        .cloned()
+        .collect::<Vec<&str>>();
+    if missing.is_empty() {
+        return Ok(());
+    };
+    let msg = "Access denied".to_string();
+    let reason = format!("Missing permissions: {}", missing.join(", "));
+    Err(PermissionDenied(msg, reason).into())
+}
+
+/// Resolves the session behind an authentication token, implemented by a webserver's shared vault
+#[async_trait(?Send)]
+pub trait SessionResolver {
+    async fn resolve_session(&self, user: &str, token: &str) -> Result<ServerClaims, Error>;
+}
+
+/// Extractor for routes with `{user}` and `{token}` in their path
+///
+/// Resolves the session through the `web::Data<V>` registered with the app and checks the permissions
+/// of `R` against the ones `profiles::ProfileEnricher` put in it at login.
+/// An invalid token is answered like `error_response` would, missing permissions with 403.
+pub struct Authorized<R, V> {
+    user: String,
+    session: ServerClaims,
+    profile: UserProfile,
+    requirement: PhantomData<(R, V)>,
+}
+
+impl<R, V> Authorized<R, V> {
+    pub fn user(&self) -> &str {
+        self.user.as_str()
+    }
+
+    pub fn session(&self) -> &ServerClaims {
+        &self.session
+    }
+
+    pub fn profile(&self) -> &UserProfile {
+        &self.profile
+    }
+}
+
+impl<R, V> FromRequest for Authorized<R, V>
+    where R: Requirement + 'static, V: SessionResolver + 'static {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self, Self::Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let vault = req.app_data::<web::Data<V>>().cloned();
+        let user = req.match_info().get("user").unwrap_or_default().to_string();
+        let token = req.match_info().get("token").unwrap_or_default().to_string();
+        Box::pin(async move {
+            let result = match vault {
+                Some(vault) => authorized::<R, V>(vault.get_ref(), user, token.as_str()).await,
+                None => Err(failure::err_msg("Session resolver is not configured, register it with App::app_data")),
+            };
+            result.map_err(|e| {
+                let response = error_response(&e);
+                InternalError::from_response(e, response).into()
+            })
+        })
+    }
+}
+
+async fn authorized<R: Requirement, V: SessionResolver>(vault: &V, user: String, token: &str) -> Result<Authorized<R, V>, Error> {
+    let session = vault.resolve_session(user.as_str(), token).await?;
+    let profile = UserProfile::from_session(session.client(), session.server());
+    authorize(&profile, R::PERMISSIONS)?;
+    Ok(Authorized { user, session, profile, requirement: PhantomData })
+}
+
+#[cfg(test)]
+mod access {
+    use super::*;
+    use crate::errors::AccessErrors;
+
+    #[test]
+    fn missing_permissions_are_named() {
+        let profile = UserProfile {
+            permissions: vec!["execute".to_string(), "status".to_string()],
+            ..UserProfile::default()
+        };
+        assert!(authorize(&profile, &[]).is_ok());
+        assert!(authorize(&profile, &["status", "execute"]).is_ok());
+
+        let e = authorize(&profile, &["execute", "revoke", "audit"]).err().unwrap();
+        match e.downcast_ref::<AccessErrors>() {
+            Some(PermissionDenied(_, reason)) => assert_eq!(reason, "Missing permissions: revoke, audit"),
+            _ => panic!("Expected PermissionDenied, got: {}", e),
+        };
+        assert!(authorize(&UserProfile::default(), &["execute"]).is_err());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn seeded_users_can_execute() {
+        use std::collections::HashMap;
+        use crate::database::fixtures::Fixtures;
+        use crate::database::profiles::{ProfileEnricher, SessionEnricher};
+        use crate::database::roles::{RoleRepository, SIGNUP_ROLE};
+        use crate::database::users_setup::UserRepository;
+        use crate::database::sqlite::{SqliteDatabase, SqliteUserRepository};
+
+        struct CanExecute;
+
+        impl Requirement for CanExecute {
+            const PERMISSIONS: &'static [&'static str] = &["execute"];
+        }
+
+        struct PlainHasher;
+
+        impl PasswordHasher<String> for PlainHasher {
+            fn hash_user_password<T: AsRef<str>>(&self, _: T, password: T) -> Result<String, Error> {
+                Ok(password.as_ref().to_string())
+            }
+
+            fn verify_user_password<T: AsRef<str>>(&self, _: T, password: T, hash: T) -> Result<bool, Error> {
+                Ok(password.as_ref() == hash.as_ref())
+            }
+        }
+
+        /// The session `ProfileEnricher` leaves at login, whatever the token
+        struct Sessions(SqliteUserRepository);
+
+        #[async_trait(?Send)]
+        impl SessionResolver for Sessions {
+            async fn resolve_session(&self, user: &str, _: &str) -> Result<ServerClaims, Error> {
+                let (mut client, mut server) = (HashMap::new(), HashMap::new());
+                ProfileEnricher::new(self.0.clone()).enrich(user, &mut client, &mut server).await?;
+                Ok(ServerClaims::new(user.as_bytes().to_vec(), Some(client), Some(server), 0, None, None, None))
+            }
+        }
+
+        let users = SqliteUserRepository::new(SqliteDatabase::open(":memory:").unwrap());
+        let fixtures = Fixtures::from_json(include_str!("../fixtures/users.json")).unwrap();
+        let report = block_on(fixtures.seed_repository(&users, &PlainHasher));
+        assert!(report.failures.is_empty());
+        assert_eq!(report.seeded.len(), 2);
+        let report = block_on(fixtures.seed_repository(&users, &PlainHasher));
+        assert_eq!(report.unchanged.len(), 2);
+
+        let sessions = Sessions(users.clone());
+        for user in &["john_doe", "jane_doe"] {
+            let access = block_on(authorized::<CanExecute, _>(&sessions, user.to_string(), "token")).unwrap();
+            assert_eq!(access.profile().permissions, vec!["execute"]);
+            assert!(access.profile().display_name.is_some());
+        }
+
+        // Signed up through a webserver
+        block_on(users.signup_user("james_doe", "james")).unwrap();
+        assert!(block_on(authorized::<CanExecute, _>(&sessions, "james_doe".to_string(), "token")).is_err());
+        block_on(users.assign_role("james_doe", SIGNUP_ROLE)).unwrap();
+        assert!(block_on(authorized::<CanExecute, _>(&sessions, "james_doe".to_string(), "token")).is_ok());
+    }
+}
@@ -2,41 +2,42 @@ use std::collections::HashMap;
 use std::collections::hash_map::DefaultHasher;
 
 use jwtvault::prelude::*;
+use jwtvault_examples::config::AppConfig;
+use jwtvault_examples::database::fixtures::Fixtures;
 
 fn main() {
+    let config = AppConfig::load();
+    if let Err(e) = &config {
+        eprintln!("Unable to start Reason: {}", e.to_string());
+        return;
+    };
+    let config = config.ok().unwrap();
+
     let hasher = ArgonPasswordHasher::default();
-    // User: John Doe
-    let user_john = "john_doe";
-    let password_for_john = "john";
-    // Save value 'hashed_password_for_john' to persistent storage
-    // This is more relevant during user signup/password reset
-    let hashed_password_for_john = hasher.hash_user_password(user_john, password_for_john).unwrap();
 
-    // User: Jane Doe
-    let user_jane = "jane_doe";
-    let password_for_jane = "jane";
-    // Save 'hashed_password_for_jane' to persistent storage
+    // load users and their (argon hashed) password from the fixtures file
     // This is more relevant during user signup/password reset
-    let hashed_password_for_jane = hasher.hash_user_password(user_jane, password_for_jane).unwrap();
-
     let mut users = HashMap::new();
+    let fixtures = Fixtures::load(&config.fixtures).unwrap();
+    fixtures.seed_map(&mut users, &hasher).log();
 
-    // load users and their password from database/somewhere
-    users.insert(user_john.to_string(), hashed_password_for_john.to_string());
-    users.insert(user_jane.to_string(), hashed_password_for_jane.to_string());
+    // User: John Doe
+    let user_john = "john_doe";
+    let password_for_john = "john";
 
     // Setup app users
     let login = LoginInfo::new(users);
 
      // Initialize vault
-    let mut vault = DynamicVault::default(Box::new(login));
+    let mut vault = DynamicVault::new(config.keys.certificate_manager(), false, Box::new(login), Box::new(DefaultIdentity));
 
     // John needs to login now
+    let expiries = config.tokens.login_expiries();
     let token = block_on(vault.login(
         user_john,
         password_for_john,
-        None,
-        None,
+        expiries.0,
+        expiries.1,
     ));
     let token = token.ok().unwrap();
     // When John presents authentication token, it can be used to restore John's session info
@@ -65,7 +66,7 @@ fn main() {
     let new_token = block_on(vault.renew(
         user_john,
         token.refresh(),
-        None,
+        config.tokens.authentication_expiry,
     ));
     let new_token = new_token.ok().unwrap();
 
@@ -1,47 +1,41 @@
 use jwtvault::prelude::*;
+use jwtvault_examples::config::AppConfig;
+use jwtvault_examples::database::fixtures::Fixtures;
 use std::collections::HashMap;
 use std::collections::hash_map::DefaultHasher;
 
 
 fn main() {
+    let config = AppConfig::load();
+    if let Err(e) = &config {
+        eprintln!("Unable to start Reason: {}", e.to_string());
+        return;
+    };
+    let config = config.ok().unwrap();
 
-    let mut users = HashMap::new();
+    let loader = config.keys.certificate_manager();
+    let hasher = ArgonPasswordHasher::from(loader.password_hashing_secret());
 
-    let loader = CertificateManger::default();
+    // load users and their (argon hashed) password from the fixtures file
+    // This should ideally be pre-computed during user sign-up/password reset/change password
+    let mut users = HashMap::new();
+    let fixtures = Fixtures::load(&config.fixtures).unwrap();
+    fixtures.seed_map(&mut users, &hasher).log();
 
     // User: John Doe
     let user_john = "john_doe";
     let password_for_john = "john";
 
-    // This should ideally be pre-computed during user sign-up/password reset/change password
-    let hashed_password_for_john = hash_password_with_argon(
-        password_for_john,
-        loader.password_hashing_secret().as_str(),
-    ).unwrap();
-
-    // User: Jane Doe
-    let user_jane = "jane_doe";
-    let password_for_jane = "jane";
-
-    // This should ideally be pre-computed during user sign-up/password reset/change password
-    let hashed_password_for_jane = hash_password_with_argon(
-        password_for_jane,
-        loader.password_hashing_secret().as_str(),
-    ).unwrap();
-
-    // load users and their (argon hashed) password from database/somewhere
-    users.insert(user_john.to_string(), hashed_password_for_john);
-    users.insert(user_jane.to_string(), hashed_password_for_jane);
-
     // Initialize vault
     let mut vault = DefaultVault::new(loader, users, false);
 
     // John needs to login now
+    let expiries = config.tokens.login_expiries();
     let token = block_on(vault.login(
         user_john,
         password_for_john,
-        None,
-        None,
+        expiries.0,
+        expiries.1,
     ));
     let token = token.ok().unwrap();
     // When John presents authentication token, it can be used to restore John's session info
@@ -70,7 +64,7 @@ fn main() {
     let new_token = block_on(vault.renew(
         user_john,
         token.refresh(),
-        None,
+        config.tokens.authentication_expiry,
     ));
     let new_token = new_token.ok().unwrap();
 
@@ -1,9 +1,16 @@
-use std::sync::Mutex;
+use futures::lock::{Mutex, MutexGuard};
 use actix_web::{get, App, web, HttpServer, Responder};
 use actix_http::{Response, body::Body, error::ErrorBadRequest};
 use std::ops::DerefMut;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use jwtvault::prelude::*;
+use jwtvault_examples::config::{AppConfig, TokenConfig};
+use jwtvault_examples::database::fixtures::Fixtures;
+use jwtvault_examples::stores::file::FilePersistence;
+use jwtvault_examples::stores::sharded::ShardedPersistence;
+use jwtvault_examples::stores::sweeper::{Sweep, Sweeper};
+use jwtvault_examples::vault::{DynamicStoreVault, SessionStore};
 
 use std::collections::HashMap;
 
@@ -14,25 +21,42 @@ async fn index() -> impl Responder {
 }
 
 
+/// A `DynamicVault` can't be cloned per request, so there is one vault per worker instead, each over
+/// a clone of the same sharded session store; a request takes whichever vault is free
 struct ServerVault {
-    vault: Mutex<DynamicVault>
+    tokens: TokenConfig,
+    vaults: Vec<Mutex<DynamicStoreVault>>,
+    next: AtomicUsize,
+}
+
+impl ServerVault {
+    async fn vault(&self) -> MutexGuard<'_, DynamicStoreVault> {
+        for vault in self.vaults.iter() {
+            if let Some(vault) = vault.try_lock() {
+                return vault;
+            };
+        }
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.vaults.len();
+        self.vaults[index].lock().await
+    }
 }
 
 #[get("/login/{user}/{password}")]
 async fn login(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Login ===");
 
-    let mut manager = vault.vault.lock().unwrap();
+    let mut manager = vault.vault().await;
 
     let user = &info.0;
     let password = &info.1;
     println!("user = {} password = {}", user, password);
 
+    let expiries = vault.tokens.login_expiries();
     let token = manager.login(
         user.as_str(),
         password.as_str(),
-        None,
-        None,
+        expiries.0,
+        expiries.1,
     ).await;
     if token.is_err() {
         return Response::from_error(ErrorBadRequest(token.err().unwrap()));
@@ -54,7 +78,7 @@ async fn login(info: web::Path<(String, String)>, vault: web::Data<ServerVault>)
 #[get("/execute/{user}/{token}")]
 async fn execute(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Execute ===");
-    let mut engine = vault.vault.lock().unwrap();
+    let mut engine = vault.vault().await;
     let vault = engine.deref_mut();
 
     let user = &info.0;
@@ -100,11 +124,11 @@ async fn execute(info: web::Path<(String, String)>, vault: web::Data<ServerVault
 #[get("/renew/{user}/{token}")]
 async fn renew(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Renew ===");
-    let mut engine = vault.vault.lock().unwrap();
+    let mut engine = vault.vault().await;
     let user = &info.0;
     let client_refresh_token = &info.1;
 
-    let result = engine.renew(user.as_str(), &client_refresh_token, None).await;
+    let result = engine.renew(user.as_str(), &client_refresh_token, vault.tokens.authentication_expiry).await;
     if result.is_err() {
         let response = Response::Unauthorized()
             .header("content-type", "text/plain")
@@ -133,7 +157,7 @@ async fn renew(info: web::Path<(String, String)>, vault: web::Data<ServerVault>)
 #[get("/logout/{user}/{token}")]
 async fn logout(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Logout ===");
-    let mut engine = vault.vault.lock().unwrap();
+    let mut engine = vault.vault().await;
     let user = &info.0;
     let client_authentication_token = &info.1;
     let result = engine.logout(user.as_str(), client_authentication_token).await;
@@ -159,43 +183,62 @@ async fn logout(info: web::Path<(String, String)>, vault: web::Data<ServerVault>
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    let uri = "127.0.0.1:8080";
+    let config = AppConfig::load().map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+    })?;
+    let uri = config.server.address();
 
     let password_hasher = ArgonPasswordHasher::default();
 
-    let mut users = HashMap::new();
-
-    // User: John Doe
-    let user_john = "john_doe";
-    let password_for_john = "john";
-
+    // load users and their (argon hashed) password from the fixtures file
     // This should ideally be pre-computed during user sign-up/password reset/change password
-    let hashed_password_for_john = password_hasher.hash_user_password(
-        user_john,
-        password_for_john,
-    ).unwrap();
-
-    // User: Jane Doe
-    let user_jane = "jane_doe";
-    let password_for_jane = "jane";
-
-    // This should ideally be pre-computed during user sign-up/password reset/change password
-    let hashed_password_for_jane = password_hasher.hash_user_password(
-        user_jane,
-        password_for_jane,
-    ).unwrap();
-
-    // load users and their (argon hashed) password from database/somewhere
-    users.insert(user_john.to_string(), hashed_password_for_john);
-    users.insert(user_jane.to_string(), hashed_password_for_jane);
-
-    let info = LoginInfo::new(users);
+    let mut users = HashMap::new();
+    let fixtures = Fixtures::load(&config.fixtures).unwrap();
+    fixtures.seed_map(&mut users, &password_hasher).log();
+
+    // Sessions survive restarts when a store directory is configured; the log has a single writer,
+    // so then there is a single vault, otherwise one per worker over the shared shards
+    let sessions = &config.sessions;
+    let (stores, sharded): (Vec<Box<dyn SessionStore>>, _) = match &sessions.store_dir {
+        Some(dir) => {
+            let store = FilePersistence::open(dir, config.tokens.refresh_expiry, sessions.fsync, sessions.compact_after).map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+            })?;
+            println!("Sessions restored: {}", store.len());
+            (vec![Box::new(store) as Box<dyn SessionStore>], None)
+        }
+        None => {
+            let store = ShardedPersistence::new(config.tokens.refresh_expiry, sessions.shards);
+            let stores = (0..config.server.workers).map(|_| Box::new(store.clone()) as Box<dyn SessionStore>).collect();
+            (stores, Some(store))
+        }
+    };
 
-    // Initialize vault
-    let vault = DynamicVault::default(Box::new(info));
-    let vault = ServerVault { vault: Mutex::new(vault) };
+    // Initialize vaults
+    let vaults = stores.into_iter().map(|store| {
+        let info = LoginInfo::new(users.clone());
+        let vault = DynamicVault::new(config.keys.certificate_manager(), false, Box::new(info), Box::new(DefaultIdentity));
+        Mutex::new(DynamicStoreVault::new(vault, store))
+    }).collect();
+    let vault = ServerVault { tokens: config.tokens, vaults, next: AtomicUsize::new(0) };
     let vault = web::Data::new(vault);
 
+    // Expired sessions are dropped from memory in the background: the shards one at a time without
+    // waiting for any vault, the file store through the only vault
+    let sweeper = {
+        let vault = vault.clone();
+        let mut sharded = sharded;
+        Sweeper::spawn(config.sessions.sweep_interval(), move || {
+            let evicted = match sharded.as_mut() {
+                Some(store) => store.sweep(),
+                None => block_on(vault.vaults[0].lock()).sweep(),
+            };
+            if evicted > 0 {
+                println!("Sessions evicted: {}", evicted);
+            };
+            evicted
+        })
+    };
 
     let server = HttpServer::new(move || {
         App::new()
@@ -214,6 +257,9 @@ async fn main() -> std::io::Result<()> {
     println!("03 - Renew: http://{}/renew/<userid>/<refresh_token>", uri);
     println!("04 - Logout: http://{}/logout/<userid>/<authentication_token>", uri);
 
-    server.bind(uri)?.workers(1).run().await
+    let result = server.bind(uri)?.workers(config.server.workers).run().await;
+    println!("Session sweeps: {} Evicted: {}", sweeper.sweeps(), sweeper.evictions());
+    sweeper.stop();
+    result
 }
 
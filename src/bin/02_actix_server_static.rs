@@ -1,9 +1,12 @@
-use std::sync::Mutex;
 use actix_web::{get, App, web, HttpServer, Responder};
 use actix_http::{Response, body::Body, error::ErrorBadRequest};
-use std::ops::DerefMut;
 
 use jwtvault::prelude::*;
+use jwtvault_examples::config::{AppConfig, TokenConfig};
+use jwtvault_examples::database::fixtures::Fixtures;
+use jwtvault_examples::stores::sharded::ShardedPersistence;
+use jwtvault_examples::stores::sweeper::{Sweep, Sweeper};
+use jwtvault_examples::vault::{ComposableVault, SharedUsers, VaultBuilder};
 
 use std::collections::HashMap;
 
@@ -14,25 +17,31 @@ async fn index() -> impl Responder {
 }
 
 
+type AppVault = ComposableVault<SharedUsers, ShardedPersistence>;
+
+/// No lock around the vault: every request works on its own clone, which shares
+/// keys, users and the sharded session store with all the others
 struct ServerVault {
-    vault: Mutex<DefaultVault>
+    tokens: TokenConfig,
+    vault: AppVault
 }
 
 #[get("/login/{user}/{password}")]
 async fn login(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Login ===");
 
-    let mut manager = vault.vault.lock().unwrap();
+    let mut manager = vault.vault.clone();
 
     let user = &info.0;
     let password = &info.1;
     println!("user = {} password = {}", user, password);
 
+    let expiries = vault.tokens.login_expiries();
     let token = manager.login(
         user.as_str(),
         password.as_str(),
-        None,
-        None,
+        expiries.0,
+        expiries.1,
     ).await;
     if token.is_err() {
         return Response::from_error(ErrorBadRequest(token.err().unwrap()));
@@ -54,14 +63,13 @@ async fn login(info: web::Path<(String, String)>, vault: web::Data<ServerVault>)
 #[get("/execute/{user}/{token}")]
 async fn execute(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Execute ===");
-    let mut engine = vault.vault.lock().unwrap();
-    let vault = engine.deref_mut();
+    let mut engine = vault.vault.clone();
 
     let user = &info.0;
     let token = &info.1;
 
     let result = resolve_session_from_client_authentication_token(
-        vault,
+        &mut engine,
         user.as_str(), token.as_str(),
     ).await;
 
@@ -100,11 +108,11 @@ async fn execute(info: web::Path<(String, String)>, vault: web::Data<ServerVault
 #[get("/renew/{user}/{token}")]
 async fn renew(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Renew ===");
-    let mut engine = vault.vault.lock().unwrap();
+    let mut engine = vault.vault.clone();
     let user = &info.0;
     let client_refresh_token = &info.1;
 
-    let result = engine.renew(user.as_str(), &client_refresh_token, None).await;
+    let result = engine.renew(user.as_str(), &client_refresh_token, vault.tokens.authentication_expiry).await;
     if result.is_err() {
         let response = Response::Unauthorized()
             .header("content-type", "text/plain")
@@ -133,7 +141,7 @@ async fn renew(info: web::Path<(String, String)>, vault: web::Data<ServerVault>)
 #[get("/logout/{user}/{token}")]
 async fn logout(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Logout ===");
-    let mut engine = vault.vault.lock().unwrap();
+    let mut engine = vault.vault.clone();
     let user = &info.0;
     let client_authentication_token = &info.1;
     let result = engine.logout(user.as_str(), client_authentication_token).await;
@@ -159,42 +167,39 @@ async fn logout(info: web::Path<(String, String)>, vault: web::Data<ServerVault>
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    let uri = "127.0.0.1:8080";
-
-    let mut users = HashMap::new();
+    let config = AppConfig::load().map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+    })?;
+    let uri = config.server.address();
 
-    let loader = CertificateManger::default();
-
-    // User: John Doe
-    let user_john = "john_doe";
-    let password_for_john = "john";
+    let builder = VaultBuilder::new(config.keys.certificate_manager());
+    let hasher = builder.password_hasher();
 
+    // load users and their (argon hashed) password from the fixtures file
     // This should ideally be pre-computed during user sign-up/password reset/change password
-    let hashed_password_for_john = hash_password_with_argon(
-        password_for_john,
-        loader.password_hashing_secret().as_str(),
-    ).unwrap();
-
-    // User: Jane Doe
-    let user_jane = "jane_doe";
-    let password_for_jane = "jane";
-
-    // This should ideally be pre-computed during user sign-up/password reset/change password
-    let hashed_password_for_jane = hash_password_with_argon(
-        password_for_jane,
-        loader.password_hashing_secret().as_str(),
-    ).unwrap();
-
-    // load users and their (argon hashed) password from database/somewhere
-    users.insert(user_john.to_string(), hashed_password_for_john);
-    users.insert(user_jane.to_string(), hashed_password_for_jane);
+    let mut users = HashMap::new();
+    let fixtures = Fixtures::load(&config.fixtures).unwrap();
+    fixtures.seed_map(&mut users, &hasher).log();
 
 
-    // Initialize vault
-    let vault = DefaultVault::new(loader, users, false);
-    let vault = ServerVault { vault: Mutex::new(vault) };
+    // Initialize vault, sessions are sharded so workers only contend on the same shard
+    let store = ShardedPersistence::new(config.tokens.refresh_expiry, config.sessions.shards);
+    let vault = builder.build(SharedUsers::new(users, hasher), store.clone());
+    let vault = ServerVault { tokens: config.tokens, vault };
     let vault = web::Data::new(vault);
 
+    // Expired sessions are dropped from memory in the background, one shard at a time
+    let sweeper = {
+        let mut store = store;
+        Sweeper::spawn(config.sessions.sweep_interval(), move || {
+            let evicted = store.sweep();
+            if evicted > 0 {
+                println!("Sessions evicted: {}", evicted);
+            };
+            evicted
+        })
+    };
+
 
     let server = HttpServer::new(move || {
         App::new()
@@ -213,6 +218,9 @@ async fn main() -> std::io::Result<()> {
     println!("03 - Renew: http://{}/renew/<userid>/<refresh_token>", uri);
     println!("04 - Logout: http://{}/logout/<userid>/<authentication_token>", uri);
 
-    server.bind(uri)?.workers(1).run().await
+    let result = server.bind(uri)?.workers(config.server.workers).run().await;
+    println!("Session sweeps: {} Evicted: {}", sweeper.sweeps(), sweeper.evictions());
+    sweeper.stop();
+    result
 }
 
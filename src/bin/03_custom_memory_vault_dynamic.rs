@@ -0,0 +1,276 @@
+use jwtvault::prelude::*;
+use jwtvault_examples::config::AppConfig;
+use jwtvault_examples::database::fixtures::Fixtures;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+
+
+fn main() {
+    let config = AppConfig::load();
+    if let Err(e) = &config {
+        eprintln!("Unable to start Reason: {}", e.to_string());
+        return;
+    };
+    let config = config.ok().unwrap();
+
+    let loader = config.keys.certificate_manager();
+    let hasher = ArgonPasswordHasher::from(loader.password_hashing_secret());
+
+    // load users and their (argon hashed) password from the fixtures file
+    // This should ideally be pre-computed during user sign-up/password reset/change password
+    let mut users = HashMap::new();
+    let fixtures = Fixtures::load(&config.fixtures).unwrap();
+    fixtures.seed_map(&mut users, &hasher).log();
+
+    // User: John Doe
+    let user_john = "john_doe";
+    let password_for_john = "john";
+
+    // Initialize vault: both the user lookup and the session store are trait objects
+    let user_authentication = Box::new(MyUserAuthentication::new(users, hasher));
+    let store = Box::new(MetadataStore::default());
+    let mut vault = MyDynamicVault::new(loader, user_authentication, store);
+
+    // John needs to login now
+    let expiries = config.tokens.login_expiries();
+    let token = block_on(vault.login(
+        user_john,
+        password_for_john,
+        expiries.0,
+        expiries.1,
+    ));
+
+    let token = token.ok().unwrap();
+    // When John presents authentication token, it can be used to restore John's session info
+    let server_refresh_token = block_on(resolve_session_from_client_authentication_token(
+        &mut vault,
+        user_john,
+        token.authentication(),
+    ));
+    let server_refresh_token = server_refresh_token.ok().unwrap();
+
+    // server_refresh_token (variable) contains server method which captures client private info
+    // which never leaves the server
+    let private_info_about_john = server_refresh_token.server().unwrap();
+    let key = digest::<_, DefaultHasher>(user_john);
+    let data_on_server_side = private_info_about_john.get(&key).unwrap();
+
+    // server_refresh_token (variable) contains client method which captures client public info
+    // which is also send back to client
+    assert!(server_refresh_token.client().is_none());
+
+    // Check out the data on client and server which are public and private respectively
+    println!("[Private] John Info: {}",
+             String::from_utf8_lossy(data_on_server_side.as_slice()).to_string());
+
+    // lets renew authentication token
+    let new_token = block_on(vault.renew(
+        user_john,
+        token.refresh(),
+        config.tokens.authentication_expiry,
+    ));
+    let new_token = new_token.ok().unwrap();
+
+    // When John presents new authentication token it can be used to restore session info
+    let result = block_on(resolve_session_from_client_authentication_token(
+        &mut vault,
+        user_john,
+        new_token.as_str(),
+    ));
+    let _ = result.ok().unwrap();
+
+    // The custom store kept track of every entry it was handed
+    for (key, metadata) in vault.store.metadata() {
+        println!("[Store] Key: {} Metadata: {:?}", key, metadata);
+    }
+}
+
+
+/// Bookkeeping kept next to every stored value; timestamps are seconds since epoch
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub writes: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    value: String,
+    metadata: Metadata,
+}
+
+/// A `Persistence` that can also report on the entries it holds
+pub trait MetadataPersistence: Persistence + Send + Sync {
+    fn metadata(&self) -> Vec<(u64, Metadata)>;
+}
+
+/// In-memory session store that records when, and how often, each entry was written
+#[derive(Debug, Default)]
+pub struct MetadataStore {
+    entries: HashMap<u64, Entry>,
+}
+
+#[async_trait]
+impl Persistence for MetadataStore {
+    async fn store(&mut self, key: u64, value: String) {
+        let now = compute_timestamp_in_seconds();
+        let metadata = match self.entries.get(&key) {
+            Some(entry) => Metadata { updated_at: now, writes: entry.metadata.writes + 1, ..entry.metadata.clone() },
+            None => Metadata { created_at: now, updated_at: now, writes: 1 }
+        };
+        self.entries.insert(key, Entry { value, metadata });
+    }
+
+    async fn load(&self, key: u64) -> Option<&String> {
+        self.entries.get(&key).map(|entry| &entry.value)
+    }
+
+    async fn remove(&mut self, key: u64) -> Option<String> {
+        self.entries.remove(&key).map(|entry| entry.value)
+    }
+}
+
+impl MetadataPersistence for MetadataStore {
+    fn metadata(&self) -> Vec<(u64, Metadata)> {
+        self.entries.iter().map(|(key, entry)| (*key, entry.metadata.clone())).collect()
+    }
+}
+
+
+/// Looks users up in memory, the dynamic counterpart of `MyVault::check_user_valid`
+pub struct MyUserAuthentication {
+    users: HashMap<String, String>,
+    hasher: ArgonPasswordHasher,
+}
+
+impl MyUserAuthentication {
+    pub fn new(users: HashMap<String, String>, hasher: ArgonPasswordHasher) -> Self {
+        Self { users, hasher }
+    }
+}
+
+#[async_trait]
+impl UserAuthentication for MyUserAuthentication {
+    async fn check_user_valid(&mut self, user: &str, password: &str) -> Result<Option<Session>, Error> {
+        let password_from_disk = self.users.get(&user.to_string());
+        if password_from_disk.is_none() {
+            let msg = "Login Failed".to_string();
+            let reason = "Invalid userid/password".to_string();
+            return Err(LoginFailed::InvalidPassword(msg, reason).into());
+        };
+
+        let password_from_disk = password_from_disk.unwrap();
+        let result = self.hasher.verify_user_password(user, password, password_from_disk.as_str())?;
+        if !result {
+            let msg = "Login Failed".to_string();
+            let reason = "Invalid userid/password".to_string();
+            return Err(LoginFailed::InvalidPassword(msg, reason).into());
+        };
+        let reference = digest::<_, DefaultHasher>(user.as_bytes());
+        let mut server = HashMap::new();
+        server.insert(reference, user.clone().as_bytes().to_vec());
+        let session = Session::new(None, Some(server));
+        Ok(Some(session))
+    }
+}
+
+
+/// `DynamicVault` keeps sessions in a private `HashMap`, so the store is swapped in here
+/// while keys, password hashing and the user checks stay with the `DynamicVault`
+pub struct MyDynamicVault {
+    vault: DynamicVault,
+    store: Box<dyn MetadataPersistence>,
+}
+
+impl MyDynamicVault {
+    pub fn new<T: Keys>(loader: T, user_authentication: Box<dyn UserAuthentication + Send + Sync>, store: Box<dyn MetadataPersistence>) -> Self {
+        let vault = DynamicVault::new(loader, false, user_authentication, Box::new(DefaultIdentity));
+        Self { vault, store }
+    }
+}
+
+impl PersistenceHasher<DefaultHasher> for MyDynamicVault {}
+
+impl TrustToken for MyDynamicVault {
+    fn trust_token_bearer(&self) -> bool {
+        self.vault.trust_token_bearer()
+    }
+}
+
+impl PasswordHasher<ArgonPasswordHasher> for MyDynamicVault {
+    fn hash_user_password<T: AsRef<str>>(&self, user: T, password: T) -> Result<String, Error> {
+        self.vault.hash_user_password(user, password)
+    }
+
+    fn verify_user_password<T: AsRef<str>>(&self, user: T, password: T, hash: T) -> Result<bool, Error> {
+        self.vault.verify_user_password(user, password, hash)
+    }
+}
+
+impl Store for MyDynamicVault {
+    fn public_authentication_certificate(&self) -> &PublicKey {
+        self.vault.public_authentication_certificate()
+    }
+
+    fn private_authentication_certificate(&self) -> &PrivateKey {
+        self.vault.private_authentication_certificate()
+    }
+
+    fn public_refresh_certificate(&self) -> &PublicKey {
+        self.vault.public_refresh_certificate()
+    }
+
+    fn private_refresh_certificate(&self) -> &PrivateKey {
+        self.vault.private_refresh_certificate()
+    }
+}
+
+#[async_trait]
+impl Persistence for MyDynamicVault {
+    async fn store(&mut self, key: u64, value: String) {
+        self.store.store(key, value).await
+    }
+
+    async fn load(&self, key: u64) -> Option<&String> {
+        self.store.load(key).await
+    }
+
+    async fn remove(&mut self, key: u64) -> Option<String> {
+        self.store.remove(key).await
+    }
+}
+
+#[async_trait]
+impl UserIdentity for MyDynamicVault {
+    async fn check_same_user(&self, user: &str, user_from_token: &str) -> Result<(), Error> {
+        self.vault.check_same_user(user, user_from_token).await
+    }
+}
+
+#[async_trait]
+impl UserAuthentication for MyDynamicVault {
+    async fn check_user_valid(&mut self, user: &str, password: &str) -> Result<Option<Session>, Error> {
+        self.vault.check_user_valid(user, password).await
+    }
+}
+
+
+#[async_trait]
+impl Workflow<DefaultHasher, ArgonPasswordHasher> for MyDynamicVault {
+    async fn login(&mut self, user: &str, pass: &str, authentication_token_expiry_in_seconds: Option<i64>, refresh_token_expiry_in_seconds: Option<i64>) -> Result<Token, Error> {
+        continue_login(self, user, pass, authentication_token_expiry_in_seconds, refresh_token_expiry_in_seconds).await
+    }
+
+    async fn renew(&mut self, user: &str, client_refresh_token: &String, authentication_token_expiry_in_seconds: Option<i64>) -> Result<String, Error> {
+        continue_renew(self, user, client_refresh_token, authentication_token_expiry_in_seconds).await
+    }
+
+    async fn logout(&mut self, user: &str, client_authentication_token: &String) -> Result<(), Error> {
+        continue_logout(self, user, client_authentication_token).await
+    }
+
+    async fn revoke(&mut self, client_refresh_token: &String) -> Result<(), Error> {
+        continue_revoke(self, client_refresh_token).await
+    }
+}
@@ -1,47 +1,45 @@
 use jwtvault::prelude::*;
+use jwtvault_examples::config::AppConfig;
+use jwtvault_examples::database::fixtures::Fixtures;
+use jwtvault_examples::stores::memory::MemoryPersistence;
+use jwtvault_examples::stores::sweeper::Sweep;
 use std::collections::HashMap;
 use std::collections::hash_map::DefaultHasher;
 use jwtvault::errors::LoginFailed::PasswordHashingFailed;
 
 
 fn main() {
-    let mut users = HashMap::new();
+    let config = AppConfig::load();
+    if let Err(e) = &config {
+        eprintln!("Unable to start Reason: {}", e.to_string());
+        return;
+    };
+    let config = config.ok().unwrap();
+
+    let loader = config.keys.certificate_manager();
+    let hasher = ArgonPasswordHasher::from(loader.password_hashing_secret());
 
-    let loader = CertificateManger::default();
+    // load users and their (argon hashed) password from the fixtures file
+    // This should ideally be pre-computed during user sign-up/password reset/change password
+    let mut users = HashMap::new();
+    let fixtures = Fixtures::load(&config.fixtures).unwrap();
+    fixtures.seed_map(&mut users, &hasher).log();
 
     // User: John Doe
     let user_john = "john_doe";
     let password_for_john = "john";
 
-    // This should ideally be pre-computed during user sign-up/password reset/change password
-    let hashed_password_for_john = hash_password_with_argon(
-        password_for_john,
-        loader.password_hashing_secret().as_str(),
-    ).unwrap();
-
-    // User: Jane Doe
-    let user_jane = "jane_doe";
-    let password_for_jane = "jane";
-
-    // This should ideally be pre-computed during user sign-up/password reset/change password
-    let hashed_password_for_jane = hash_password_with_argon(
-        password_for_jane,
-        loader.password_hashing_secret().as_str(),
-    ).unwrap();
-
-    // load users and their (argon hashed) password from database/somewhere
-    users.insert(user_john.to_string(), hashed_password_for_john.to_string());
-    users.insert(user_jane.to_string(), hashed_password_for_jane.to_string());
-
-    // Initialize vault
-    let mut vault = MyVault::new(loader, users);
+    // Initialize vault, sessions are forgotten once the refresh token expires
+    let store = MemoryPersistence::new(config.tokens.refresh_expiry);
+    let mut vault = MyVault::new(loader, users, store);
 
     // John needs to login now
+    let expiries = config.tokens.login_expiries();
     let token = block_on(vault.login(
         user_john,
         password_for_john,
-        None,
-        None,
+        expiries.0,
+        expiries.1,
     ));
 
     let token = token.ok().unwrap();
@@ -71,7 +69,7 @@ fn main() {
     let new_token = block_on(vault.renew(
         user_john,
         token.refresh(),
-        None,
+        config.tokens.authentication_expiry,
     ));
     let new_token = new_token.ok().unwrap();
 
@@ -82,6 +80,10 @@ fn main() {
         new_token.as_str(),
     ));
     let _ = result.ok().unwrap();
+
+    // Long running servers sweep on a timer instead, see `stores::sweeper::Sweeper`
+    let evicted = vault.store.sweep();
+    println!("[Store] Sessions: {} Evicted: {}", vault.store.len(), evicted);
 }
 
 
@@ -92,7 +94,7 @@ pub struct MyVault {
     public_refresh_certificate: PublicKey,
     private_refresh_certificate: PrivateKey,
     password_hashing_secret: PrivateKey,
-    store: HashMap<u64, String>,
+    store: MemoryPersistence,
     users: HashMap<String, String>,
 
 }
@@ -142,13 +144,12 @@ impl Store for MyVault {
 }
 
 impl MyVault {
-    pub fn new<T: Keys>(loader: T, users: HashMap<String, String>) -> Self {
+    pub fn new<T: Keys>(loader: T, users: HashMap<String, String>, store: MemoryPersistence) -> Self {
         let public_authentication_certificate = loader.public_authentication_certificate().clone();
         let private_authentication_certificate = loader.private_authentication_certificate().clone();
         let public_refresh_certificate = loader.public_refresh_certificate().clone();
         let private_refresh_certificate = loader.private_refresh_certificate().clone();
         let password_hashing_secret = loader.password_hashing_secret();
-        let store = HashMap::new();
 
         Self {
             public_authentication_certificate,
@@ -166,15 +167,15 @@ impl MyVault {
 #[async_trait]
 impl Persistence for MyVault {
     async fn store(&mut self, key: u64, value: String) {
-        self.store.insert(key, value);
+        self.store.store(key, value).await
     }
 
     async fn load(&self, key: u64) -> Option<&String> {
-        self.store.get(&key)
+        self.store.load(key).await
     }
 
     async fn remove(&mut self, key: u64) -> Option<String> {
-        self.store.remove(&key)
+        self.store.remove(key).await
     }
 }
 
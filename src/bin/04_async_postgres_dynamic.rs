@@ -1,51 +1,59 @@
-use postgres::NoTls;
-use r2d2::Pool;
-use r2d2_postgres::PostgresConnectionManager;
-
 use jwtvault::prelude::*;
 
-use jwtvault_examples::database::setup::connection;
-use jwtvault_examples::database::users_setup::{signup_app_users, resolve_password_for_user};
+use jwtvault_examples::database::setup::async_replicated_connection_with;
+use jwtvault_examples::database::migrations::migrate;
+use jwtvault_examples::database::password::CurrentParams;
+use jwtvault_examples::database::users_setup::{authenticate_user, PostgresUserRepository};
+use jwtvault_examples::config::{AppConfig, DatabaseConfig};
+use jwtvault_examples::database::fixtures::Fixtures;
 
 struct PostgresDynamicVault {
-    pool: Pool<PostgresConnectionManager<NoTls>>,
+    users: PostgresUserRepository,
     hasher: ArgonPasswordHasher,
+    params: CurrentParams,
 }
 
-impl Default for PostgresDynamicVault {
-    fn default() -> Self {
-        let pool = connection().ok().unwrap();
+impl PostgresDynamicVault {
+    async fn connect(config: &DatabaseConfig) -> Result<Self, Error> {
+        let pool = async_replicated_connection_with(config).await?;
+        for version in migrate(pool.primary()).await? {
+            println!("DB Migration applied: V{}", version);
+        };
+        let users = PostgresUserRepository::new(pool);
         let hasher = ArgonPasswordHasher::default();
-        PostgresDynamicVault { pool, hasher }
+        Ok(PostgresDynamicVault { users, hasher, params: CurrentParams::default() })
     }
 }
 
 #[async_trait]
 impl UserAuthentication for PostgresDynamicVault {
     async fn check_user_valid(&mut self, user: &str, password: &str) -> Result<Option<Session>, Error> {
-        let password_from_disk = resolve_password_for_user(self.pool.clone(), user).await?;
-
-        if password_from_disk.is_none() {
-            let msg = "Login Failed".to_string();
-            let reason = "Invalid userid/password".to_string();
-            return Err(LoginFailed::InvalidPassword(msg, reason).into());
-        };
-        let password_from_disk = password_from_disk.unwrap();
-        let hash = password_from_disk.as_str();
-        let result = self.hasher.verify_user_password(user, password, hash)?;
-        if !result {
-            let msg = "Login Failed".to_string();
-            let reason = "Invalid userid/password".to_string();
-            return Err(LoginFailed::InvalidPassword(msg, reason).into());
-        };
+        let _ = authenticate_user(&self.users, &self.hasher, &self.params, user, password).await?;
         let session: Option<Session> = None;
         Ok(session)
     }
 }
 
-fn main() {
-    dotenv::dotenv().ok();
-    block_on(signup_app_users());
+#[actix_rt::main]
+async fn main() {
+    let config = AppConfig::load();
+    if let Err(e) = &config {
+        eprintln!("Unable to start Reason: {}", e.to_string());
+        return;
+    };
+    let config = config.ok().unwrap();
+    let user_authentication = PostgresDynamicVault::connect(&config.database).await;
+    if let Err(e) = &user_authentication {
+        eprintln!("Unable to start Reason: {}", e.to_string());
+        return;
+    };
+    let user_authentication = Box::new(user_authentication.ok().unwrap());
+
+    // This should be done during user signup
+    match Fixtures::load(&config.fixtures) {
+        Ok(fixtures) => fixtures.seed_repository(&user_authentication.users, &user_authentication.hasher).await.log(),
+        Err(e) => eprintln!("{}", e.to_string()),
+    };
 
     // User: John Doe
     let user_john = "john_doe";
@@ -56,25 +64,24 @@ fn main() {
     let user_jane = "jane_doe";
     let password_for_jane = "jane";
 
-    let user_authentication = Box::new(PostgresDynamicVault::default());
-
-    let mut vault = DynamicVault::default(user_authentication);
+    let mut vault = DynamicVault::new(config.keys.certificate_manager(), false, user_authentication, Box::new(DefaultIdentity));
+    let expiries = config.tokens.login_expiries();
 
-    let result = block_on(vault.login(
+    let result = vault.login(
         user_john,
         password_for_john,
-        None,
-        None,
-    ));
+        expiries.0,
+        expiries.1,
+    ).await;
 
     assert!(result.is_ok());
 
-    let result = block_on(vault.login(
+    let result = vault.login(
         user_jane,
         password_for_jane,
-        None,
-        None,
-    ));
+        expiries.0,
+        expiries.1,
+    ).await;
 
     assert!(result.is_ok());
 }
\ No newline at end of file
@@ -1,78 +1,114 @@
 use std::ops::DerefMut;
 
-use postgres::NoTls;
-use r2d2::Pool;
-use r2d2_postgres::PostgresConnectionManager;
-
 use actix_web::{get, App, web, HttpServer, Responder};
-use actix_http::{Response, body::Body, error::ErrorBadRequest};
+use actix_http::{Response, body::Body};
 
 use jwtvault::prelude::*;
 
-use jwtvault_examples::database::setup::connection;
-use jwtvault_examples::database::users_setup::{resolve_password_for_user, signup_user};
-use std::sync::Mutex;
+use jwtvault_examples::config::{AppConfig, TokenConfig};
+use jwtvault_examples::access::{Authorized, Requirement, SessionResolver};
+use jwtvault_examples::database::setup::async_replicated_connection_with;
+use jwtvault_examples::database::migrations::migrate;
+use jwtvault_examples::database::password::CurrentParams;
+use jwtvault_examples::database::users_setup::{authenticate_user, UserRepository, PostgresUserRepository};
+use jwtvault_examples::database::roles::{RoleRepository, SIGNUP_ROLE};
+use jwtvault_examples::errors::error_response;
+use jwtvault_examples::database::profiles::{ProfileEnricher, SessionEnricher, UserProfile};
+use jwtvault_examples::stores::memory::MemoryPersistence;
+use jwtvault_examples::stores::redis::RedisPersistence;
+use jwtvault_examples::stores::sweeper::{Sweep, Sweeper};
+use jwtvault_examples::vault::{DynamicStoreVault, SessionStore};
+use futures::lock::Mutex;
+use std::collections::HashMap;
 use std::collections::hash_map::DefaultHasher;
 
 struct WebDynamicVault {
-    pool: Pool<PostgresConnectionManager<NoTls>>,
+    users: PostgresUserRepository,
     hasher: ArgonPasswordHasher,
+    params: CurrentParams,
+    enricher: ProfileEnricher<PostgresUserRepository>,
 }
 
 
-impl Default for WebDynamicVault {
-    fn default() -> Self {
-        let pool = connection().ok().unwrap();
+impl WebDynamicVault {
+    fn new(users: PostgresUserRepository) -> Self {
         let hasher = ArgonPasswordHasher::default();
-        WebDynamicVault { pool, hasher }
+        let enricher = ProfileEnricher::new(users.clone());
+        WebDynamicVault { users, hasher, params: CurrentParams::default(), enricher }
     }
 }
 
 #[async_trait]
 impl UserAuthentication for WebDynamicVault {
     async fn check_user_valid(&mut self, user: &str, password: &str) -> Result<Option<Session>, Error> {
-        let password_from_disk = resolve_password_for_user(self.pool.clone(), user).await?;
-
-        if password_from_disk.is_none() {
-            let msg = "Login Failed".to_string();
-            let reason = "Invalid userid/password".to_string();
-            return Err(LoginFailed::InvalidPassword(msg, reason).into());
-        };
-        let password_from_disk = password_from_disk.unwrap();
-        let hash = password_from_disk.as_str();
-        let result = self.hasher.verify_user_password(user, password, hash)?;
-        if !result {
-            let msg = "Login Failed".to_string();
-            let reason = "Invalid userid/password".to_string();
-            return Err(LoginFailed::InvalidPassword(msg, reason).into());
-        };
-        let session: Option<Session> = None;
-        Ok(session)
+        let _ = authenticate_user(&self.users, &self.hasher, &self.params, user, password).await?;
+        let (mut client, mut server) = (HashMap::new(), HashMap::new());
+        self.enricher.enrich(user, &mut client, &mut server).await?;
+        let client = if client.is_empty() { None } else { Some(client) };
+        let session = Session::new(client, Some(server));
+        Ok(Some(session))
     }
 }
 
 struct ServerVault {
-    vault: Mutex<DynamicVault>,
-    pool: Pool<PostgresConnectionManager<NoTls>>,
+    tokens: TokenConfig,
+    /// Held across database calls, so it must be an async lock: a blocked worker thread could never
+    /// resume the request holding it
+    vault: Mutex<DynamicStoreVault>,
+    users: PostgresUserRepository,
     hasher: ArgonPasswordHasher,
+    params: CurrentParams,
 }
 
-impl Default for ServerVault {
-    fn default() -> Self {
-        let vault = Mutex::new(
-            DynamicVault::default(Box::new(WebDynamicVault::default()))
+impl ServerVault {
+    async fn connect(config: &AppConfig) -> Result<Self, Error> {
+        let pool = async_replicated_connection_with(&config.database).await?;
+        for version in migrate(pool.primary()).await? {
+            println!("DB Migration applied: V{}", version);
+        };
+        let users = PostgresUserRepository::new(pool);
+        // Sessions on a redis server are shared by every instance of the webserver
+        let store: Box<dyn SessionStore> = match &config.sessions.redis {
+            Some(redis) => {
+                let store = RedisPersistence::new(redis.clone(), config.database.pool.clone(), config.tokens.refresh_expiry);
+                store.ping().await?;
+                println!("Sessions on redis: {}", redis.address);
+                Box::new(store)
+            }
+            None => Box::new(MemoryPersistence::new(config.tokens.refresh_expiry)),
+        };
+        let vault = DynamicVault::new(
+            config.keys.certificate_manager(),
+            false,
+            Box::new(WebDynamicVault::new(users.clone())),
+            Box::new(DefaultIdentity),
         );
-        let pool = connection().ok().unwrap();
+        let vault = Mutex::new(DynamicStoreVault::new(vault, store));
         let hasher = ArgonPasswordHasher::default();
-        Self {
+        Ok(Self {
+            tokens: config.tokens.clone(),
             vault,
-            pool,
+            users,
             hasher,
+        })
+    }
+}
 
-        }
+
+#[async_trait(?Send)]
+impl SessionResolver for ServerVault {
+    async fn resolve_session(&self, user: &str, token: &str) -> Result<ServerClaims, Error> {
+        let mut engine = self.vault.lock().await;
+        resolve_session_from_client_authentication_token(engine.deref_mut(), user, token).await
     }
 }
 
+/// Granted through `tbl_role_permissions`, see `database::roles`
+struct CanExecute;
+
+impl Requirement for CanExecute {
+    const PERMISSIONS: &'static [&'static str] = &["execute"];
+}
 
 impl ServerVault {
     async fn signup_app_user(&self, user: &str, password: &str) -> Result<String, Error> {
@@ -81,7 +117,8 @@ impl ServerVault {
             user,
             password,
         )?;
-        let _ = signup_user::<&str>(self.pool.clone(), &user_id, &password).await?;
+        let _ = self.users.signup_user(user_id.as_str(), password.as_str()).await?;
+        self.users.assign_role(user_id.as_str(), SIGNUP_ROLE).await?;
         Ok(user_id)
     }
 }
@@ -100,7 +137,7 @@ async fn signup(info: web::Path<(String, String)>, vault: web::Data<ServerVault>
 
     let result = vault.signup_app_user(user, password).await;
     if result.is_err() {
-        return Response::from_error(ErrorBadRequest(result.err().unwrap()));
+        return error_response(&result.err().unwrap());
     };
     let result = result.ok().unwrap();
 
@@ -120,7 +157,8 @@ async fn signup(info: web::Path<(String, String)>, vault: web::Data<ServerVault>
 async fn login(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Login ===");
 
-    let mut manager = vault.vault.lock().unwrap();
+    let expiries = vault.tokens.login_expiries();
+    let mut manager = vault.vault.lock().await;
     let vault = manager.deref_mut();
 
     let user = &info.0;
@@ -130,11 +168,11 @@ async fn login(info: web::Path<(String, String)>, vault: web::Data<ServerVault>)
     let token = vault.login(
         user.as_str(),
         password.as_str(),
-        None,
-        None,
+        expiries.0,
+        expiries.1,
     ).await;
     if token.is_err() {
-        return Response::from_error(ErrorBadRequest(token.err().unwrap()));
+        return error_response(&token.err().unwrap());
     };
     let token = token.ok().unwrap();
     let token = serde_json::to_string(&token).unwrap();
@@ -152,47 +190,26 @@ async fn login(info: web::Path<(String, String)>, vault: web::Data<ServerVault>)
 
 
 #[get("/execute/{user}/{token}")]
-async fn execute(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
+async fn execute(access: Authorized<CanExecute, ServerVault>) -> Response {
     println!("=== Execute ===");
-    let mut engine = vault.vault.lock().unwrap();
-    let vault = engine.deref_mut();
-
-    let user = &info.0;
-    let token = &info.1;
-
-    let result = resolve_session_from_client_authentication_token(
-        vault,
-        user.as_str(), token.as_str(),
-    ).await;
-
-    if result.is_err() {
-        let response = Response::Unauthorized()
-            .header("content-type", "text/plain")
-            .finish();
-        return response;
-    };
-    let result = result.ok();
-
-    if result.is_none() {
-        let response = Response::NotAcceptable()
-            .header("content-type", "text/plain")
-            .finish();
-        return response;
-    };
-    let session = result.unwrap();
+    let user = access.user();
+    let session = access.session();
 
     let client = session.client();
     let server = session.server();
 
     println!("Session for User: {} - Client: {:#?} Server: {:#?}", user, client, server);
 
+    let public = UserProfile::from_session(client, None);
+    let profile = access.profile();
+    println!("Profile for User: {} - Public: {:?} Private: {:?}", user, public, profile);
+
     // Prepare json for dispatch
-    let body = Body::from(
-        format!("{}", "Executed")
-    );
+    let profile = serde_json::to_string(profile).unwrap();
+    let body = Body::from(profile);
 
     let response = Response::Ok()
-        .header("Content-Type", "text/plain")
+        .header("Content-Type", "application/json")
         .finish();
     response.set_body(body)
 }
@@ -201,16 +218,13 @@ async fn execute(info: web::Path<(String, String)>, vault: web::Data<ServerVault
 #[get("/renew/{user}/{token}")]
 async fn renew(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Renew ===");
-    let mut engine = vault.vault.lock().unwrap();
+    let mut engine = vault.vault.lock().await;
     let user = &info.0;
     let client_refresh_token = &info.1;
 
-    let result = engine.renew(user.as_str(), &client_refresh_token, None).await;
-    if result.is_err() {
-        let response = Response::Unauthorized()
-            .header("content-type", "text/plain")
-            .finish();
-        return response;
+    let result = engine.renew(user.as_str(), &client_refresh_token, vault.tokens.authentication_expiry).await;
+    if let Err(e) = &result {
+        return error_response(e);
     };
     let client_authentication_token = result.ok().unwrap();
 
@@ -233,15 +247,12 @@ async fn renew(info: web::Path<(String, String)>, vault: web::Data<ServerVault>)
 #[get("/logout/{user}/{token}")]
 async fn logout(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Logout ===");
-    let mut engine = vault.vault.lock().unwrap();
+    let mut engine = vault.vault.lock().await;
     let user = &info.0;
     let client_authentication_token = &info.1;
     let result = engine.logout(user.as_str(), client_authentication_token).await;
-    if result.is_err() {
-        let response = Response::Unauthorized()
-            .header("content-type", "text/plain")
-            .finish();
-        return response;
+    if let Err(e) = &result {
+        return error_response(e);
     };
     println!("Logout: {}", user);
 
@@ -258,13 +269,47 @@ async fn logout(info: web::Path<(String, String)>, vault: web::Data<ServerVault>
 }
 
 
+#[get("/status")]
+async fn status(vault: web::Data<ServerVault>) -> Response {
+    println!("=== Status ===");
+    let status = vault.users.pool().status();
+    let status = serde_json::to_string(&status).unwrap();
+
+    let body = Body::from(
+        status
+    );
+
+    let response = Response::Ok()
+        .header("Content-Type", "application/json")
+        .finish();
+
+    response.set_body(body)
+}
+
+
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    dotenv::dotenv().ok();
-    let uri = "127.0.0.1:8080";
-    let vault = ServerVault::default();
+    let config = AppConfig::load().map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+    })?;
+    let uri = config.server.address();
+    let vault = ServerVault::connect(&config).await.map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+    })?;
     let vault = web::Data::new(vault);
 
+    // Expired sessions are dropped from memory in the background (redis expires them itself)
+    let sweeper = {
+        let vault = vault.clone();
+        Sweeper::spawn(config.sessions.sweep_interval(), move || {
+            let evicted = block_on(vault.vault.lock()).sweep();
+            if evicted > 0 {
+                println!("Sessions evicted: {}", evicted);
+            };
+            evicted
+        })
+    };
+
     let server = HttpServer::new(move || {
         App::new()
             .app_data(vault.clone())
@@ -274,6 +319,7 @@ async fn main() -> std::io::Result<()> {
             .service(execute)
             .service(renew)
             .service(logout)
+            .service(status)
     });
 
     println!("[Web server - Dynamic] Running Server: {}", uri);
@@ -284,6 +330,10 @@ async fn main() -> std::io::Result<()> {
     println!("03 - Execute: http://{}/execute/<userid>/<authentication_token>", uri);
     println!("04 - Renew: http://{}/renew/<userid>/<refresh_token>", uri);
     println!("05 - Logout: http://{}/logout/<userid>/<authentication_token>", uri);
+    println!("06 - Pool status: http://{}/status", uri);
 
-    server.bind(uri)?.workers(1).run().await
+    let result = server.bind(uri)?.workers(config.server.workers).run().await;
+    println!("Session sweeps: {} Evicted: {}", sweeper.sweeps(), sweeper.evictions());
+    sweeper.stop();
+    result
 }
\ No newline at end of file
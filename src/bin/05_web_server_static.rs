@@ -1,212 +1,161 @@
-use std::sync::Mutex;
+use futures::lock::Mutex;
 use std::ops::DerefMut;
-use std::collections::HashMap;
 use std::collections::hash_map::DefaultHasher;
 
 use actix_web::{get, App, web, HttpServer, Responder};
-use actix_http::{Response, body::Body, error::ErrorBadRequest};
-
-use postgres::NoTls;
-use r2d2::Pool;
-use r2d2_postgres::PostgresConnectionManager;
+use actix_http::{Response, body::Body};
 
 use jwtvault::prelude::*;
-use jwtvault_examples::database::setup::connection;
-use jwtvault::errors::LoginFailed::PasswordHashingFailed;
-
-
-#[derive(Debug, Clone)]
-pub struct WebVault {
-    public_authentication_certificate: PublicKey,
-    private_authentication_certificate: PrivateKey,
-    public_refresh_certificate: PublicKey,
-    private_refresh_certificate: PrivateKey,
-    password_hashing_secret: PrivateKey,
-    store: HashMap<u64, String>,
-    pool: Pool<PostgresConnectionManager<NoTls>>,
+use jwtvault_examples::config::{AppConfig, TokenConfig};
+use jwtvault_examples::access::{Authorized, Requirement, SessionResolver};
+#[cfg(not(feature = "sqlite"))]
+use jwtvault_examples::database::setup::async_replicated_connection_with;
+#[cfg(not(feature = "sqlite"))]
+use jwtvault_examples::database::migrations::migrate;
+use jwtvault_examples::database::users_setup::{RepositoryAuthentication, UserRepository};
+use jwtvault_examples::database::roles::{RoleRepository, SIGNUP_ROLE};
+use jwtvault_examples::vault::{ComposableVault, VaultBuilder, SessionStore};
+use jwtvault_examples::stores::redis::RedisPersistence;
+use jwtvault_examples::stores::sweeper::{Sweep, Sweeper};
+#[cfg(not(feature = "sqlite"))]
+use jwtvault_examples::database::users_setup::PostgresUserRepository;
+use jwtvault_examples::errors::error_response;
+use jwtvault_examples::database::errors::DatabaseErrors;
+#[cfg(not(feature = "sqlite"))]
+use jwtvault_examples::database::persistence::PostgresPersistence;
+use jwtvault_examples::database::profiles::{ProfileEnricher, UserProfile};
+use jwtvault_examples::database::db_pool::PoolStatus;
+#[cfg(feature = "sqlite")]
+use jwtvault_examples::database::sqlite::{SqliteDatabase, SqliteUserRepository, SqlitePersistence};
+
+/// Users are looked up in the database instead of in-memory, everything else is the shared vault.
+/// Token owners are sealed (see `VaultBuilder::seal_token_owner`), so a token only works for the user it was issued to.
+/// Every login is a session of its own, capped per user by `SESSION_MAX_PER_USER` (see `VaultBuilder::track_sessions`)
+pub type WebVault<U, P> = ComposableVault<RepositoryAuthentication<U>, P>;
+
+#[cfg(not(feature = "sqlite"))]
+type AppRepository = PostgresUserRepository;
+
+#[cfg(feature = "sqlite")]
+type AppRepository = SqliteUserRepository;
+
+/// Sessions are kept in the database unless `SESSION_REDIS_URL` is set
+type AppVault = WebVault<AppRepository, Box<dyn SessionStore>>;
+
+/// The store for the vault and a handle on the same sessions for the sweeper, which then never waits for the vault
+fn with_handle<S: SessionStore + Clone + 'static>(store: S) -> (Box<dyn SessionStore>, Box<dyn SessionStore>) {
+    (Box::new(store.clone()), Box::new(store))
 }
 
-impl PersistenceHasher<DefaultHasher> for WebVault {}
-
-impl TrustToken for WebVault {
-    fn trust_token_bearer(&self) -> bool {
-        false
-    }
-}
-
-impl PasswordHasher<ArgonHasher<'static>> for WebVault {
-    fn hash_user_password<T: AsRef<str>>(&self, user: T, password: T) -> Result<String, Error> {
-        let secret_key = self.password_hashing_secret.as_str();
-        hash_password_with_argon::<&str>(password.as_ref(), secret_key).map_err(|e| {
-            PasswordHashingFailed(user.as_ref().to_string(), e.to_string()).into()
-        })
-    }
-
-    fn verify_user_password<T: AsRef<str>>(&self, user: T, password: T, hash: T) -> Result<bool, Error> {
-        let secret_key = self.password_hashing_secret.as_str();
-        verify_user_password_with_argon::<&str>(password.as_ref(), secret_key, hash.as_ref()).map_err(|e| {
-            PasswordHashingFailed(user.as_ref().to_string(), e.to_string()).into()
-        })
-    }
+/// Sessions on a redis server are shared by every instance of the webserver, and expire there
+async fn redis_store(config: &AppConfig) -> Result<Option<RedisPersistence>, Error> {
+    let redis = match &config.sessions.redis {
+        Some(redis) => redis,
+        None => return Ok(None),
+    };
+    let store = RedisPersistence::new(redis.clone(), config.database.pool.clone(), config.tokens.refresh_expiry);
+    store.ping().await?;
+    println!("Sessions on redis: {}", redis.address);
+    Ok(Some(store))
 }
 
-
-impl Store for WebVault {
-    fn public_authentication_certificate(&self) -> &PublicKey {
-        &self.public_authentication_certificate
-    }
-
-    fn private_authentication_certificate(&self) -> &PrivateKey {
-        &self.private_authentication_certificate
-    }
-
-    fn public_refresh_certificate(&self) -> &PublicKey {
-        &self.public_refresh_certificate
-    }
-
-    fn private_refresh_certificate(&self) -> &PrivateKey {
-        &self.private_refresh_certificate
-    }
+#[cfg(not(feature = "sqlite"))]
+async fn connect(config: &AppConfig) -> Result<(AppVault, Box<dyn SessionStore>), Error> {
+    let pool = async_replicated_connection_with(&config.database).await?;
+    for version in migrate(pool.primary()).await? {
+        println!("DB Migration applied: V{}", version);
+    };
+    let builder = VaultBuilder::new(config.keys.certificate_manager())
+        .seal_token_owner()
+        .track_sessions(config.sessions.limit());
+    let repository = PostgresUserRepository::new(pool.clone());
+    // Display name and tenant go out with the authentication token, roles stay in the vault
+    let users = RepositoryAuthentication::new(repository.clone(), builder.password_hasher())
+        .with_enricher(Box::new(ProfileEnricher::new(repository)));
+    if let Some(store) = redis_store(config).await? {
+        let (store, handle) = with_handle(store);
+        return Ok((builder.build(users, store), handle));
+    };
+    let store = PostgresPersistence::new(pool, config.tokens.refresh_expiry);
+    // Sessions survive restarts and are shared with other instances, only expired ones are dropped
+    if let Err(e) = store.purge_expired().await {
+        eprintln!("Unable to purge expired sessions Reason: {}", e.to_string());
+    };
+    let (store, handle) = with_handle(store);
+    Ok((builder.build(users, store), handle))
 }
 
-impl WebVault {
-    pub fn new<T: Keys>(loader: T, pool: Pool<PostgresConnectionManager<NoTls>>) -> Self {
-        let public_authentication_certificate = loader.public_authentication_certificate().clone();
-        let private_authentication_certificate = loader.private_authentication_certificate().clone();
-        let public_refresh_certificate = loader.public_refresh_certificate().clone();
-        let private_refresh_certificate = loader.private_refresh_certificate().clone();
-        let password_hashing_secret = loader.password_hashing_secret();
-        let store = HashMap::new();
-
-        Self {
-            public_authentication_certificate,
-            private_authentication_certificate,
-            public_refresh_certificate,
-            private_refresh_certificate,
-            password_hashing_secret,
-            store,
-            pool,
-        }
-    }
-    async fn signup_app_user(&self, user: &str, password: &str) -> Result<String, Error> {
-        let user_id = format!("{}", digest::<_, DefaultHasher>(user));
-        let secret_key = self.password_hashing_secret.as_str();
-        let password = hash_password_with_argon(
-            password,
-            secret_key,
-        )?;
-        let _ = signup_user::<&str>(self.pool.clone(), &user_id, &password).await?;
-        Ok(user_id)
-    }
+#[cfg(not(feature = "sqlite"))]
+fn pool_status(users: &AppRepository) -> Option<PoolStatus> {
+    Some(users.pool().status())
 }
 
-
-#[async_trait]
-impl Persistence for WebVault {
-    async fn store(&mut self, key: u64, value: String) {
-        self.store.insert(key, value);
-    }
-
-    async fn load(&self, key: u64) -> Option<&String> {
-        self.store.get(&key)
-    }
-
-    async fn remove(&mut self, key: u64) -> Option<String> {
-        self.store.remove(&key)
-    }
+#[cfg(feature = "sqlite")]
+async fn connect(config: &AppConfig) -> Result<(AppVault, Box<dyn SessionStore>), Error> {
+    let db = SqliteDatabase::open(config.database.sqlite_path.as_str())?;
+    let builder = VaultBuilder::new(config.keys.certificate_manager())
+        .seal_token_owner()
+        .track_sessions(config.sessions.limit());
+    let repository = SqliteUserRepository::new(db.clone());
+    let users = RepositoryAuthentication::new(repository.clone(), builder.password_hasher())
+        .with_enricher(Box::new(ProfileEnricher::new(repository)));
+    if let Some(store) = redis_store(config).await? {
+        let (store, handle) = with_handle(store);
+        return Ok((builder.build(users, store), handle));
+    };
+    let store = SqlitePersistence::new(db, config.tokens.refresh_expiry);
+    // Sessions survive restarts and are shared with other instances, only expired ones are dropped
+    if let Err(e) = store.purge_expired().await {
+        eprintln!("Unable to purge expired sessions Reason: {}", e.to_string());
+    };
+    let (store, handle) = with_handle(store);
+    Ok((builder.build(users, store), handle))
 }
 
-#[async_trait]
-impl UserIdentity for WebVault {
-    async fn check_same_user(&self, _: &str, _: &str) -> Result<(), Error> {
-        // If the user was encrypted then it can be decrypted and compared
-        Ok(())
-    }
+#[cfg(feature = "sqlite")]
+fn pool_status(_: &AppRepository) -> Option<PoolStatus> {
+    // A single sqlite connection, nothing to report
+    None
 }
 
-#[async_trait]
-impl UserAuthentication for WebVault {
-    async fn check_user_valid(&mut self, user: &str, password: &str) -> Result<Option<Session>, Error> {
-        // lookup database instead of im-memory
-        let password_from_disk = resolve_password_for_user::<&str>(self.pool.clone(), user).await?;
-        // let password_from_disk = self.users.get(&user.to_string());
-        if password_from_disk.is_none() {
-            let msg = "Login Failed".to_string();
-            let reason = "Invalid userid/password".to_string();
-            return Err(LoginFailed::InvalidPassword(msg, reason).into());
-        };
-
-        let password_from_disk = password_from_disk.unwrap();
-        let result = verify_user_password_with_argon(password, self.password_hashing_secret.as_str(), password_from_disk.as_str())?;
-        if !result {
-            let msg = "Login Failed".to_string();
-            let reason = "Invalid userid/password".to_string();
-            return Err(LoginFailed::InvalidPassword(msg, reason).into());
-        };
-        let reference = digest::<_, DefaultHasher>(user.as_bytes());
-        let mut server = HashMap::new();
-        server.insert(reference, user.clone().as_bytes().to_vec());
-        let session = Session::new(None, Some(server));
-        Ok(Some(session))
-    }
+async fn signup_app_user(vault: &AppVault, user: &str, password: &str) -> Result<String, Error> {
+    let user_id = format!("{}", digest::<_, DefaultHasher>(user));
+    let password = vault.hash_user_password(user, password)?;
+    let _ = vault.users().repository().signup_user(user_id.as_str(), password.as_str()).await?;
+    vault.users().repository().assign_role(user_id.as_str(), SIGNUP_ROLE).await?;
+    Ok(user_id)
 }
 
 
-#[async_trait]
-impl Workflow<DefaultHasher, ArgonHasher<'static>> for WebVault {
-    async fn login(&mut self, user: &str, pass: &str, authentication_token_expiry_in_seconds: Option<i64>, refresh_token_expiry_in_seconds: Option<i64>) -> Result<Token, Error> {
-        continue_login(self, user, pass, authentication_token_expiry_in_seconds, refresh_token_expiry_in_seconds).await
-    }
-
-    async fn renew(&mut self, user: &str, client_refresh_token: &String, authentication_token_expiry_in_seconds: Option<i64>) -> Result<String, Error> {
-        continue_renew(self, user, client_refresh_token, authentication_token_expiry_in_seconds).await
-    }
-
-    async fn logout(&mut self, user: &str, client_authentication_token: &String) -> Result<(), Error> {
-        continue_logout(self, user, client_authentication_token).await
-    }
-
-    async fn revoke(&mut self, client_refresh_token: &String) -> Result<(), Error> {
-        continue_revoke(self, client_refresh_token).await
-    }
+struct ServerVault {
+    tokens: TokenConfig,
+    /// Held across database calls, so it must be an async lock: a blocked worker thread could never
+    /// resume the request holding it
+    vault: Mutex<AppVault>,
+    /// Shares the pool with the vault, `/status` reads it without waiting for the vault lock
+    users: AppRepository,
 }
 
-
-impl Default for WebVault {
-    fn default() -> Self {
-        let pool = connection();
-        if let Err(e) = pool {
-            eprintln!("DB Connection failed Reason: {}", e.to_string());
-        };
-        let pool = connection().ok().unwrap();
-        Self::new(CertificateManger::default(), pool)
+#[async_trait(?Send)]
+impl SessionResolver for ServerVault {
+    async fn resolve_session(&self, user: &str, token: &str) -> Result<ServerClaims, Error> {
+        let mut engine = self.vault.lock().await;
+        resolve_session_from_client_authentication_token(engine.deref_mut(), user, token).await
     }
 }
 
-async fn signup_user<T: AsRef<str>>(pool: Pool<PostgresConnectionManager<NoTls>>, user: T, password: T) -> Result<(), Error> {
-    let mut conn = pool.get()?;
-    let user = user.as_ref();
-    let password = password.as_ref();
-    // Watch out for SQL Injection
-    let query = format!("INSERT INTO tbl_users VALUES ('{}', '{}')", user, password);
-    let _ = conn.execute(query.as_str(), &[])?;
-    Ok(())
-}
+/// Granted through `tbl_role_permissions`, see `database::roles`
+struct CanExecute;
 
-async fn resolve_password_for_user<T: AsRef<str>>(pool: Pool<PostgresConnectionManager<NoTls>>, user: T) -> Result<Option<String>, Error> {
-    let mut conn = pool.get()?;
-    let query = format!("SELECT user_password FROM tbl_users WHERE user_id = '{}' ", user.as_ref());
-    let rs = conn.query(query.as_str(), &[])?;
-    for row in rs {
-        let rs: Option<String> = row.get(0);
-        return Ok(rs);
-    }
-    return Ok(None);
+impl Requirement for CanExecute {
+    const PERMISSIONS: &'static [&'static str] = &["execute"];
 }
 
+/// Any valid session, e.g. to manage the user's own sessions
+struct SignedIn;
 
-struct ServerVault {
-    vault: Mutex<WebVault>
+impl Requirement for SignedIn {
+    const PERMISSIONS: &'static [&'static str] = &[];
 }
 
 #[get("/")]
@@ -221,10 +170,10 @@ async fn signup(info: web::Path<(String, String)>, vault: web::Data<ServerVault>
     let user = &info.0;
     let password = &info.1;
     println!("user = {} password = {}", user, password);
-    let manager = vault.vault.lock().unwrap();
-    let result = manager.signup_app_user(user, password).await;
+    let manager = vault.vault.lock().await;
+    let result = signup_app_user(&manager, user, password).await;
     if result.is_err() {
-        return Response::from_error(ErrorBadRequest(result.err().unwrap()));
+        return error_response(&result.err().unwrap());
     };
     let result = result.ok().unwrap();
 
@@ -243,20 +192,21 @@ async fn signup(info: web::Path<(String, String)>, vault: web::Data<ServerVault>
 async fn login(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Login ===");
 
-    let mut manager = vault.vault.lock().unwrap();
+    let mut manager = vault.vault.lock().await;
 
     let user = &info.0;
     let password = &info.1;
     println!("user = {} password = {}", user, password);
 
+    let expiries = vault.tokens.login_expiries();
     let token = manager.login(
         user.as_str(),
         password.as_str(),
-        None,
-        None,
+        expiries.0,
+        expiries.1,
     ).await;
     if token.is_err() {
-        return Response::from_error(ErrorBadRequest(token.err().unwrap()));
+        return error_response(&token.err().unwrap());
     };
     let token = token.ok().unwrap();
     let token = serde_json::to_string(&token).unwrap();
@@ -273,47 +223,26 @@ async fn login(info: web::Path<(String, String)>, vault: web::Data<ServerVault>)
 }
 
 #[get("/execute/{user}/{token}")]
-async fn execute(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
+async fn execute(access: Authorized<CanExecute, ServerVault>) -> Response {
     println!("=== Execute ===");
-    let mut engine = vault.vault.lock().unwrap();
-    let vault = engine.deref_mut();
-
-    let user = &info.0;
-    let token = &info.1;
-
-    let result = resolve_session_from_client_authentication_token(
-        vault,
-        user.as_str(), token.as_str(),
-    ).await;
-
-    if result.is_err() {
-        let response = Response::Unauthorized()
-            .header("content-type", "text/plain")
-            .finish();
-        return response;
-    };
-    let result = result.ok();
-
-    if result.is_none() {
-        let response = Response::NotAcceptable()
-            .header("content-type", "text/plain")
-            .finish();
-        return response;
-    };
-    let session = result.unwrap();
+    let user = access.user();
+    let session = access.session();
 
     let client = session.client();
     let server = session.server();
 
     println!("Session for User: {} - Client: {:#?} Server: {:#?}", user, client, server.unwrap());
 
+    let public = UserProfile::from_session(client, None);
+    let profile = access.profile();
+    println!("Profile for User: {} - Public: {:?} Private: {:?}", user, public, profile);
+
     // Prepare json for dispatch
-    let body = Body::from(
-        format!("{}", "Executed")
-    );
+    let profile = serde_json::to_string(profile).unwrap();
+    let body = Body::from(profile);
 
     let response = Response::Ok()
-        .header("Content-Type", "text/plain")
+        .header("Content-Type", "application/json")
         .finish();
     response.set_body(body)
 }
@@ -321,16 +250,13 @@ async fn execute(info: web::Path<(String, String)>, vault: web::Data<ServerVault
 #[get("/renew/{user}/{token}")]
 async fn renew(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Renew ===");
-    let mut engine = vault.vault.lock().unwrap();
+    let mut engine = vault.vault.lock().await;
     let user = &info.0;
     let client_refresh_token = &info.1;
 
-    let result = engine.renew(user.as_str(), &client_refresh_token, None).await;
-    if result.is_err() {
-        let response = Response::Unauthorized()
-            .header("content-type", "text/plain")
-            .finish();
-        return response;
+    let result = engine.renew(user.as_str(), &client_refresh_token, vault.tokens.authentication_expiry).await;
+    if let Err(e) = &result {
+        return error_response(e);
     };
     let client_authentication_token = result.ok().unwrap();
 
@@ -354,15 +280,12 @@ async fn renew(info: web::Path<(String, String)>, vault: web::Data<ServerVault>)
 #[get("/logout/{user}/{token}")]
 async fn logout(info: web::Path<(String, String)>, vault: web::Data<ServerVault>) -> Response {
     println!("=== Logout ===");
-    let mut engine = vault.vault.lock().unwrap();
+    let mut engine = vault.vault.lock().await;
     let user = &info.0;
     let client_authentication_token = &info.1;
     let result = engine.logout(user.as_str(), client_authentication_token).await;
-    if result.is_err() {
-        let response = Response::Unauthorized()
-            .header("content-type", "text/plain")
-            .finish();
-        return response;
+    if let Err(e) = &result {
+        return error_response(e);
     };
     println!("Logout: {}", user);
 
@@ -378,15 +301,95 @@ async fn logout(info: web::Path<(String, String)>, vault: web::Data<ServerVault>
     response.set_body(body)
 }
 
+#[get("/sessions/{user}/{token}")]
+async fn sessions(access: Authorized<SignedIn, ServerVault>, vault: web::Data<ServerVault>) -> Response {
+    println!("=== Sessions ===");
+    let user = access.user();
+    let manager = vault.vault.lock().await;
+    let sessions = manager.list_sessions(user).await;
+    println!("Sessions for User: {} - {}", user, sessions.len());
+
+    // Prepare json for dispatch
+    let sessions = serde_json::to_string(&sessions).unwrap();
+    let body = Body::from(sessions);
+
+    let response = Response::Ok()
+        .header("Content-Type", "application/json")
+        .finish();
+    response.set_body(body)
+}
+
+/// Ends every session of the user, the one presenting the token included, e.g. after a password change
+#[get("/revoke/{user}/{token}")]
+async fn revoke(access: Authorized<SignedIn, ServerVault>, vault: web::Data<ServerVault>) -> Response {
+    println!("=== Revoke ===");
+    let user = access.user();
+    let mut manager = vault.vault.lock().await;
+    let revoked = manager.revoke_sessions(user).await;
+    println!("Revoked: {} - {}", user, revoked);
+
+    // Prepare json for dispatch
+    let body = Body::from(
+        format!("Revoked sessions: {}", revoked)
+    );
+
+    let response = Response::Ok()
+        .header("Content-Type", "text/plain")
+        .finish();
+
+    response.set_body(body)
+}
+
+#[get("/status")]
+async fn status(vault: web::Data<ServerVault>) -> Response {
+    println!("=== Status ===");
+    let status = pool_status(&vault.users);
+    if status.is_none() {
+        let msg = "Pool status unavailable".to_string();
+        let reason = "Not running on a connection pool".to_string();
+        return error_response(&DatabaseErrors::NotFound(msg, reason).into());
+    };
+    let status = serde_json::to_string(&status.unwrap()).unwrap();
+
+    let body = Body::from(
+        status
+    );
+
+    let response = Response::Ok()
+        .header("Content-Type", "application/json")
+        .finish();
+
+    response.set_body(body)
+}
+
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    dotenv::dotenv().ok();
-    let uri = "127.0.0.1:8080";
-
-    let vault = Mutex::new(WebVault::default());
-    let vault = ServerVault { vault };
+    let config = AppConfig::load().map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+    })?;
+    let uri = config.server.address();
+
+    let (vault, store) = connect(&config).await.map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+    })?;
+    let users = vault.users().repository().clone();
+    let vault = Mutex::new(vault);
+    let vault = ServerVault { tokens: config.tokens, vault, users };
     let vault = web::Data::new(vault);
 
+    // Swept through a handle of its own, so the sweeper never waits for the vault: sqlite deletes expired rows,
+    // postgres purges them on startup and redis expires them itself, none of them loads an expired session
+    let sweeper = {
+        let mut store = store;
+        Sweeper::spawn(config.sessions.sweep_interval(), move || {
+            let evicted = store.sweep();
+            if evicted > 0 {
+                println!("Sessions evicted: {}", evicted);
+            };
+            evicted
+        })
+    };
+
     let server = HttpServer::new(move || {
         App::new()
             .app_data(vault.clone())
@@ -396,6 +399,9 @@ async fn main() -> std::io::Result<()> {
             .service(execute)
             .service(renew)
             .service(logout)
+            .service(sessions)
+            .service(revoke)
+            .service(status)
     });
 
     println!("Running Server: {}", uri);
@@ -406,7 +412,13 @@ async fn main() -> std::io::Result<()> {
     println!("03 - Execute: http://{}/execute/<userid>/<authentication_token>", uri);
     println!("04 - Renew: http://{}/renew/<userid>/<refresh_token>", uri);
     println!("05 - Logout: http://{}/logout/<userid>/<authentication_token>", uri);
-
-    server.bind(uri)?.workers(1).run().await
+    println!("06 - Pool status: http://{}/status", uri);
+    println!("07 - Sessions: http://{}/sessions/<userid>/<authentication_token>", uri);
+    println!("08 - Revoke all sessions: http://{}/revoke/<userid>/<authentication_token>", uri);
+
+    let result = server.bind(uri)?.workers(config.server.workers).run().await;
+    println!("Session sweeps: {} Evicted: {}", sweeper.sweeps(), sweeper.evictions());
+    sweeper.stop();
+    result
 }
 
@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use failure::Error;
+use jwtvault::prelude::CertificateManger;
+use jwtvault::constants::{
+    DEFAULT_PUBLIC_AUTHENTICATION_TOKEN_PATH, DEFAULT_PRIVATE_AUTHENTICATION_TOKEN_PATH,
+    DEFAULT_PUBLIC_REFRESH_TOKEN_PATH, DEFAULT_PRIVATE_REFRESH_TOKEN_PATH, DEFAULT_PASSWORD_HASHING_SECRET_PATH,
+};
+
+use crate::database::db_common::{
+    pg_pool_config_from, pg_retry_config_from, pg_connection_params_from, pg_replica_params_from,
+    ConnectionParams, ConnectionConfig, PoolConfig, RetryConfig,
+};
+use crate::errors::ConfigErrors::{self, UnreadableFile, InvalidFile, UnknownSetting, InvalidSetting};
+use crate::database::errors::DatabaseErrors::ConnectionFailed;
+use crate::database::fixtures::DEFAULT_FIXTURES_PATH;
+use crate::stores::file::FsyncPolicy;
+use crate::stores::sharded::DEFAULT_SHARDS;
+use crate::stores::redis::RedisConfig;
+use crate::stores::index::{LimitPolicy, SessionLimit};
+
+/// Read when present; `--config` or `APP_CONFIG` name a file that must exist
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// One setting, as named in the config file, the environment and on the command line
+struct Setting {
+    key: &'static str,
+    var: &'static str,
+    flag: &'static str,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting { key: "database.url", var: "DATABASE_URL", flag: "--database-url" },
+    Setting { key: "database.replicas", var: "DATABASE_REPLICAS", flag: "--database-replicas" },
+    Setting { key: "database.host", var: "PG_HOST", flag: "--pg-host" },
+    Setting { key: "database.port", var: "PG_PORT", flag: "--pg-port" },
+    Setting { key: "database.dbname", var: "PG_DBNAME", flag: "--pg-dbname" },
+    Setting { key: "database.user", var: "PG_USER", flag: "--pg-user" },
+    Setting { key: "database.password", var: "PG_PASSWORD", flag: "--pg-password" },
+    Setting { key: "database.sslmode", var: "PG_SSLMODE", flag: "--pg-sslmode" },
+    Setting { key: "database.sslrootcert", var: "PG_SSLROOTCERT", flag: "--pg-sslrootcert" },
+    Setting { key: "database.sslcert", var: "PG_SSLCERT", flag: "--pg-sslcert" },
+    Setting { key: "database.sslkey", var: "PG_SSLKEY", flag: "--pg-sslkey" },
+    Setting { key: "database.sqlite_path", var: "SQLITE_PATH", flag: "--sqlite-path" },
+    Setting { key: "pool.min_size", var: "POOL_MIN_SIZE", flag: "--pool-min-size" },
+    Setting { key: "pool.max_size", var: "POOL_MAX_SIZE", flag: "--pool-max-size" },
+    Setting { key: "pool.connection_timeout", var: "POOL_CONNECTION_TIMEOUT", flag: "--pool-connection-timeout" },
+    Setting { key: "pool.idle_timeout", var: "POOL_IDLE_TIMEOUT", flag: "--pool-idle-timeout" },
+    Setting { key: "pool.max_lifetime", var: "POOL_MAX_LIFETIME", flag: "--pool-max-lifetime" },
+    Setting { key: "pool.test_on_checkout", var: "POOL_TEST_ON_CHECKOUT", flag: "--pool-test-on-checkout" },
+    Setting { key: "retry.attempts", var: "DB_CONNECT_ATTEMPTS", flag: "--db-connect-attempts" },
+    Setting { key: "retry.initial_delay_ms", var: "DB_CONNECT_INITIAL_DELAY_MS", flag: "--db-connect-initial-delay-ms" },
+    Setting { key: "retry.max_delay_ms", var: "DB_CONNECT_MAX_DELAY_MS", flag: "--db-connect-max-delay-ms" },
+    Setting { key: "server.host", var: "SERVER_HOST", flag: "--host" },
+    Setting { key: "server.port", var: "SERVER_PORT", flag: "--port" },
+    Setting { key: "server.workers", var: "SERVER_WORKERS", flag: "--workers" },
+    Setting { key: "keys.dir", var: "KEYS_DIR", flag: "--keys-dir" },
+    Setting { key: "keys.public_authentication", var: "PUBLIC_AUTHENTICATION_KEY", flag: "--public-authentication-key" },
+    Setting { key: "keys.private_authentication", var: "PRIVATE_AUTHENTICATION_KEY", flag: "--private-authentication-key" },
+    Setting { key: "keys.public_refresh", var: "PUBLIC_REFRESH_KEY", flag: "--public-refresh-key" },
+    Setting { key: "keys.private_refresh", var: "PRIVATE_REFRESH_KEY", flag: "--private-refresh-key" },
+    Setting { key: "keys.password_hashing_secret", var: "PASSWORD_HASHING_SECRET", flag: "--password-hashing-secret" },
+    Setting { key: "tokens.authentication_expiry", var: "AUTHENTICATION_TOKEN_EXPIRY", flag: "--authentication-expiry" },
+    Setting { key: "tokens.refresh_expiry", var: "REFRESH_TOKEN_EXPIRY", flag: "--refresh-expiry" },
+    Setting { key: "sessions.sweep_interval", var: "SESSION_SWEEP_INTERVAL", flag: "--session-sweep-interval" },
+    Setting { key: "sessions.dir", var: "SESSION_STORE_DIR", flag: "--session-store-dir" },
+    Setting { key: "sessions.fsync", var: "SESSION_FSYNC", flag: "--session-fsync" },
+    Setting { key: "sessions.compact_after", var: "SESSION_COMPACT_AFTER", flag: "--session-compact-after" },
+    Setting { key: "sessions.shards", var: "SESSION_SHARDS", flag: "--session-shards" },
+    Setting { key: "sessions.redis_url", var: "SESSION_REDIS_URL", flag: "--session-redis-url" },
+    Setting { key: "sessions.max_per_user", var: "SESSION_MAX_PER_USER", flag: "--session-max-per-user" },
+    Setting { key: "sessions.on_limit", var: "SESSION_ON_LIMIT", flag: "--session-on-limit" },
+    Setting { key: "fixtures.path", var: "USERS_FIXTURES", flag: "--fixtures" },
+];
+
+/// Raw setting values keyed by variable name; later layers replace earlier ones
+#[derive(Debug, Default)]
+struct Layers {
+    values: HashMap<&'static str, String>,
+}
+
+impl Layers {
+    fn get(&self, var: &str) -> Option<String> {
+        self.values.get(var).cloned()
+    }
+
+    /// Sections map to tables, e.g. `[pool]` `min_size = 4` is `pool.min_size`
+    fn add_file(&mut self, data: &str, path: &str) -> Result<(), Error> {
+        let msg = format!("Invalid config file: {}", path);
+        let root = data.parse::<toml::Value>().map_err(|e| InvalidFile(msg.clone(), e.to_string()))?;
+        let sections = root.as_table().ok_or(InvalidFile(msg.clone(), "Expected a table".to_string()))?;
+        for (section, table) in sections.iter() {
+            let table = table.as_table()
+                .ok_or(InvalidFile(msg.clone(), format!("Expected [{}] to be a table", section)))?;
+            for (name, value) in table.iter() {
+                let key = format!("{}.{}", section, name);
+                let setting = SETTINGS.iter().find(|s| s.key == key)
+                    .ok_or(UnknownSetting(msg.clone(), format!("Unknown key: {}", key)))?;
+                let value = match value {
+                    toml::Value::String(s) => s.clone(),
+                    toml::Value::Integer(n) => n.to_string(),
+                    toml::Value::Boolean(b) => b.to_string(),
+                    // Lists of strings are comma separated, the way `DATABASE_REPLICAS` spells them
+                    toml::Value::Array(items) if items.iter().all(|item| item.is_str()) => {
+                        items.iter().filter_map(|item| item.as_str()).collect::<Vec<_>>().join(",")
+                    }
+                    _ => return Err(InvalidFile(msg.clone(), format!("Expected a string, integer, boolean or list of strings for {}", key)).into())
+                };
+                self.values.insert(setting.var, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_vars<F: Fn(&str) -> Option<String>>(&mut self, lookup: F) {
+        for setting in SETTINGS.iter() {
+            if let Some(value) = lookup(setting.var) {
+                self.values.insert(setting.var, value);
+            };
+        }
+    }
+
+    fn add_flags(&mut self, flags: Vec<(&'static str, String)>) {
+        for (var, value) in flags {
+            self.values.insert(var, value);
+        }
+    }
+}
+
+/// Splits `--flag value` / `--flag=value` pairs into the config file path and the settings
+fn parse_args<I: IntoIterator<Item=String>>(args: I) -> Result<(Option<String>, Vec<(&'static str, String)>), Error> {
+    let mut config_path = None;
+    let mut flags = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            _ => (arg.clone(), None)
+        };
+        let setting = SETTINGS.iter().find(|s| s.flag == flag);
+        if flag != "--config" && setting.is_none() {
+            return Err(UnknownSetting("Invalid command line".to_string(), format!("Unknown argument: {}", flag)).into());
+        };
+        let value = match value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(InvalidSetting("Invalid command line".to_string(), format!("Missing value for {}", flag)).into())
+        };
+        match setting {
+            Some(setting) => flags.push((setting.var, value)),
+            None => config_path = Some(value),
+        };
+    }
+    Ok((config_path, flags))
+}
+
+fn invalid(section: &str) -> impl Fn(String) -> ConfigErrors + '_ {
+    move |e| InvalidSetting(format!("Invalid {} configuration", section), e)
+}
+
+fn parse_from<T: std::str::FromStr>(layers: &Layers, var: &str) -> Result<Option<T>, String> {
+    match layers.get(var) {
+        Some(value) => value.parse::<T>().map(Some).map_err(|_| format!("Invalid {}: {}", var, value)),
+        None => Ok(None)
+    }
+}
+
+/// Postgres components stay unresolved until a connection is needed
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatabaseConfig {
+    pub params: ConnectionParams,
+    /// Read replicas, components left out are taken from `params`
+    pub replicas: Vec<ConnectionParams>,
+    pub pool: PoolConfig,
+    pub retry: RetryConfig,
+    pub sqlite_path: String,
+}
+
+impl DatabaseConfig {
+    /// Environment only, see `AppConfig::load` for all the layers
+    pub fn from_env() -> Result<Self, Error> {
+        let mut layers = Layers::default();
+        layers.add_vars(|var| env::var(var).ok());
+        Ok(Self::from_layers(&layers)?)
+    }
+
+    fn from_layers(layers: &Layers) -> Result<Self, ConfigErrors> {
+        let lookup = |var: &str| layers.get(var);
+        let params = pg_connection_params_from(lookup).map_err(invalid("database"))?;
+        let replicas = pg_replica_params_from(lookup, &params).map_err(invalid("database"))?;
+        let pool = pg_pool_config_from(lookup).map_err(invalid("pool"))?;
+        let retry = pg_retry_config_from(lookup).map_err(invalid("retry"))?;
+        let sqlite_path = layers.get("SQLITE_PATH").unwrap_or(":memory:".to_string());
+        Ok(Self { params, replicas, pool, retry, sqlite_path })
+    }
+
+    pub fn connection_config(&self) -> Result<ConnectionConfig, Error> {
+        self.params.clone().resolve().map_err(|e| {
+            ConnectionFailed("Bad Connection Config".to_string(), failure::err_msg(e)).into()
+        })
+    }
+
+    pub fn replica_configs(&self) -> Result<Vec<ConnectionConfig>, Error> {
+        let mut configs = Vec::with_capacity(self.replicas.len());
+        for replica in self.replicas.iter() {
+            let config = replica.clone().resolve().map_err(|e| {
+                ConnectionFailed("Bad Replica Connection Config".to_string(), failure::err_msg(e))
+            })?;
+            configs.push(config);
+        }
+        Ok(configs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub workers: usize,
+}
+
+impl ServerConfig {
+    /// Defaults: 127.0.0.1:8080 with a single worker
+    fn from_layers(layers: &Layers) -> Result<Self, ConfigErrors> {
+        let host = layers.get("SERVER_HOST").unwrap_or("127.0.0.1".to_string());
+        let port: u16 = parse_from(layers, "SERVER_PORT").map_err(invalid("server"))?.unwrap_or(8080);
+        let workers: usize = parse_from(layers, "SERVER_WORKERS").map_err(invalid("server"))?.unwrap_or(1);
+        if workers == 0 {
+            return Err(invalid("server")("Invalid SERVER_WORKERS: 0".to_string()));
+        };
+        Ok(Self { host, port, workers })
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+/// Paths to the files created by `generate_certificates.sh`
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeysConfig {
+    pub public_authentication: String,
+    pub private_authentication: String,
+    pub public_refresh: String,
+    pub private_refresh: String,
+    pub password_hashing_secret: String,
+}
+
+impl KeysConfig {
+    /// File names are relative to `KEYS_DIR` (default `store`) unless absolute; every file must exist
+    fn from_layers(layers: &Layers) -> Result<Self, ConfigErrors> {
+        let dir = layers.get("KEYS_DIR").unwrap_or("store".to_string());
+        let path = |var: &str, default: &str| -> Result<String, ConfigErrors> {
+            let name = layers.get(var).unwrap_or_else(|| {
+                Path::new(default).file_name().unwrap().to_string_lossy().to_string()
+            });
+            let path = Path::new(dir.as_str()).join(name);
+            if !path.is_file() {
+                return Err(invalid("keys")(format!("Missing {}: {}", var, path.display())));
+            };
+            Ok(path.to_string_lossy().to_string())
+        };
+        Ok(Self {
+            public_authentication: path("PUBLIC_AUTHENTICATION_KEY", DEFAULT_PUBLIC_AUTHENTICATION_TOKEN_PATH)?,
+            private_authentication: path("PRIVATE_AUTHENTICATION_KEY", DEFAULT_PRIVATE_AUTHENTICATION_TOKEN_PATH)?,
+            public_refresh: path("PUBLIC_REFRESH_KEY", DEFAULT_PUBLIC_REFRESH_TOKEN_PATH)?,
+            private_refresh: path("PRIVATE_REFRESH_KEY", DEFAULT_PRIVATE_REFRESH_TOKEN_PATH)?,
+            password_hashing_secret: path("PASSWORD_HASHING_SECRET", DEFAULT_PASSWORD_HASHING_SECRET_PATH)?,
+        })
+    }
+
+    pub fn certificate_manager(&self) -> CertificateManger {
+        CertificateManger::new(
+            self.public_authentication.clone(),
+            self.private_authentication.clone(),
+            self.public_refresh.clone(),
+            self.private_refresh.clone(),
+            self.password_hashing_secret.clone(),
+        )
+    }
+}
+
+/// Token lifetimes in seconds; `None` keeps the jwtvault defaults
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenConfig {
+    pub authentication_expiry: Option<i64>,
+    pub refresh_expiry: Option<i64>,
+}
+
+impl TokenConfig {
+    fn from_layers(layers: &Layers) -> Result<Self, ConfigErrors> {
+        let authentication_expiry: Option<i64> = parse_from(layers, "AUTHENTICATION_TOKEN_EXPIRY").map_err(invalid("tokens"))?;
+        let refresh_expiry: Option<i64> = parse_from(layers, "REFRESH_TOKEN_EXPIRY").map_err(invalid("tokens"))?;
+        if let Some(n) = authentication_expiry.filter(|n| *n <= 0) {
+            return Err(invalid("tokens")(format!("Invalid AUTHENTICATION_TOKEN_EXPIRY: {}", n)));
+        };
+        if let Some(n) = refresh_expiry.filter(|n| *n <= 0) {
+            return Err(invalid("tokens")(format!("Invalid REFRESH_TOKEN_EXPIRY: {}", n)));
+        };
+        if let (Some(authentication), Some(refresh)) = (authentication_expiry, refresh_expiry) {
+            if authentication > refresh {
+                let e = format!("AUTHENTICATION_TOKEN_EXPIRY ({}) exceeds REFRESH_TOKEN_EXPIRY ({})", authentication, refresh);
+                return Err(invalid("tokens")(e));
+            };
+        };
+        Ok(Self { authentication_expiry, refresh_expiry })
+    }
+
+    /// Expiry arguments for `Workflow::login`, in the order the vaults apply them:
+    /// jwtvault 0.6 hands them on to `continue_login`, which takes the refresh expiry first
+    pub fn login_expiries(&self) -> (Option<i64>, Option<i64>) {
+        (self.refresh_expiry, self.authentication_expiry)
+    }
+}
+
+/// Session storage of the examples without a database
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionConfig {
+    /// Seconds between sweeps of expired sessions
+    pub sweep_interval: u64,
+    /// Directory of the `FilePersistence` log and snapshot; `None` keeps sessions in memory only
+    pub store_dir: Option<String>,
+    pub fsync: FsyncPolicy,
+    /// Log records after which the log is compacted into a snapshot
+    pub compact_after: usize,
+    /// Shards of the `ShardedPersistence` shared by the actix-static and actix-dynamic workers
+    pub shards: usize,
+    /// Session server of the webserver examples; `None` keeps sessions in their database or in memory
+    pub redis: Option<RedisConfig>,
+    /// Concurrent sessions per user of the vaults which track sessions; `None` is unlimited
+    pub max_per_user: Option<usize>,
+    /// What a login beyond `max_per_user` does
+    pub on_limit: LimitPolicy,
+}
+
+impl SessionConfig {
+    /// Defaults: sweep every minute, in memory over 16 shards; on disk fsync every write and compact after 1000 records;
+    /// no cap on the sessions of a user, which evicts the oldest session when set
+    fn from_layers(layers: &Layers) -> Result<Self, ConfigErrors> {
+        let sweep_interval: u64 = parse_from(layers, "SESSION_SWEEP_INTERVAL").map_err(invalid("sessions"))?.unwrap_or(60);
+        if sweep_interval == 0 {
+            return Err(invalid("sessions")("Invalid SESSION_SWEEP_INTERVAL: 0".to_string()));
+        };
+        let store_dir = layers.get("SESSION_STORE_DIR").filter(|dir| !dir.trim().is_empty());
+        let fsync = parse_from(layers, "SESSION_FSYNC").map_err(invalid("sessions"))?.unwrap_or(FsyncPolicy::Always);
+        let compact_after: usize = parse_from(layers, "SESSION_COMPACT_AFTER").map_err(invalid("sessions"))?.unwrap_or(1000);
+        if compact_after == 0 {
+            return Err(invalid("sessions")("Invalid SESSION_COMPACT_AFTER: 0".to_string()));
+        };
+        let shards: usize = parse_from(layers, "SESSION_SHARDS").map_err(invalid("sessions"))?.unwrap_or(DEFAULT_SHARDS);
+        if shards == 0 {
+            return Err(invalid("sessions")("Invalid SESSION_SHARDS: 0".to_string()));
+        };
+        let redis = parse_from(layers, "SESSION_REDIS_URL").map_err(invalid("sessions"))?;
+        let max_per_user: Option<usize> = parse_from(layers, "SESSION_MAX_PER_USER").map_err(invalid("sessions"))?;
+        if max_per_user == Some(0) {
+            return Err(invalid("sessions")("Invalid SESSION_MAX_PER_USER: 0".to_string()));
+        };
+        let on_limit = parse_from(layers, "SESSION_ON_LIMIT").map_err(invalid("sessions"))?.unwrap_or(LimitPolicy::EvictOldest);
+        Ok(Self { sweep_interval, store_dir, fsync, compact_after, shards, redis, max_per_user, on_limit })
+    }
+
+    pub fn sweep_interval(&self) -> Duration {
+        Duration::from_secs(self.sweep_interval)
+    }
+
+    /// See `vault::VaultBuilder::track_sessions`
+    pub fn limit(&self) -> SessionLimit {
+        SessionLimit { max_sessions: self.max_per_user, policy: self.on_limit }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppConfig {
+    pub database: DatabaseConfig,
+    pub server: ServerConfig,
+    pub keys: KeysConfig,
+    pub tokens: TokenConfig,
+    pub sessions: SessionConfig,
+    pub fixtures: String,
+}
+
+impl AppConfig {
+    /// Precedence (highest first)
+    /// * command line flags, e.g. `--port 9090` or `--port=9090`
+    /// * environment (including `.env`)
+    /// * config file: `--config`, `APP_CONFIG` or `config.toml`
+    /// * defaults
+    pub fn load() -> Result<Self, Error> {
+        dotenv::dotenv().ok();
+        Self::from_sources(|var| env::var(var).ok(), env::args().skip(1))
+    }
+
+    /// Same as `load`, with the environment coming from `lookup` and the flags from `args`
+    pub fn from_sources<F, I>(lookup: F, args: I) -> Result<Self, Error>
+        where F: Fn(&str) -> Option<String>, I: IntoIterator<Item=String> {
+        let (config_path, flags) = parse_args(args)?;
+        let config_path = config_path.or_else(|| lookup("APP_CONFIG"));
+        let mut layers = Layers::default();
+        match config_path {
+            Some(path) => {
+                let data = fs::read_to_string(path.as_str()).map_err(|e| {
+                    UnreadableFile(format!("Unable to read config file: {}", path), e.to_string())
+                })?;
+                layers.add_file(data.as_str(), path.as_str())?;
+            }
+            None => if let Ok(data) = fs::read_to_string(DEFAULT_CONFIG_PATH) {
+                layers.add_file(data.as_str(), DEFAULT_CONFIG_PATH)?;
+            }
+        };
+        layers.add_vars(lookup);
+        layers.add_flags(flags);
+        Ok(Self::from_layers(&layers)?)
+    }
+
+    fn from_layers(layers: &Layers) -> Result<Self, ConfigErrors> {
+        Ok(Self {
+            database: DatabaseConfig::from_layers(layers)?,
+            server: ServerConfig::from_layers(layers)?,
+            keys: KeysConfig::from_layers(layers)?,
+            tokens: TokenConfig::from_layers(layers)?,
+            sessions: SessionConfig::from_layers(layers)?,
+            fixtures: layers.get("USERS_FIXTURES").unwrap_or(DEFAULT_FIXTURES_PATH.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod config {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn keys_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for file in ["public_authentication_token.pem", "private_authentication_token.pem",
+            "public_refresh_token.pem", "private_refresh_token.pem", "password_hashing_secret.pem"].iter() {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        dir
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn app_config_layer_precedence() {
+        let dir = keys_dir("jwtvault_examples_config_precedence");
+        let file = dir.join("config.toml");
+        fs::write(&file, "[server]\nhost = \"0.0.0.0\"\nport = 7070\nworkers = 4\n\n[pool]\nmin_size = 2\nmax_size = 4\n").unwrap();
+        let keys = dir.to_string_lossy().to_string();
+        let lookup = |var: &str| match var {
+            "KEYS_DIR" => Some(keys.clone()),
+            "SERVER_PORT" => Some("8081".to_string()),
+            _ => None,
+        };
+        let config = AppConfig::from_sources(lookup, args(&["--config", file.to_str().unwrap(), "--workers=2"])).unwrap();
+        assert_eq!(config.server, ServerConfig { host: "0.0.0.0".to_string(), port: 8081, workers: 2 });
+        assert_eq!(config.database.pool, PoolConfig::new(2, 4));
+        assert_eq!(config.tokens, TokenConfig { authentication_expiry: None, refresh_expiry: None });
+        assert_eq!(config.sessions, SessionConfig { sweep_interval: 60, store_dir: None, fsync: FsyncPolicy::Always, compact_after: 1000, shards: 16, redis: None, max_per_user: None, on_limit: LimitPolicy::EvictOldest });
+        assert_eq!(config.fixtures, DEFAULT_FIXTURES_PATH.to_string());
+    }
+
+    #[test]
+    fn app_config_validation() {
+        let dir = keys_dir("jwtvault_examples_config_validation");
+        let keys = dir.to_string_lossy().to_string();
+        let lookup = |var: &str| if var == "KEYS_DIR" { Some(keys.clone()) } else { None };
+
+        let err = AppConfig::from_sources(lookup, args(&["--pool-min-size", "32"])).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid pool configuration. Reason: POOL_MIN_SIZE (32) exceeds POOL_MAX_SIZE (16)");
+        let err = AppConfig::from_sources(lookup, args(&["--authentication-expiry", "600", "--refresh-expiry", "60"])).unwrap_err();
+        assert!(err.to_string().contains("AUTHENTICATION_TOKEN_EXPIRY (600) exceeds REFRESH_TOKEN_EXPIRY (60)"));
+        let err = AppConfig::from_sources(lookup, args(&["--colour", "blue"])).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid command line. Reason: Unknown argument: --colour");
+        let err = AppConfig::from_sources(lookup, args(&["--port"])).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid command line. Reason: Missing value for --port");
+        let err = AppConfig::from_sources(|_: &str| None, args(&["--keys-dir", "/nonexistent"])).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid keys configuration. Reason: Missing PUBLIC_AUTHENTICATION_KEY"));
+        let err = AppConfig::from_sources(lookup, args(&["--session-fsync", "sometimes"])).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid sessions configuration. Reason: Invalid SESSION_FSYNC: sometimes");
+        let err = AppConfig::from_sources(lookup, args(&["--session-shards=0"])).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid sessions configuration. Reason: Invalid SESSION_SHARDS: 0");
+        let err = AppConfig::from_sources(lookup, args(&["--session-redis-url", "http://cache"])).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid sessions configuration. Reason: Invalid SESSION_REDIS_URL: http://cache");
+        let config = AppConfig::from_sources(lookup, args(&["--session-redis-url", "redis://cache/1"])).unwrap();
+        assert_eq!(config.sessions.redis.unwrap().address, "cache:6379".to_string());
+        let err = AppConfig::from_sources(lookup, args(&["--session-max-per-user", "0"])).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid sessions configuration. Reason: Invalid SESSION_MAX_PER_USER: 0");
+        let err = AppConfig::from_sources(lookup, args(&["--session-on-limit", "oldest"])).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid sessions configuration. Reason: Invalid SESSION_ON_LIMIT: oldest");
+        let config = AppConfig::from_sources(lookup, args(&["--session-max-per-user=3", "--session-on-limit", "reject"])).unwrap();
+        assert_eq!(config.sessions.limit(), SessionLimit { max_sessions: Some(3), policy: LimitPolicy::Reject });
+    }
+
+    #[test]
+    fn config_file_rejects_unknown_keys() {
+        let mut layers = Layers::default();
+        let err = layers.add_file("[server]\ncolour = \"blue\"\n", "config.toml").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid config file: config.toml. Reason: Unknown key: server.colour");
+        let err = layers.add_file("[server]\nport = [8080]\n", "config.toml").unwrap_err();
+        assert!(err.to_string().contains("Expected a string, integer, boolean or list of strings for server.port"));
+        layers.add_file("[database]\nreplicas = [\"postgres://replica-1\", \"postgres://replica-2\"]\n", "config.toml").unwrap();
+        assert_eq!(layers.get("DATABASE_REPLICAS"), Some("postgres://replica-1,postgres://replica-2".to_string()));
+    }
+}
@@ -3,4 +3,14 @@ pub mod setup;
 pub mod db_common;
 pub mod db_pool;
 pub mod r2d2_pool;
-pub mod users_setup;
\ No newline at end of file
+pub mod tokio_pool;
+pub mod tls;
+pub mod users_setup;
+pub mod password;
+pub mod fixtures;
+pub mod persistence;
+pub mod migrations;
+pub mod profiles;
+pub mod roles;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
\ No newline at end of file
@@ -1,20 +1,169 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
 use failure::Error;
+use serde::Serialize;
 
 use r2d2::{ManageConnection, Pool};
 
 use crate::database::db_common::PoolConfig;
 use crate::database::errors::DatabaseErrors::PoolCreationFailed;
 
-pub(crate) fn create_pool<M>(manager: M, pool_config: PoolConfig) -> Result<Pool<M>, Error>
+/// Snapshot of a connection pool
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PoolStatus {
+    pub max_size: u32,
+    pub connections: u32,
+    pub idle: u32,
+    pub in_use: u32,
+    pub waiting: u32,
+}
+
+impl PoolStatus {
+    pub(crate) fn new(max_size: u32, connections: u32, idle: u32, waiting: u32) -> Self {
+        let in_use = connections.saturating_sub(idle);
+        Self { max_size, connections, idle, in_use, waiting }
+    }
+}
+
+/// Counts a caller waiting for a connection until dropped
+pub(crate) struct WaitGuard<'a>(&'a AtomicU32);
+
+impl<'a> WaitGuard<'a> {
+    pub(crate) fn new(waiting: &'a AtomicU32) -> Self {
+        waiting.fetch_add(1, Ordering::SeqCst);
+        WaitGuard(waiting)
+    }
+}
+
+impl<'a> Drop for WaitGuard<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A primary pool for writes and replica pools for reads
+///
+/// Replicas lag behind the primary, so reads that must see a write just made belong on the primary.
+#[derive(Clone)]
+pub struct ReplicatedPool<P> {
+    primary: P,
+    replicas: Vec<P>,
+    next: Arc<AtomicUsize>,
+}
+
+impl<P> ReplicatedPool<P> {
+    pub fn new(primary: P, replicas: Vec<P>) -> Self {
+        let next = Arc::new(AtomicUsize::new(0));
+        Self { primary, replicas, next }
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    pub fn replicas(&self) -> &[P] {
+        self.replicas.as_slice()
+    }
+
+    /// Pools to try for a read: the replicas round-robin, then the primary
+    pub fn read_order(&self) -> Vec<&P> {
+        let mut pools = Vec::with_capacity(self.replicas.len() + 1);
+        if !self.replicas.is_empty() {
+            let start = self.next.fetch_add(1, Ordering::Relaxed) % self.replicas.len();
+            pools.extend(self.replicas[start..].iter());
+            pools.extend(self.replicas[..start].iter());
+        };
+        pools.push(&self.primary);
+        pools
+    }
+}
+
+fn pool_builder<M>(pool_config: PoolConfig) -> r2d2::Builder<M>
     where M: ManageConnection
 {
-    let builder = Pool::<M>::builder();
-    builder
+    Pool::<M>::builder()
+        .min_idle(Some(pool_config.min_size.into()))
+        .max_size(pool_config.max_size.into())
+        .connection_timeout(pool_config.connection_timeout)
+        .idle_timeout(pool_config.idle_timeout)
+        .max_lifetime(pool_config.max_lifetime)
+        .test_on_check_out(pool_config.test_on_check_out)
+}
+
+fn async_pool_builder<M>(pool_config: PoolConfig) -> bb8::Builder<M>
+    where M: bb8::ManageConnection
+{
+    bb8::Pool::<M>::builder()
         .min_idle(Some(pool_config.min_size.into()))
         .max_size(pool_config.max_size.into())
-        .build(manager).map_err(|e| {
-        let reason = e.to_string();
+        .connection_timeout(pool_config.connection_timeout)
+        .idle_timeout(pool_config.idle_timeout)
+        .max_lifetime(pool_config.max_lifetime)
+        .test_on_check_out(pool_config.test_on_check_out)
+}
+
+pub(crate) fn create_pool<M>(manager: M, pool_config: PoolConfig) -> Result<Pool<M>, Error>
+    where M: ManageConnection
+{
+    pool_builder(pool_config).build(manager).map_err(|e| {
         let msg = "Unable to create pool".to_string();
-        PoolCreationFailed(msg, reason).into()
+        PoolCreationFailed(msg, e.into()).into()
     })
-}
\ No newline at end of file
+}
+
+/// Does not wait for the first connections, so an unreachable database only fails on `get`
+pub(crate) fn create_lazy_pool<M>(manager: M, pool_config: PoolConfig) -> Pool<M>
+    where M: ManageConnection
+{
+    pool_builder(pool_config).build_unchecked(manager)
+}
+
+pub(crate) async fn create_async_pool<M>(manager: M, pool_config: PoolConfig) -> Result<bb8::Pool<M>, Error>
+    where M: bb8::ManageConnection, M::Error: std::fmt::Display
+{
+    async_pool_builder(pool_config).build(manager).await.map_err(|e| {
+        let msg = "Unable to create pool".to_string();
+        PoolCreationFailed(msg, failure::err_msg(e.to_string())).into()
+    })
+}
+
+/// Does not wait for the first connections, so an unreachable database only fails on `get`
+pub(crate) fn create_lazy_async_pool<M>(manager: M, pool_config: PoolConfig) -> bb8::Pool<M>
+    where M: bb8::ManageConnection
+{
+    async_pool_builder(pool_config).build_unchecked(manager)
+}
+
+#[cfg(test)]
+mod db_pool {
+    use super::*;
+
+    #[test]
+    fn wait_guard_validation() {
+        let waiting = AtomicU32::new(0);
+        {
+            let _first = WaitGuard::new(&waiting);
+            let _second = WaitGuard::new(&waiting);
+            assert_eq!(waiting.load(Ordering::SeqCst), 2);
+        }
+        assert_eq!(waiting.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn read_order_validation() {
+        let pools = ReplicatedPool::new("primary", vec!["replica-1", "replica-2"]);
+        assert_eq!(pools.read_order(), vec![&"replica-1", &"replica-2", &"primary"]);
+        assert_eq!(pools.read_order(), vec![&"replica-2", &"replica-1", &"primary"]);
+        assert_eq!(pools.read_order(), vec![&"replica-1", &"replica-2", &"primary"]);
+
+        let pools = ReplicatedPool::new("primary", vec![]);
+        assert_eq!(pools.read_order(), vec![&"primary"]);
+    }
+
+    #[test]
+    fn pool_status_validation() {
+        let status = PoolStatus::new(16, 4, 3, 0);
+        assert_eq!(status.in_use, 1);
+    }
+}
@@ -1,9 +1,153 @@
-use failure::Fail;
+use failure::{Error, Fail};
+
+use actix_http::{Response, ResponseError};
+use actix_http::http::StatusCode;
+use tokio_postgres::error::SqlState;
+
+use crate::errors::ErrorBody;
 
 #[derive(Debug, Fail)]
 pub enum DatabaseErrors {
     #[fail(display = "{}. Reason: {}", 0, 1)]
-    ConnectionFailed(String, String),
+    ConnectionFailed(String, #[cause] Error),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    ConnectionAttemptFailed(String, #[cause] Error),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    ConnectionRetriesExhausted(String, #[cause] Error),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    PoolCreationFailed(String, #[cause] Error),
+    /// Every connection stayed checked out for the whole connection timeout
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    PoolExhausted(String, #[cause] Error),
+    /// A read replica could not hand out a connection, the read moves on to the next pool
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    ReplicaUnavailable(String, #[cause] Error),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    TlsConfigurationFailed(String, #[cause] Error),
     #[fail(display = "{}. Reason: {}", 0, 1)]
-    PoolCreationFailed(String, String),
+    MigrationFailed(String, #[cause] Error),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    MigrationMismatch(String, String),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    UniqueViolation(String, #[cause] Error),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    NotFound(String, String),
+    /// The row refers to one that does not exist, e.g. a role assigned to a missing user
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    ConstraintViolation(String, #[cause] Error),
+    /// The statement was cancelled, e.g. by `statement_timeout`, or the database was busy for too long
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    Timeout(String, #[cause] Error),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    QueryFailed(String, #[cause] Error),
+}
+
+impl DatabaseErrors {
+    /// Classify a failed postgres statement, keeping the driver error as the cause
+    pub fn from_postgres(msg: String, e: tokio_postgres::Error) -> Self {
+        if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+            return DatabaseErrors::UniqueViolation(msg, e.into());
+        };
+        if e.code() == Some(&SqlState::QUERY_CANCELED) {
+            return DatabaseErrors::Timeout(msg, e.into());
+        };
+        if e.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+            return DatabaseErrors::ConstraintViolation(msg, e.into());
+        };
+        DatabaseErrors::QueryFailed(msg, e.into())
+    }
+
+    /// Classify a failed sqlite statement, keeping the driver error as the cause
+    #[cfg(feature = "sqlite")]
+    pub fn from_sqlite(msg: String, e: rusqlite::Error) -> Self {
+        use rusqlite::ErrorCode;
+
+        match &e {
+            rusqlite::Error::QueryReturnedNoRows => DatabaseErrors::NotFound(msg, e.to_string()),
+            rusqlite::Error::SqliteFailure(f, _) if f.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY => {
+                DatabaseErrors::ConstraintViolation(msg, e.into())
+            }
+            rusqlite::Error::SqliteFailure(f, _) if f.code == ErrorCode::ConstraintViolation => {
+                DatabaseErrors::UniqueViolation(msg, e.into())
+            }
+            rusqlite::Error::SqliteFailure(f, _) if f.code == ErrorCode::DatabaseBusy || f.code == ErrorCode::DatabaseLocked => {
+                DatabaseErrors::Timeout(msg, e.into())
+            }
+            _ => DatabaseErrors::QueryFailed(msg, e.into())
+        }
+    }
+
+    /// Stable identifier used in JSON error bodies
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DatabaseErrors::ConnectionFailed(_, _) => "connection_failed",
+            DatabaseErrors::ConnectionAttemptFailed(_, _) => "connection_attempt_failed",
+            DatabaseErrors::ConnectionRetriesExhausted(_, _) => "connection_retries_exhausted",
+            DatabaseErrors::PoolCreationFailed(_, _) => "pool_creation_failed",
+            DatabaseErrors::PoolExhausted(_, _) => "pool_exhausted",
+            DatabaseErrors::ReplicaUnavailable(_, _) => "replica_unavailable",
+            DatabaseErrors::TlsConfigurationFailed(_, _) => "tls_configuration_failed",
+            DatabaseErrors::MigrationFailed(_, _) => "migration_failed",
+            DatabaseErrors::MigrationMismatch(_, _) => "migration_mismatch",
+            DatabaseErrors::UniqueViolation(_, _) => "unique_violation",
+            DatabaseErrors::NotFound(_, _) => "not_found",
+            DatabaseErrors::ConstraintViolation(_, _) => "constraint_violation",
+            DatabaseErrors::Timeout(_, _) => "timeout",
+            DatabaseErrors::QueryFailed(_, _) => "query_failed",
+        }
+    }
+}
+
+impl ResponseError for DatabaseErrors {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DatabaseErrors::UniqueViolation(_, _) => StatusCode::CONFLICT,
+            DatabaseErrors::NotFound(_, _) => StatusCode::NOT_FOUND,
+            DatabaseErrors::ConstraintViolation(_, _) => StatusCode::CONFLICT,
+            DatabaseErrors::Timeout(_, _) => StatusCode::GATEWAY_TIMEOUT,
+            DatabaseErrors::PoolExhausted(_, _)
+            | DatabaseErrors::ReplicaUnavailable(_, _)
+            | DatabaseErrors::ConnectionFailed(_, _)
+            | DatabaseErrors::ConnectionAttemptFailed(_, _)
+            | DatabaseErrors::ConnectionRetriesExhausted(_, _) => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The details stay in the server log
+    fn error_response(&self) -> Response {
+        eprintln!("{}", self);
+        ErrorBody::response(self.status_code(), self.kind())
+    }
+}
+
+#[cfg(test)]
+mod errors {
+    use super::*;
+
+    #[test]
+    fn status_code_validation() {
+        let cause = failure::err_msg("duplicate key value violates unique constraint");
+        let e = DatabaseErrors::UniqueViolation("Signup failed for user: john_doe".to_string(), cause);
+        assert_eq!(e.status_code(), StatusCode::CONFLICT);
+        assert_eq!(e.cause().unwrap().to_string(), "duplicate key value violates unique constraint");
+
+        let e = DatabaseErrors::NotFound("Lookup failed".to_string(), "missing".to_string());
+        assert_eq!(e.status_code(), StatusCode::NOT_FOUND);
+
+        let e = DatabaseErrors::PoolExhausted("No connection".to_string(), failure::err_msg("4 of 4 connections in use"));
+        assert_eq!(e.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(e.cause().unwrap().to_string(), "4 of 4 connections in use");
+
+        let e = DatabaseErrors::MigrationMismatch("Schema".to_string(), "ahead".to_string());
+        assert_eq!(e.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn constraint_violation_validation() {
+        let cause = failure::err_msg("insert or update on table \"tbl_user_roles\" violates foreign key constraint");
+        let e = DatabaseErrors::ConstraintViolation("Unable to assign role: admin to user: john_doe".to_string(), cause);
+        assert_eq!(e.status_code(), StatusCode::CONFLICT);
+        assert_eq!(e.kind(), "constraint_violation");
+    }
 }
@@ -0,0 +1,381 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use jwtvault::prelude::*;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::database::users_setup::UserRepository;
+use crate::database::profiles::ProfileRepository;
+use crate::database::roles::RoleRepository;
+use crate::database::errors::DatabaseErrors;
+use crate::errors::FixtureErrors::{UnreadableFile, UnsupportedFormat, InvalidFile};
+
+/// Used when `USERS_FIXTURES` is not set
+pub const DEFAULT_FIXTURES_PATH: &str = "fixtures/users.json";
+
+/// Separates roles in the `roles` column of a CSV fixture
+const CSV_ROLE_SEPARATOR: char = ';';
+
+/// Columns of a CSV fixture
+const CSV_COLUMNS: &[&str] = &["user", "password", "roles", "display_name", "tenant"];
+
+/// A user to seed, with the plaintext password
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct UserFixture {
+    /// 1 based position in the file, header excluded
+    #[serde(skip)]
+    pub row: usize,
+    pub user: String,
+    pub password: String,
+    /// Assigned to the user, created if missing
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub profile: ProfileFixture,
+}
+
+/// Profile fields a fixture can set, see `profiles::ProfileRepository`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileFixture {
+    pub display_name: Option<String>,
+    pub tenant: Option<String>,
+}
+
+impl ProfileFixture {
+    pub fn is_empty(&self) -> bool {
+        self.display_name.is_none() && self.tenant.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowFailure {
+    pub row: usize,
+    pub user: Option<String>,
+    pub reason: String,
+}
+
+impl fmt::Display for RowFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "Row {} (user: {}) Reason: {}", self.row, user, self.reason),
+            None => write!(f, "Row {} Reason: {}", self.row, self.reason),
+        }
+    }
+}
+
+/// Outcome of seeding, one entry per row of the fixtures file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FixtureReport {
+    /// Users created or whose password changed
+    pub seeded: Vec<String>,
+    /// Users whose stored hash already matches the fixture password
+    pub unchanged: Vec<String>,
+    pub failures: Vec<RowFailure>,
+}
+
+impl FixtureReport {
+    pub fn log(&self) {
+        println!("Fixtures: {} seeded, {} unchanged, {} failed", self.seeded.len(), self.unchanged.len(), self.failures.len());
+        for failure in &self.failures {
+            eprintln!("Fixture failed: {}", failure);
+        }
+    }
+}
+
+/// Users read from a JSON or CSV fixtures file
+///
+/// JSON is an array of `{"user", "password", "roles", "profile"}` objects, `roles` and `profile` are optional
+/// and `profile` takes `display_name` and `tenant`.
+/// CSV needs a header with `user` and `password`, and may add `roles` (`;` separated), `display_name` and `tenant`.
+/// Rows that can't be read are kept as failures instead of failing the whole file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fixtures {
+    pub users: Vec<UserFixture>,
+    pub failures: Vec<RowFailure>,
+}
+
+impl Fixtures {
+    /// The format is picked from the extension: `.json` or `.csv`
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let data = fs::read_to_string(path).map_err(|e| {
+            UnreadableFile(format!("Unable to read fixtures: {}", name), e.to_string())
+        })?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Fixtures::from_json(data.as_str()),
+            Some("csv") => Fixtures::from_csv(data.as_str()),
+            _ => {
+                let msg = format!("Unable to load fixtures: {}", name);
+                let reason = "Expected a .json or .csv file".to_string();
+                Err(UnsupportedFormat(msg, reason).into())
+            }
+        }
+    }
+
+    pub fn from_json(data: &str) -> Result<Self, Error> {
+        let rows: Vec<Value> = serde_json::from_str(data).map_err(|e| {
+            InvalidFile("Fixtures must be a JSON array".to_string(), e.to_string())
+        })?;
+        let mut fixtures = Fixtures::default();
+        for (index, value) in rows.into_iter().enumerate() {
+            let user = value.get("user").and_then(|v| v.as_str()).map(|v| v.to_string());
+            match serde_json::from_value::<UserFixture>(value) {
+                Ok(fixture) => fixtures.push(index + 1, fixture),
+                Err(e) => fixtures.fail(index + 1, user, e.to_string()),
+            };
+        }
+        Ok(fixtures)
+    }
+
+    pub fn from_csv(data: &str) -> Result<Self, Error> {
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let headers = reader.headers().map_err(|e| {
+            InvalidFile("Fixtures must start with a CSV header".to_string(), e.to_string())
+        })?.clone();
+        for column in &["user", "password"] {
+            if !headers.iter().any(|h| h == *column) {
+                let msg = "Fixtures must start with a CSV header".to_string();
+                let reason = format!("Missing column: {}", column);
+                return Err(InvalidFile(msg, reason).into());
+            };
+        }
+        if let Some(column) = headers.iter().find(|h| !CSV_COLUMNS.contains(h)) {
+            let msg = "Fixtures must start with a CSV header".to_string();
+            let reason = format!("Unknown column: {}", column);
+            return Err(InvalidFile(msg, reason).into());
+        };
+        let mut fixtures = Fixtures::default();
+        for (index, record) in reader.records().enumerate() {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    fixtures.fail(index + 1, None, e.to_string());
+                    continue;
+                }
+            };
+            let mut fixture = UserFixture {
+                row: 0,
+                user: String::new(),
+                password: String::new(),
+                roles: Vec::new(),
+                profile: ProfileFixture::default(),
+            };
+            for (column, value) in headers.iter().zip(record.iter()) {
+                match column {
+                    "user" => fixture.user = value.to_string(),
+                    "password" => fixture.password = value.to_string(),
+                    "roles" => {
+                        fixture.roles = value.split(CSV_ROLE_SEPARATOR)
+                            .map(|role| role.trim())
+                            .filter(|role| !role.is_empty())
+                            .map(|role| role.to_string())
+                            .collect();
+                    }
+                    "display_name" => fixture.profile.display_name = Some(value.to_string()).filter(|v| !v.is_empty()),
+                    "tenant" => fixture.profile.tenant = Some(value.to_string()).filter(|v| !v.is_empty()),
+                    _ => {}
+                };
+            }
+            fixtures.push(index + 1, fixture);
+        }
+        Ok(fixtures)
+    }
+
+    fn push(&mut self, row: usize, mut fixture: UserFixture) {
+        if fixture.user.is_empty() || fixture.password.is_empty() {
+            let user = Some(fixture.user).filter(|u| !u.is_empty());
+            self.fail(row, user, "user and password are required".to_string());
+            return;
+        };
+        fixture.row = row;
+        self.users.push(fixture);
+    }
+
+    fn fail(&mut self, row: usize, user: Option<String>, reason: String) {
+        self.failures.push(RowFailure { row, user, reason });
+    }
+
+    /// Rows after the first one for a user are reported instead of applied
+    fn distinct(&self, report: &mut FixtureReport) -> Vec<&UserFixture> {
+        let mut seen = HashSet::new();
+        let mut distinct = Vec::new();
+        for fixture in &self.users {
+            if !seen.insert(fixture.user.as_str()) {
+                let reason = "Duplicate user in fixtures".to_string();
+                report.failures.push(RowFailure { row: fixture.row, user: Some(fixture.user.clone()), reason });
+                continue;
+            };
+            distinct.push(fixture);
+        }
+        distinct
+    }
+
+    /// Upsert into an in-memory users map (user -> hash), as used by `DefaultVault` and `LoginInfo`
+    ///
+    /// Only passwords are kept, roles and profiles need `seed_repository`.
+    pub fn seed_map<H, P>(&self, users: &mut HashMap<String, String>, hasher: &P) -> FixtureReport
+        where H: Default, P: PasswordHasher<H>
+    {
+        let mut report = FixtureReport { failures: self.failures.clone(), ..Default::default() };
+        for fixture in self.distinct(&mut report) {
+            let user = fixture.user.as_str();
+            let password = fixture.password.as_str();
+            // A fresh salt would change the hash on every run, keep the one that still matches
+            if let Some(hash) = users.get(user) {
+                if let Ok(true) = hasher.verify_user_password(user, password, hash.as_str()) {
+                    report.unchanged.push(user.to_string());
+                    continue;
+                };
+            };
+            match hasher.hash_user_password(user, password) {
+                Ok(hash) => {
+                    let _ = users.insert(user.to_string(), hash);
+                    report.seeded.push(user.to_string());
+                }
+                Err(e) => report.failures.push(RowFailure { row: fixture.row, user: Some(user.to_string()), reason: e.to_string() }),
+            };
+        }
+        report
+    }
+
+    /// Upsert into a repository; rows are applied one by one and a failing row does not stop the others
+    ///
+    /// Roles are added to the ones the user holds, a profile given in the fixture replaces the stored one.
+    pub async fn seed_repository<U, H, P>(&self, repository: &U, hasher: &P) -> FixtureReport
+        where U: UserRepository + ProfileRepository + RoleRepository, H: Default, P: PasswordHasher<H>
+    {
+        let mut report = FixtureReport { failures: self.failures.clone(), ..Default::default() };
+        for fixture in self.distinct(&mut report) {
+            let user = fixture.user.as_str();
+            match seed_user(repository, hasher, fixture).await {
+                Ok(true) => report.seeded.push(user.to_string()),
+                Ok(false) => report.unchanged.push(user.to_string()),
+                Err(e) => report.failures.push(RowFailure { row: fixture.row, user: Some(user.to_string()), reason: e.to_string() }),
+            };
+        }
+        report
+    }
+}
+
+/// Returns whether anything was written
+async fn seed_user<U, H, P>(repository: &U, hasher: &P, fixture: &UserFixture) -> Result<bool, Error>
+    where U: UserRepository + ProfileRepository + RoleRepository, H: Default, P: PasswordHasher<H>
+{
+    let mut seeded = seed_password(repository, hasher, fixture).await?;
+    let user = fixture.user.as_str();
+    let profile = repository.resolve_profile(user).await?;
+    let stored = ProfileFixture { display_name: profile.display_name, tenant: profile.tenant };
+    if !fixture.profile.is_empty() && fixture.profile != stored {
+        let display_name = fixture.profile.display_name.as_ref().map(|v| v.as_str());
+        let tenant = fixture.profile.tenant.as_ref().map(|v| v.as_str());
+        repository.update_profile(user, display_name, tenant).await?;
+        seeded = true;
+    };
+    for role in fixture.roles.iter().filter(|role| !profile.roles.contains(*role)) {
+        repository.create_role(role).await?;
+        repository.assign_role(user, role).await?;
+        seeded = true;
+    }
+    Ok(seeded)
+}
+
+/// Returns whether the user was created or its password changed
+async fn seed_password<U, H, P>(repository: &U, hasher: &P, fixture: &UserFixture) -> Result<bool, Error>
+    where U: UserRepository, H: Default, P: PasswordHasher<H>
+{
+    let user = fixture.user.as_str();
+    let password = fixture.password.as_str();
+    let account = repository.resolve_account(user).await;
+    if let Err(e) = account {
+        if let Some(DatabaseErrors::NotFound(_, _)) = e.downcast_ref::<DatabaseErrors>() {
+            let hash = hasher.hash_user_password(user, password)?;
+            repository.signup_user(user, hash.as_str()).await?;
+            return Ok(true);
+        };
+        return Err(e);
+    };
+    let account = account.ok().unwrap();
+    if hasher.verify_user_password(user, password, account.password.as_str())? {
+        return Ok(false);
+    };
+    let hash = hasher.hash_user_password(user, password)?;
+    repository.update_password(user, account.password.as_str(), hash.as_str()).await
+}
+
+#[cfg(test)]
+mod fixtures {
+    use super::*;
+
+    struct PlainHasher;
+
+    impl PasswordHasher<String> for PlainHasher {
+        fn hash_user_password<T: AsRef<str>>(&self, _: T, password: T) -> Result<String, Error> {
+            Ok(format!("hashed:{}", password.as_ref()))
+        }
+
+        fn verify_user_password<T: AsRef<str>>(&self, _: T, password: T, hash: T) -> Result<bool, Error> {
+            Ok(hash.as_ref() == format!("hashed:{}", password.as_ref()))
+        }
+    }
+
+    #[test]
+    fn from_json_validation() {
+        let data = r#"[
+            {"user": "john_doe", "password": "john", "roles": ["admin"], "profile": {"display_name": "John Doe"}},
+            {"user": "jane_doe"},
+            {"user": "", "password": "nobody"},
+            {"user": "james_doe", "password": "james"},
+            {"user": "jack_doe", "password": "jack", "profile": {"email": "jack.doe@example.com"}}
+        ]"#;
+        let fixtures = Fixtures::from_json(data).unwrap();
+        assert_eq!(fixtures.users.len(), 2);
+        assert_eq!(fixtures.users[0].roles, vec!["admin".to_string()]);
+        assert_eq!(fixtures.users[0].profile.display_name, Some("John Doe".to_string()));
+        assert_eq!(fixtures.users[1].row, 4);
+        assert!(fixtures.users[1].roles.is_empty());
+        assert!(fixtures.users[1].profile.is_empty());
+        let failed: Vec<usize> = fixtures.failures.iter().map(|f| f.row).collect();
+        assert_eq!(failed, vec![2, 3, 5]);
+        assert_eq!(fixtures.failures[0].user, Some("jane_doe".to_string()));
+
+        assert!(Fixtures::from_json("{\"user\": \"john_doe\"}").is_err());
+    }
+
+    #[test]
+    fn from_csv_validation() {
+        let data = "user,password,roles,display_name,tenant\njohn_doe,john,admin;user,John Doe,\njane_doe,,,,\n";
+        let fixtures = Fixtures::from_csv(data).unwrap();
+        assert_eq!(fixtures.users.len(), 1);
+        assert_eq!(fixtures.users[0].roles, vec!["admin".to_string(), "user".to_string()]);
+        assert_eq!(fixtures.users[0].profile.display_name, Some("John Doe".to_string()));
+        assert_eq!(fixtures.users[0].profile.tenant, None);
+        assert_eq!(fixtures.failures[0].row, 2);
+
+        assert!(Fixtures::from_csv("name,password\njohn_doe,john\n").is_err());
+        assert!(Fixtures::from_csv("user,password,email\njohn_doe,john,john.doe@example.com\n").is_err());
+    }
+
+    #[test]
+    fn seed_map_is_idempotent() {
+        let data = r#"[
+            {"user": "john_doe", "password": "john"},
+            {"user": "jane_doe", "password": "jane"},
+            {"user": "john_doe", "password": "other"}
+        ]"#;
+        let fixtures = Fixtures::from_json(data).unwrap();
+        let mut users = HashMap::new();
+        let report = fixtures.seed_map(&mut users, &PlainHasher);
+        assert_eq!(report.seeded, vec!["john_doe".to_string(), "jane_doe".to_string()]);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(users.get("john_doe"), Some(&"hashed:john".to_string()));
+
+        let report = fixtures.seed_map(&mut users, &PlainHasher);
+        assert!(report.seeded.is_empty());
+        assert_eq!(report.unchanged.len(), 2);
+    }
+}
@@ -0,0 +1,110 @@
+use failure::Error;
+
+use jwtvault::prelude::*;
+
+use crate::database::tokio_pool::TokioPool;
+
+use crate::database::errors::DatabaseErrors::{self, MigrationFailed, MigrationMismatch};
+
+// Serialises concurrent `migrate` calls across instances
+const MIGRATION_LOCK_ID: i64 = 0x6a77_7476_6175_6c74;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Ordered by version; never edit a released migration, add a new one instead
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "create_tbl_users", sql: include_str!("migrations/V1__create_tbl_users.sql") },
+    Migration { version: 2, name: "create_tbl_sessions", sql: include_str!("migrations/V2__create_tbl_sessions.sql") },
+    Migration { version: 3, name: "add_account_state_to_tbl_users", sql: include_str!("migrations/V3__add_account_state_to_tbl_users.sql") },
+    Migration { version: 4, name: "add_profiles_and_roles", sql: include_str!("migrations/V4__add_profiles_and_roles.sql") },
+    Migration { version: 5, name: "add_roles_and_permissions", sql: include_str!("migrations/V5__add_roles_and_permissions.sql") },
+];
+
+/// Latest schema version known to this binary
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Migrations still to be applied given the versions already recorded in the database
+pub fn pending<'a>(migrations: &'a [Migration], applied: &[i32]) -> Result<Vec<&'a Migration>, Error> {
+    let latest = migrations.last().map_or(0, |m| m.version);
+    if let Some(ahead) = applied.iter().filter(|v| **v > latest).max() {
+        let msg = format!("Database schema version: {} Binary schema version: {}", ahead, latest);
+        let reason = "Database is ahead of the binary".to_string();
+        return Err(MigrationMismatch(msg, reason).into());
+    };
+    let pending = migrations.iter().filter(|m| !applied.contains(&m.version)).collect();
+    Ok(pending)
+}
+
+/// Apply pending migrations in a single transaction and return the versions applied
+pub async fn migrate(pool: &TokioPool) -> Result<Vec<i32>, Error> {
+    let failed = |e| DatabaseErrors::from_postgres("Unable to apply migrations".to_string(), e);
+    let mut conn = pool.get().await?;
+    let transaction = conn.transaction().await.map_err(failed)?;
+    transaction.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_ID]).await.map_err(failed)?;
+    transaction.batch_execute(
+        "CREATE TABLE IF NOT EXISTS tbl_schema_migrations ( \
+            version INTEGER NOT NULL, \
+            name VARCHAR(256) NOT NULL, \
+            applied_at BIGINT NOT NULL, \
+            PRIMARY KEY (version) \
+        )"
+    ).await.map_err(failed)?;
+
+    let rs = transaction.query("SELECT version FROM tbl_schema_migrations", &[]).await.map_err(failed)?;
+    let applied: Vec<i32> = rs.iter().map(|row| row.get(0)).collect();
+
+    let mut versions = Vec::new();
+    for migration in pending(MIGRATIONS, applied.as_slice())? {
+        transaction.batch_execute(migration.sql).await.map_err(|e| {
+            let msg = format!("Migration V{} {} failed", migration.version, migration.name);
+            MigrationFailed(msg, e.into())
+        })?;
+        let applied_at = compute_timestamp_in_seconds();
+        transaction.execute(
+            "INSERT INTO tbl_schema_migrations (version, name, applied_at) VALUES ($1, $2, $3)",
+            &[&migration.version, &migration.name, &applied_at],
+        ).await.map_err(failed)?;
+        versions.push(migration.version);
+    }
+    transaction.commit().await.map_err(failed)?;
+    Ok(versions)
+}
+
+#[cfg(test)]
+mod migrations {
+    use super::*;
+
+    #[test]
+    fn migrations_are_ordered() {
+        let versions: Vec<i32> = MIGRATIONS.iter().map(|m| m.version).collect();
+        let expected: Vec<i32> = (1..=MIGRATIONS.len() as i32).collect();
+        assert_eq!(versions, expected);
+        assert_eq!(latest_version(), MIGRATIONS.len() as i32);
+    }
+
+    #[test]
+    fn pending_validation() {
+        let computed: Vec<i32> = pending(MIGRATIONS, &[]).unwrap().iter().map(|m| m.version).collect();
+        assert_eq!(computed, vec![1, 2, 3, 4, 5]);
+
+        let computed: Vec<i32> = pending(MIGRATIONS, &[1]).unwrap().iter().map(|m| m.version).collect();
+        assert_eq!(computed, vec![2, 3, 4, 5]);
+
+        let computed = pending(MIGRATIONS, &[1, 2, 3, 4, 5]).unwrap();
+        assert!(computed.is_empty());
+    }
+
+    #[test]
+    fn pending_refuses_newer_database() {
+        let ahead = latest_version() + 1;
+        let result = pending(MIGRATIONS, &[1, 2, 3, 4, 5, ahead]);
+        assert!(result.is_err());
+    }
+}
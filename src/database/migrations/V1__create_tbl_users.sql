@@ -1,6 +1,4 @@
-DROP TABLE IF EXISTS tbl_users;
-
-CREATE TABLE tbl_users (
+CREATE TABLE IF NOT EXISTS tbl_users (
 
     -- ##################
     -- Column definitions
@@ -9,4 +7,4 @@ CREATE TABLE tbl_users (
     user_id VARCHAR(512) NOT NULL,
     user_password VARCHAR(512) NOT NULL,
     PRIMARY KEY (user_id)
-);
\ No newline at end of file
+);
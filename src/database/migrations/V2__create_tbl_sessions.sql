@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS tbl_sessions (
+
+    -- ##################
+    -- Column definitions
+    -- ##################
+
+    session_key BIGINT NOT NULL,
+    session_value TEXT NOT NULL,
+    -- seconds since epoch, NULL never expires
+    expires_at BIGINT NULL,
+    PRIMARY KEY (session_key)
+);
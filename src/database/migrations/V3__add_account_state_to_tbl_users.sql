@@ -0,0 +1,15 @@
+ALTER TABLE tbl_users
+
+    -- ##################
+    -- Column definitions
+    -- ##################
+
+    ADD COLUMN IF NOT EXISTS disabled BOOLEAN NOT NULL DEFAULT FALSE,
+    -- set once failed_attempts reaches the lockout threshold, cleared by an unlock
+    ADD COLUMN IF NOT EXISTS locked BOOLEAN NOT NULL DEFAULT FALSE,
+    -- consecutive failed logins, reset by a successful one
+    ADD COLUMN IF NOT EXISTS failed_attempts INTEGER NOT NULL DEFAULT 0,
+    -- seconds since epoch; existing users are stamped with the migration time
+    ADD COLUMN IF NOT EXISTS created_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH FROM NOW())::BIGINT,
+    -- seconds since epoch, NULL until the first successful login
+    ADD COLUMN IF NOT EXISTS last_login BIGINT NULL;
@@ -0,0 +1,20 @@
+ALTER TABLE tbl_users
+
+    -- ##################
+    -- Column definitions
+    -- ##################
+
+    -- shown to the user, sent to the client with the authentication token
+    ADD COLUMN IF NOT EXISTS display_name VARCHAR(256) NULL,
+    ADD COLUMN IF NOT EXISTS tenant VARCHAR(256) NULL;
+
+CREATE TABLE IF NOT EXISTS tbl_user_roles (
+
+    -- ##################
+    -- Column definitions
+    -- ##################
+
+    user_id VARCHAR(512) NOT NULL REFERENCES tbl_users (user_id) ON DELETE CASCADE,
+    role_name VARCHAR(256) NOT NULL,
+    PRIMARY KEY (user_id, role_name)
+);
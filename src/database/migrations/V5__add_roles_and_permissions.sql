@@ -0,0 +1,36 @@
+CREATE TABLE IF NOT EXISTS tbl_roles (
+
+    -- ##################
+    -- Column definitions
+    -- ##################
+
+    role_name VARCHAR(256) NOT NULL,
+    PRIMARY KEY (role_name)
+);
+
+-- roles already assigned in tbl_user_roles
+INSERT INTO tbl_roles (role_name)
+    SELECT DISTINCT role_name FROM tbl_user_roles
+    ON CONFLICT DO NOTHING;
+
+ALTER TABLE tbl_user_roles
+    ADD CONSTRAINT fk_user_roles_role_name FOREIGN KEY (role_name) REFERENCES tbl_roles (role_name) ON DELETE CASCADE;
+
+CREATE TABLE IF NOT EXISTS tbl_role_permissions (
+
+    -- ##################
+    -- Column definitions
+    -- ##################
+
+    role_name VARCHAR(256) NOT NULL REFERENCES tbl_roles (role_name) ON DELETE CASCADE,
+    -- checked by routes, e.g. execute
+    permission VARCHAR(256) NOT NULL,
+    PRIMARY KEY (role_name, permission)
+);
+
+-- roles of the fixtures, `user` is also given to users signing up through the webservers
+INSERT INTO tbl_roles (role_name) VALUES ('admin'), ('user')
+    ON CONFLICT DO NOTHING;
+
+INSERT INTO tbl_role_permissions (role_name, permission) VALUES ('admin', 'execute'), ('user', 'execute')
+    ON CONFLICT DO NOTHING;
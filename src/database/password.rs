@@ -0,0 +1,132 @@
+use std::str::FromStr;
+
+use jwtvault::prelude::*;
+use once_cell::sync::OnceCell;
+
+/// Argon2 parameters of an encoded hash, e.g. `$argon2id$v=19$m=4096,t=192,p=8$<salt>$<hash>`
+///
+/// Lanes are left out: argonautica defaults them to the number of cores, so they differ between hosts
+/// without making a hash any weaker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashParams {
+    pub variant: String,
+    pub version: u32,
+    pub memory_size: u32,
+    pub iterations: u32,
+    pub hash_len: u32,
+}
+
+impl HashParams {
+    /// Parameters `hasher` hashes with, read back from a hash of a throwaway password
+    pub fn current<H: Default, P: PasswordHasher<H>>(hasher: &P) -> Result<Self, Error> {
+        let hash = hasher.hash_user_password("probe", "probe")?;
+        hash.parse::<HashParams>().map_err(|reason| {
+            LoginFailed::PasswordHashingFailed("Unable to read the current hash parameters".to_string(), reason).into()
+        })
+    }
+}
+
+/// `HashParams::current` of a hasher, probed on first use
+///
+/// Probing hashes a password, so it is done once rather than on every login.
+#[derive(Debug, Default)]
+pub struct CurrentParams(OnceCell<HashParams>);
+
+impl CurrentParams {
+    pub fn get<H: Default, P: PasswordHasher<H>>(&self, hasher: &P) -> Result<&HashParams, Error> {
+        self.0.get_or_try_init(|| HashParams::current(hasher))
+    }
+}
+
+impl FromStr for HashParams {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid argon2 hash: {}", s);
+        let mut parts = s.split('$').skip(1);
+        let variant = parts.next().ok_or_else(invalid)?;
+        if !variant.starts_with("argon2") {
+            return Err(format!("Unsupported hash algorithm: {}", variant));
+        };
+        let mut part = parts.next().ok_or_else(invalid)?;
+        // Hashes from before argon2 1.3 have no version field
+        let mut version = 0x10;
+        if part.starts_with("v=") {
+            version = part[2..].parse::<u32>().map_err(|_| invalid())?;
+            part = parts.next().ok_or_else(invalid)?;
+        };
+        let (mut memory_size, mut iterations) = (None, None);
+        for param in part.split(',') {
+            let mut kv = param.splitn(2, '=');
+            let key = kv.next().ok_or_else(invalid)?;
+            let value = kv.next().ok_or_else(invalid)?.parse::<u32>().map_err(|_| invalid())?;
+            match key {
+                "m" => memory_size = Some(value),
+                "t" => iterations = Some(value),
+                _ => {}
+            };
+        }
+        let _salt = parts.next().ok_or_else(invalid)?;
+        let hash = parts.next().ok_or_else(invalid)?;
+        Ok(HashParams {
+            variant: variant.to_string(),
+            version,
+            memory_size: memory_size.ok_or_else(invalid)?,
+            iterations: iterations.ok_or_else(invalid)?,
+            // unpadded base64
+            hash_len: (hash.len() * 3 / 4) as u32,
+        })
+    }
+}
+
+/// Whether a stored hash was produced with other parameters (or another algorithm) than `current`
+pub fn needs_rehash(hash: &str, current: &HashParams) -> bool {
+    match hash.parse::<HashParams>() {
+        Ok(params) => &params != current,
+        Err(_) => true
+    }
+}
+
+#[cfg(test)]
+mod password {
+    use super::*;
+
+    const CURRENT: &str = "$argon2id$v=19$m=4096,t=192,p=8$KPMGPmJtqQ5RW6oJpO0hUKFDfEwxxk4pyy4GHz6A9xE$NX9m5GLSQ9lS0gRDzNrAoh7tRZJLJcNvt6mHRBNJYTw";
+
+    #[test]
+    fn hash_params_from_str_validation() {
+        let computed = CURRENT.parse::<HashParams>().unwrap();
+        assert_eq!(computed.variant, "argon2id");
+        assert_eq!(computed.version, 0x13);
+        assert_eq!(computed.memory_size, 4096);
+        assert_eq!(computed.iterations, 192);
+        assert_eq!(computed.hash_len, 32);
+
+        let computed = "$argon2i$m=1024,t=2,p=1$c29tZXNhbHQ$9sTbSlTio3Biev89thdrlKKiCaYsjjYVJxGAL3swxpQ".parse::<HashParams>().unwrap();
+        assert_eq!(computed.variant, "argon2i");
+        assert_eq!(computed.version, 0x10);
+        assert_eq!(computed.memory_size, 1024);
+        assert_eq!(computed.iterations, 2);
+
+        assert!("$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW".parse::<HashParams>().is_err());
+        assert!("plaintext".parse::<HashParams>().is_err());
+    }
+
+    #[test]
+    fn current_params_validation() {
+        let hasher = ArgonPasswordHasher::default();
+        let current = CurrentParams::default();
+        assert_eq!(current.get(&hasher).unwrap(), &CURRENT.parse::<HashParams>().unwrap());
+    }
+
+    #[test]
+    fn needs_rehash_validation() {
+        let current = CURRENT.parse::<HashParams>().unwrap();
+        assert!(!needs_rehash(CURRENT, &current));
+        // lanes are ignored
+        assert!(!needs_rehash(CURRENT.replace("p=8", "p=2").as_str(), &current));
+        assert!(needs_rehash(CURRENT.replace("t=192", "t=96").as_str(), &current));
+        assert!(needs_rehash(CURRENT.replace("argon2id", "argon2i").as_str(), &current));
+        assert!(needs_rehash("plaintext", &current));
+    }
+}
@@ -0,0 +1,138 @@
+use jwtvault::prelude::*;
+use tokio_postgres::Client;
+
+use crate::database::db_pool::ReplicatedPool;
+use crate::database::tokio_pool::TokioPool;
+use crate::database::errors::DatabaseErrors;
+use crate::stores::expiry::{self, StoreUntil};
+use crate::stores::pins::Pins;
+use crate::stores::sweeper::Sweep;
+
+
+/// Session storage backed by `tbl_sessions`
+///
+/// Instances sharing the database share sessions. Writes go to the primary; loads go to a replica and,
+/// when the session is not there yet, to the primary, so a login on one instance is seen by the others right away.
+/// A logout or revoke ends the session everywhere once the replicas caught up.
+/// `Persistence::load` hands out a reference, so values loaded are pinned until the next write or `sweep`.
+/// A failed write is logged and leaves nothing behind; the vault then fails the login (see `vault::ComposableVault`).
+/// Expired rows are never loaded; call `purge_expired` on startup to delete them.
+/// A `clone` shares the pool and starts without pins.
+pub struct PostgresPersistence {
+    pool: ReplicatedPool<TokioPool>,
+    expiry_in_seconds: Option<i64>,
+    pins: Pins,
+}
+
+impl PostgresPersistence {
+    /// `expiry_in_seconds` should match the refresh token expiry; `None` never expires
+    pub fn new(pool: ReplicatedPool<TokioPool>, expiry_in_seconds: Option<i64>) -> Self {
+        Self { pool, expiry_in_seconds, pins: Pins::default() }
+    }
+
+    /// Delete expired sessions, returns how many there were
+    pub async fn purge_expired(&self) -> Result<u64, Error> {
+        let now = compute_timestamp_in_seconds();
+        let failed = |e| DatabaseErrors::from_postgres("Unable to purge expired sessions".to_string(), e);
+        let conn = self.pool.write().await?;
+        let statement = conn.prepare("DELETE FROM tbl_sessions WHERE expires_at <= $1").await.map_err(failed)?;
+        let purged = conn.execute(&statement, &[&now]).await.map_err(failed)?;
+        Ok(purged)
+    }
+
+    async fn write(&self, key: u64, value: &str, expires_at: Option<i64>) -> Result<(), Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Unable to store session: {}", key), e);
+        let conn = self.pool.write().await?;
+        let statement = conn.prepare(
+            "INSERT INTO tbl_sessions (session_key, session_value, expires_at) VALUES ($1, $2, $3) \
+             ON CONFLICT (session_key) DO UPDATE SET session_value = EXCLUDED.session_value, expires_at = EXCLUDED.expires_at"
+        ).await.map_err(failed)?;
+        let _ = conn.execute(&statement, &[&(key as i64), &value, &expires_at]).await.map_err(failed)?;
+        Ok(())
+    }
+
+    /// Read from a replica, then from the primary: a session written a moment ago may not be on the replica yet
+    async fn read(&self, key: u64) -> Result<Option<String>, Error> {
+        let value = Self::select(&*self.pool.read().await?, key).await?;
+        if value.is_some() {
+            return Ok(value);
+        };
+        Self::select(&*self.pool.write().await?, key).await
+    }
+
+    async fn select(conn: &Client, key: u64) -> Result<Option<String>, Error> {
+        let now = compute_timestamp_in_seconds();
+        let failed = |e| DatabaseErrors::from_postgres(format!("Unable to load session: {}", key), e);
+        let statement = conn.prepare(
+            "SELECT session_value FROM tbl_sessions WHERE session_key = $1 AND (expires_at IS NULL OR expires_at > $2)"
+        ).await.map_err(failed)?;
+        let row = conn.query_opt(&statement, &[&(key as i64), &now]).await.map_err(failed)?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    async fn delete(&self, key: u64) -> Result<Option<String>, Error> {
+        let now = compute_timestamp_in_seconds();
+        let failed = |e| DatabaseErrors::from_postgres(format!("Unable to remove session: {}", key), e);
+        let conn = self.pool.write().await?;
+        let statement = conn.prepare(
+            "DELETE FROM tbl_sessions WHERE session_key = $1 \
+             RETURNING session_value, expires_at IS NULL OR expires_at > $2"
+        ).await.map_err(failed)?;
+        let row = conn.query_opt(&statement, &[&(key as i64), &now]).await.map_err(failed)?;
+        // An expired session is gone already
+        Ok(row.filter(|row| row.get::<_, bool>(1)).map(|row| row.get(0)))
+    }
+}
+
+impl Clone for PostgresPersistence {
+    fn clone(&self) -> Self {
+        Self { pool: self.pool.clone(), expiry_in_seconds: self.expiry_in_seconds, pins: Pins::default() }
+    }
+}
+
+#[async_trait]
+impl Persistence for PostgresPersistence {
+    async fn store(&mut self, key: u64, value: String) {
+        let expires_at = expiry::expires_at(self.expiry_in_seconds);
+        self.store_until(key, value, expires_at).await
+    }
+
+    async fn load(&self, key: u64) -> Option<&String> {
+        match self.read(key).await {
+            Ok(value) => value.map(|value| self.pins.pin(key, value)),
+            Err(e) => {
+                eprintln!("{}", e.to_string());
+                None
+            }
+        }
+    }
+
+    async fn remove(&mut self, key: u64) -> Option<String> {
+        self.pins.clear();
+        match self.delete(key).await {
+            Ok(value) => value,
+            Err(e) => {
+                eprintln!("{}", e.to_string());
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StoreUntil for PostgresPersistence {
+    async fn store_until(&mut self, key: u64, value: String, expires_at: Option<i64>) {
+        self.pins.clear();
+        if let Err(e) = self.write(key, value.as_str(), expires_at).await {
+            eprintln!("{}", e.to_string());
+        };
+    }
+}
+
+impl Sweep for PostgresPersistence {
+    /// Expired rows are never loaded, this only lets go of the values pinned by `load`
+    fn sweep(&mut self) -> usize {
+        self.pins.clear();
+        0
+    }
+}
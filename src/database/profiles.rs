@@ -0,0 +1,187 @@
+use jwtvault::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+
+use crate::database::users_setup::PostgresUserRepository;
+use crate::database::errors::DatabaseErrors::{self, NotFound};
+
+/// Session fields, see `session_key`
+pub const DISPLAY_NAME: &str = "profile.display_name";
+pub const TENANT: &str = "profile.tenant";
+pub const ROLES: &str = "profile.roles";
+pub const PERMISSIONS: &str = "profile.permissions";
+
+/// Key of a profile field in a session map; the prefix keeps it apart from the digest of a user
+pub fn session_key(field: &str) -> u64 {
+    digest::<_, DefaultHasher>(field)
+}
+
+/// What is known about a user beyond the password: `display_name` and `tenant` from `tbl_users`,
+/// `roles` from `tbl_user_roles` and the `permissions` those roles grant in `tbl_role_permissions`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UserProfile {
+    pub display_name: Option<String>,
+    pub tenant: Option<String>,
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+}
+
+impl UserProfile {
+    /// The profile `ProfileEnricher` left in a session; fields it did not find are left empty
+    pub fn from_session(client: Option<&HashMap<u64, Vec<u8>>>, server: Option<&HashMap<u64, Vec<u8>>>) -> Self {
+        let text = |map: Option<&HashMap<u64, Vec<u8>>>, field: &str| {
+            map.and_then(|map| map.get(&session_key(field)))
+                .map(|value| String::from_utf8_lossy(value.as_slice()).to_string())
+        };
+        let list = |field: &str| {
+            server.and_then(|map| map.get(&session_key(field)))
+                .and_then(|value| serde_json::from_slice::<Vec<String>>(value.as_slice()).ok())
+                .unwrap_or_default()
+        };
+        Self {
+            display_name: text(client, DISPLAY_NAME),
+            tenant: text(client, TENANT),
+            roles: list(ROLES),
+            permissions: list(PERMISSIONS),
+        }
+    }
+}
+
+/// Storage for user profiles
+#[async_trait]
+pub trait ProfileRepository {
+    /// Fails with `DatabaseErrors::NotFound` if the user does not exist
+    async fn resolve_profile(&self, user: &str) -> Result<UserProfile, Error>;
+    /// Fails with `DatabaseErrors::NotFound` if the user does not exist
+    async fn update_profile(&self, user: &str, display_name: Option<&str>, tenant: Option<&str>) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl ProfileRepository for PostgresUserRepository {
+    async fn resolve_profile(&self, user: &str) -> Result<UserProfile, Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Profile lookup failed for user: {}", user), e);
+        let conn = self.replicated().read().await?;
+        let statement = conn.prepare(
+            "SELECT display_name, tenant, \
+             ARRAY(SELECT role_name FROM tbl_user_roles r WHERE r.user_id = u.user_id ORDER BY role_name), \
+             ARRAY(SELECT DISTINCT p.permission FROM tbl_role_permissions p \
+                   JOIN tbl_user_roles r ON r.role_name = p.role_name WHERE r.user_id = u.user_id ORDER BY p.permission) \
+             FROM tbl_users u WHERE user_id = $1"
+        ).await.map_err(failed)?;
+        let rs = conn.query(&statement, &[&user]).await.map_err(failed)?;
+        if let Some(row) = rs.into_iter().next() {
+            let profile = UserProfile {
+                display_name: row.get(0),
+                tenant: row.get(1),
+                roles: row.get(2),
+                permissions: row.get(3),
+            };
+            return Ok(profile);
+        };
+        let msg = format!("Profile lookup failed for user: {}", user);
+        let reason = "User does not exist".to_string();
+        Err(NotFound(msg, reason).into())
+    }
+
+    async fn update_profile(&self, user: &str, display_name: Option<&str>, tenant: Option<&str>) -> Result<(), Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Profile update failed for user: {}", user), e);
+        let conn = self.replicated().write().await?;
+        let statement = conn.prepare(
+            "UPDATE tbl_users SET display_name = $2, tenant = $3 WHERE user_id = $1"
+        ).await.map_err(failed)?;
+        let updated = conn.execute(&statement, &[&user, &display_name, &tenant]).await.map_err(failed)?;
+        if updated == 0 {
+            let msg = format!("Profile update failed for user: {}", user);
+            let reason = "User does not exist".to_string();
+            return Err(NotFound(msg, reason).into());
+        };
+        Ok(())
+    }
+}
+
+
+/// Adds to the session of a user who just logged in, see `users_setup::RepositoryAuthentication::with_enricher`
+#[async_trait]
+pub trait SessionEnricher {
+    /// `client` goes out with the authentication token and can be read by anyone holding it,
+    /// `server` stays in the vault
+    async fn enrich(&self, user: &str, client: &mut HashMap<u64, Vec<u8>>, server: &mut HashMap<u64, Vec<u8>>) -> Result<(), Error>;
+}
+
+/// Display name and tenant go to the client, roles and permissions stay on the server
+pub struct ProfileEnricher<R> {
+    repository: R,
+}
+
+impl<R> ProfileEnricher<R> {
+    pub fn new(repository: R) -> Self {
+        Self { repository }
+    }
+}
+
+#[async_trait]
+impl<R> SessionEnricher for ProfileEnricher<R>
+    where R: ProfileRepository + Send + Sync {
+    async fn enrich(&self, user: &str, client: &mut HashMap<u64, Vec<u8>>, server: &mut HashMap<u64, Vec<u8>>) -> Result<(), Error> {
+        let profile = self.repository.resolve_profile(user).await?;
+        if let Some(display_name) = profile.display_name {
+            client.insert(session_key(DISPLAY_NAME), display_name.into_bytes());
+        };
+        if let Some(tenant) = profile.tenant {
+            client.insert(session_key(TENANT), tenant.into_bytes());
+        };
+        server.insert(session_key(ROLES), serde_json::to_vec(&profile.roles)?);
+        server.insert(session_key(PERMISSIONS), serde_json::to_vec(&profile.permissions)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod profiles {
+    use super::*;
+
+    struct Profiles(HashMap<String, UserProfile>);
+
+    #[async_trait]
+    impl ProfileRepository for Profiles {
+        async fn resolve_profile(&self, user: &str) -> Result<UserProfile, Error> {
+            self.0.get(user).cloned().ok_or_else(|| NotFound(user.to_string(), "User does not exist".to_string()).into())
+        }
+
+        async fn update_profile(&self, _: &str, _: Option<&str>, _: Option<&str>) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn profile_is_split_between_client_and_server() {
+        let profile = UserProfile {
+            display_name: Some("John Doe".to_string()),
+            tenant: Some("acme".to_string()),
+            roles: vec!["admin".to_string(), "auditor".to_string()],
+            permissions: vec!["execute".to_string()],
+        };
+        let mut profiles = HashMap::new();
+        profiles.insert("john_doe".to_string(), profile.clone());
+        profiles.insert("jane_doe".to_string(), UserProfile::default());
+        let enricher = ProfileEnricher::new(Profiles(profiles));
+
+        let (mut client, mut server) = (HashMap::new(), HashMap::new());
+        block_on(enricher.enrich("john_doe", &mut client, &mut server)).unwrap();
+        assert_eq!(client.len(), 2);
+        assert!(!server.contains_key(&session_key(DISPLAY_NAME)));
+        assert!(!client.contains_key(&session_key(ROLES)));
+        assert_eq!(UserProfile::from_session(Some(&client), Some(&server)), profile);
+        let public = UserProfile::from_session(Some(&client), None);
+        assert!(public.roles.is_empty());
+        assert!(public.permissions.is_empty());
+
+        let (mut client, mut server) = (HashMap::new(), HashMap::new());
+        block_on(enricher.enrich("jane_doe", &mut client, &mut server)).unwrap();
+        assert!(client.is_empty());
+        assert_eq!(UserProfile::from_session(None, Some(&server)), UserProfile::default());
+
+        assert!(block_on(enricher.enrich("nobody", &mut client, &mut server)).is_err());
+    }
+}
@@ -1,26 +1,101 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
 use failure::Error;
 
-use postgres::NoTls;
 use postgres::Config;
+use postgres_openssl::MakeTlsConnector;
 
-use r2d2::Pool;
+use r2d2::{Pool, PooledConnection};
 use r2d2_postgres::PostgresConnectionManager;
 
 use crate::database::db_common::{ConnectionConfig, PoolConfig};
-use crate::database::db_pool::create_pool;
+use crate::database::db_pool::{create_pool, PoolStatus, WaitGuard};
+use crate::database::tls::tls_connector;
+use crate::database::errors::DatabaseErrors::PoolExhausted;
+
+
+/// r2d2 pool that keeps track of callers waiting for a connection
+#[derive(Clone)]
+pub struct R2D2Pool {
+    pool: Pool<PostgresConnectionManager<MakeTlsConnector>>,
+    waiting: Arc<AtomicU32>,
+}
 
+impl R2D2Pool {
+    pub fn get(&self) -> Result<PooledConnection<PostgresConnectionManager<MakeTlsConnector>>, Error> {
+        let _waiting = WaitGuard::new(&self.waiting);
+        // r2d2 only gives up on `get` once the connection timeout has passed
+        self.pool.get().map_err(|e| {
+            let status = self.status();
+            let msg = format!("Timed out waiting for a connection, {} of {} in use", status.in_use, status.max_size);
+            PoolExhausted(msg, e.into()).into()
+        })
+    }
 
-pub(crate) fn manager(conn_config: ConnectionConfig) -> PostgresConnectionManager<NoTls> {
+    pub fn status(&self) -> PoolStatus {
+        let state = self.pool.state();
+        let waiting = self.waiting.load(Ordering::SeqCst);
+        PoolStatus::new(self.pool.max_size(), state.connections, state.idle_connections, waiting)
+    }
+}
+
+pub(crate) fn manager(conn_config: ConnectionConfig) -> Result<PostgresConnectionManager<MakeTlsConnector>, Error> {
+    let tls_connector = tls_connector(&conn_config)?;
     let conn_config = Config::from(conn_config);
-    let tls_connector = postgres::NoTls;
-    let r2d2_manager = PostgresConnectionManager::<NoTls>::new(
+    let r2d2_manager = PostgresConnectionManager::<MakeTlsConnector>::new(
         conn_config,
         tls_connector,
     );
-    r2d2_manager
+    Ok(r2d2_manager)
+}
+
+pub fn create_r2d2_pool(conn_config: ConnectionConfig, pool_config: PoolConfig) -> Result<R2D2Pool, Error> {
+    let manager = manager(conn_config)?;
+    let pool = create_pool(manager, pool_config)?;
+    let waiting = Arc::new(AtomicU32::new(0));
+    Ok(R2D2Pool { pool, waiting })
 }
 
-pub fn create_r2d2_pool(conn_config: ConnectionConfig, pool_config: PoolConfig) -> Result<Pool<PostgresConnectionManager<NoTls>>, Error> {
-    let manager = manager(conn_config);
-    create_pool(manager, pool_config)
-}
\ No newline at end of file
+#[cfg(test)]
+mod r2d2_pool {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use crate::database::db_common::SslMode;
+    use crate::database::errors::DatabaseErrors;
+
+    fn conn_config(ssl_mode: SslMode, ssl_root_cert: Option<String>, ssl_cert: Option<String>, ssl_key: Option<String>) -> ConnectionConfig {
+        ConnectionConfig::new("127.0.0.1".to_string(), 5432, "jwtvault".to_string(), "postgres".to_string(), "postgres".to_string())
+            .with_ssl(ssl_mode, ssl_root_cert, ssl_cert, ssl_key)
+    }
+
+    fn assert_tls_failure(result: Result<R2D2Pool, Error>) {
+        let e = result.err().unwrap();
+        match e.downcast_ref::<DatabaseErrors>() {
+            Some(DatabaseErrors::TlsConfigurationFailed(_, _)) => {}
+            _ => panic!("Expected TlsConfigurationFailed, got: {}", e),
+        };
+    }
+
+    #[test]
+    fn tls_manager_validation() {
+        // Building the manager does not connect
+        assert!(manager(conn_config(SslMode::Disable, None, None, None)).is_ok());
+        assert!(manager(conn_config(SslMode::Require, None, None, None)).is_ok());
+
+        // A bad certificate fails the pool before any connection is attempted
+        let dir = env::temp_dir().join(format!("jwtvault-r2d2-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let garbage = dir.join("garbage.pem");
+        fs::write(&garbage, "not a certificate").unwrap();
+        let garbage = garbage.to_str().unwrap().to_string();
+        let missing = dir.join("missing.pem").to_str().unwrap().to_string();
+        let pool_config = PoolConfig::new(0, 1);
+
+        assert_tls_failure(create_r2d2_pool(conn_config(SslMode::VerifyFull, Some(missing.clone()), None, None), pool_config.clone()));
+        assert_tls_failure(create_r2d2_pool(conn_config(SslMode::VerifyCa, Some(garbage.clone()), None, None), pool_config.clone()));
+        assert_tls_failure(create_r2d2_pool(conn_config(SslMode::Require, None, Some(garbage), Some(missing)), pool_config));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use jwtvault::prelude::*;
+
+use crate::database::users_setup::PostgresUserRepository;
+use crate::database::errors::DatabaseErrors;
+
+/// Given to users signing up through the webservers, granted `execute` by migration V5
+pub const SIGNUP_ROLE: &str = "user";
+
+/// Role assignment and the permissions each role grants
+///
+/// Sessions carry the permissions of a user as of login (see `profiles::ProfileEnricher`),
+/// so changes apply from the next login.
+#[async_trait]
+pub trait RoleRepository {
+    /// Does nothing if the role exists
+    async fn create_role(&self, role: &str) -> Result<(), Error>;
+    /// Also removes it from every user
+    async fn delete_role(&self, role: &str) -> Result<bool, Error>;
+    /// Creates the role if needed; does nothing if it already grants `permission`
+    async fn grant_permission(&self, role: &str, permission: &str) -> Result<(), Error>;
+    async fn revoke_permission(&self, role: &str, permission: &str) -> Result<bool, Error>;
+    /// Fails with `DatabaseErrors::ConstraintViolation` if the user or the role does not exist
+    async fn assign_role(&self, user: &str, role: &str) -> Result<(), Error>;
+    async fn revoke_role(&self, user: &str, role: &str) -> Result<bool, Error>;
+    /// Sorted
+    async fn resolve_roles(&self, user: &str) -> Result<Vec<String>, Error>;
+    /// Granted by any of the user's roles, sorted
+    async fn resolve_permissions(&self, user: &str) -> Result<Vec<String>, Error>;
+}
+
+impl PostgresUserRepository {
+    /// Run a write on roles, returning the rows changed
+    async fn update_roles(&self, msg: String, sql: &str, params: &[&str]) -> Result<u64, Error> {
+        let conn = self.replicated().write().await?;
+        let statement = conn.prepare(sql).await
+            .map_err(|e| DatabaseErrors::from_postgres(msg.clone(), e))?;
+        let params = params.iter().map(|p| p as &(dyn tokio_postgres::types::ToSql + Sync)).collect::<Vec<_>>();
+        let updated = conn.execute(&statement, params.as_slice()).await
+            .map_err(|e| DatabaseErrors::from_postgres(msg, e))?;
+        Ok(updated)
+    }
+
+    /// Run a lookup returning a single text column
+    async fn resolve_names(&self, msg: String, sql: &str, user: &str) -> Result<Vec<String>, Error> {
+        let conn = self.replicated().read().await?;
+        let statement = conn.prepare(sql).await
+            .map_err(|e| DatabaseErrors::from_postgres(msg.clone(), e))?;
+        let rs = conn.query(&statement, &[&user]).await
+            .map_err(|e| DatabaseErrors::from_postgres(msg, e))?;
+        Ok(rs.into_iter().map(|row| row.get(0)).collect())
+    }
+}
+
+#[async_trait]
+impl RoleRepository for PostgresUserRepository {
+    async fn create_role(&self, role: &str) -> Result<(), Error> {
+        let _ = self.update_roles(
+            format!("Unable to create role: {}", role),
+            "INSERT INTO tbl_roles (role_name) VALUES ($1) ON CONFLICT DO NOTHING",
+            &[role],
+        ).await?;
+        Ok(())
+    }
+
+    async fn delete_role(&self, role: &str) -> Result<bool, Error> {
+        let deleted = self.update_roles(
+            format!("Unable to delete role: {}", role),
+            "DELETE FROM tbl_roles WHERE role_name = $1",
+            &[role],
+        ).await?;
+        Ok(deleted == 1)
+    }
+
+    async fn grant_permission(&self, role: &str, permission: &str) -> Result<(), Error> {
+        self.create_role(role).await?;
+        let _ = self.update_roles(
+            format!("Unable to grant permission: {} to role: {}", permission, role),
+            "INSERT INTO tbl_role_permissions (role_name, permission) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            &[role, permission],
+        ).await?;
+        Ok(())
+    }
+
+    async fn revoke_permission(&self, role: &str, permission: &str) -> Result<bool, Error> {
+        let deleted = self.update_roles(
+            format!("Unable to revoke permission: {} from role: {}", permission, role),
+            "DELETE FROM tbl_role_permissions WHERE role_name = $1 AND permission = $2",
+            &[role, permission],
+        ).await?;
+        Ok(deleted == 1)
+    }
+
+    async fn assign_role(&self, user: &str, role: &str) -> Result<(), Error> {
+        // A missing user or role is a foreign key violation, reported as ConstraintViolation
+        let _ = self.update_roles(
+            format!("Unable to assign role: {} to user: {}", role, user),
+            "INSERT INTO tbl_user_roles (user_id, role_name) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            &[user, role],
+        ).await?;
+        Ok(())
+    }
+
+    async fn revoke_role(&self, user: &str, role: &str) -> Result<bool, Error> {
+        let deleted = self.update_roles(
+            format!("Unable to revoke role: {} from user: {}", role, user),
+            "DELETE FROM tbl_user_roles WHERE user_id = $1 AND role_name = $2",
+            &[user, role],
+        ).await?;
+        Ok(deleted == 1)
+    }
+
+    async fn resolve_roles(&self, user: &str) -> Result<Vec<String>, Error> {
+        self.resolve_names(
+            format!("Role lookup failed for user: {}", user),
+            "SELECT role_name FROM tbl_user_roles WHERE user_id = $1 ORDER BY role_name",
+            user,
+        ).await
+    }
+
+    async fn resolve_permissions(&self, user: &str) -> Result<Vec<String>, Error> {
+        self.resolve_names(
+            format!("Permission lookup failed for user: {}", user),
+            "SELECT DISTINCT p.permission FROM tbl_role_permissions p \
+             JOIN tbl_user_roles r ON r.role_name = p.role_name \
+             WHERE r.user_id = $1 ORDER BY p.permission",
+            user,
+        ).await
+    }
+}
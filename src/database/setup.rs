@@ -1,24 +1,80 @@
+use std::thread;
+use std::time::Duration;
+
 use failure::Error;
-use crate::database::db_common::{pg_pool_config_from_env, pg_connection_config_from_env, PoolConfig, ConnectionConfig};
-use crate::database::r2d2_pool::create_r2d2_pool;
-use crate::database::errors::DatabaseErrors::ConnectionFailed;
+use rand::Rng;
+
+use crate::config::DatabaseConfig;
+use crate::database::db_common::RetryConfig;
+use crate::database::db_pool::ReplicatedPool;
+use crate::database::r2d2_pool::{create_r2d2_pool, R2D2Pool};
+use crate::database::tokio_pool::{create_tokio_pool, create_replicated_tokio_pool, TokioPool};
+use crate::database::errors::DatabaseErrors::{self, ConnectionAttemptFailed, ConnectionRetriesExhausted};
 
-use postgres::NoTls;
 
+/// Retries with exponential backoff (see `RetryConfig`) while the database is unreachable
+pub fn connection() -> Result<R2D2Pool, Error> {
+    connection_with(&DatabaseConfig::from_env()?)
+}
+
+pub fn connection_with(config: &DatabaseConfig) -> Result<R2D2Pool, Error> {
+    let conn_config = config.connection_config()?;
+    let mut attempt = 1;
+    loop {
+        match create_r2d2_pool(conn_config.clone(), config.pool.clone()) {
+            Ok(pool) => return Ok(pool),
+            Err(e) => thread::sleep(retry_delay(&config.retry, attempt, e)?),
+        };
+        attempt += 1;
+    }
+}
 
-use r2d2::Pool;
-use r2d2_postgres::PostgresConnectionManager;
+/// Retries with exponential backoff (see `RetryConfig`) while the database is unreachable
+pub async fn async_connection() -> Result<TokioPool, Error> {
+    async_connection_with(&DatabaseConfig::from_env()?).await
+}
 
+pub async fn async_connection_with(config: &DatabaseConfig) -> Result<TokioPool, Error> {
+    let conn_config = config.connection_config()?;
+    let mut attempt = 1;
+    loop {
+        match create_tokio_pool(conn_config.clone(), config.pool.clone()).await {
+            Ok(pool) => return Ok(pool),
+            Err(e) => actix_rt::time::delay_for(retry_delay(&config.retry, attempt, e)?).await,
+        };
+        attempt += 1;
+    }
+}
 
-pub fn connection() -> Result<Pool<PostgresConnectionManager<NoTls>>, Error>{
-    let pool_config: Result<PoolConfig, Error> = pg_pool_config_from_env().map_err(|e| {
-        ConnectionFailed("Bad PoolConfig".to_string(), e).into()
-    });
-    let pool_config = pool_config?;
-    let conn_config:Result<ConnectionConfig, Error> = pg_connection_config_from_env().map_err(|e| {
-        ConnectionFailed("Bad Connection Config".to_string(), e).into()
-    });
-    let conn_config = conn_config?;
-    create_r2d2_pool(conn_config, pool_config)
+/// Retries while the primary is unreachable; replicas are not waited for (see `create_replicated_tokio_pool`)
+pub async fn async_replicated_connection_with(config: &DatabaseConfig) -> Result<ReplicatedPool<TokioPool>, Error> {
+    let conn_config = config.connection_config()?;
+    let replica_configs = config.replica_configs()?;
+    let mut attempt = 1;
+    loop {
+        match create_replicated_tokio_pool(conn_config.clone(), replica_configs.clone(), config.pool.clone()).await {
+            Ok(pool) => return Ok(pool),
+            Err(e) => actix_rt::time::delay_for(retry_delay(&config.retry, attempt, e)?).await,
+        };
+        attempt += 1;
+    }
 }
 
+/// Reports the failed attempt and works out how long to wait before the next one
+fn retry_delay(retry_config: &RetryConfig, attempt: u32, error: Error) -> Result<Duration, Error> {
+    // A bad certificate or key will not fix itself
+    if let Some(DatabaseErrors::TlsConfigurationFailed(_, _)) = error.downcast_ref::<DatabaseErrors>() {
+        return Err(error);
+    };
+    if attempt >= retry_config.max_attempts {
+        let msg = format!("Unable to connect after {} attempt(s)", attempt);
+        return Err(ConnectionRetriesExhausted(msg, error).into());
+    };
+    // Equal jitter: wait at least half the backoff, so instances starting together spread out
+    let backoff = retry_config.backoff(attempt).as_millis() as u64;
+    let delay = backoff / 2 + rand::thread_rng().gen_range(0, backoff / 2 + 1);
+    let delay = Duration::from_millis(delay);
+    let msg = format!("Connection attempt {}/{} failed, retrying in {}ms", attempt, retry_config.max_attempts, delay.as_millis());
+    eprintln!("{}", ConnectionAttemptFailed(msg, error));
+    Ok(delay)
+}
@@ -0,0 +1,621 @@
+use std::sync::{Arc, Mutex};
+
+use jwtvault::prelude::*;
+
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+
+use crate::database::users_setup::{Account, UserRepository};
+use crate::database::profiles::{ProfileRepository, UserProfile};
+use crate::database::roles::RoleRepository;
+use crate::database::errors::DatabaseErrors::{self, ConnectionFailed, NotFound};
+use crate::stores::expiry::{self, StoreUntil};
+use crate::stores::pins::Pins;
+use crate::stores::sweeper::Sweep;
+
+/// Indexed by `PRAGMA user_version`, mirrors the postgres migrations
+const SCHEMA: &[&str] = &["
+CREATE TABLE IF NOT EXISTS tbl_users (
+    user_id VARCHAR(512) NOT NULL,
+    user_password VARCHAR(512) NOT NULL,
+    PRIMARY KEY (user_id)
+);
+
+CREATE TABLE IF NOT EXISTS tbl_sessions (
+    session_key BIGINT NOT NULL,
+    session_value TEXT NOT NULL,
+    expires_at BIGINT NULL,
+    PRIMARY KEY (session_key)
+);
+", "
+ALTER TABLE tbl_users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE tbl_users ADD COLUMN locked BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE tbl_users ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tbl_users ADD COLUMN created_at BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE tbl_users ADD COLUMN last_login BIGINT NULL;
+", "
+ALTER TABLE tbl_users ADD COLUMN display_name VARCHAR(256) NULL;
+ALTER TABLE tbl_users ADD COLUMN tenant VARCHAR(256) NULL;
+
+CREATE TABLE IF NOT EXISTS tbl_roles (
+    role_name VARCHAR(256) NOT NULL,
+    PRIMARY KEY (role_name)
+);
+
+CREATE TABLE IF NOT EXISTS tbl_user_roles (
+    user_id VARCHAR(512) NOT NULL REFERENCES tbl_users (user_id) ON DELETE CASCADE,
+    role_name VARCHAR(256) NOT NULL REFERENCES tbl_roles (role_name) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, role_name)
+);
+
+CREATE TABLE IF NOT EXISTS tbl_role_permissions (
+    role_name VARCHAR(256) NOT NULL REFERENCES tbl_roles (role_name) ON DELETE CASCADE,
+    permission VARCHAR(256) NOT NULL,
+    PRIMARY KEY (role_name, permission)
+);
+
+INSERT OR IGNORE INTO tbl_roles (role_name) VALUES ('admin'), ('user');
+INSERT OR IGNORE INTO tbl_role_permissions (role_name, permission) VALUES ('admin', 'execute'), ('user', 'execute');
+"];
+
+/// Shared handle on a SQLite database (file or in-memory)
+///
+/// Clone the handle to share one in-memory database between the user store and the sessions.
+#[derive(Clone)]
+pub struct SqliteDatabase {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteDatabase {
+    /// `:memory:` opens a private in-memory database
+    pub fn open<T: AsRef<str>>(path: T) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let conn = if path == ":memory:" {
+            Connection::open_in_memory()
+        } else {
+            Connection::open(path)
+        };
+        let conn = conn.map_err(|e| {
+            ConnectionFailed(format!("Unable to open sqlite database: {}", path), e.into())
+        })?;
+        // Off by default in sqlite, role assignment relies on it
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        let version: i64 = conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+        for (version, sql) in SCHEMA.iter().enumerate().skip(version as usize) {
+            conn.execute_batch(sql)?;
+            conn.execute_batch(format!("PRAGMA user_version = {}", version + 1).as_str())?;
+        }
+        let conn = Arc::new(Mutex::new(conn));
+        Ok(Self { conn })
+    }
+
+    fn execute<F, T>(&self, f: F) -> Result<T, Error>
+        where F: FnOnce(&Connection) -> Result<T, Error>
+    {
+        let conn = self.conn.lock().map_err(|e| {
+            ConnectionFailed("Unable to lock sqlite connection".to_string(), failure::err_msg(e.to_string()))
+        })?;
+        f(&conn)
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteUserRepository {
+    db: SqliteDatabase,
+}
+
+impl SqliteUserRepository {
+    pub fn new(db: SqliteDatabase) -> Self {
+        Self { db }
+    }
+
+    /// Run an UPDATE on a single user, `?1` is the user
+    fn update_user(&self, user: &str, sql: &str, value: bool) -> Result<(), Error> {
+        self.db.execute(|conn| {
+            let updated = conn.execute(sql, params![user, value])
+                .map_err(|e| DatabaseErrors::from_sqlite(format!("Update failed for user: {}", user), e))?;
+            if updated == 0 {
+                let msg = format!("Update failed for user: {}", user);
+                let reason = "User does not exist".to_string();
+                return Err(NotFound(msg, reason).into());
+            };
+            Ok(())
+        })
+    }
+}
+
+#[async_trait]
+impl UserRepository for SqliteUserRepository {
+    async fn signup_user(&self, user: &str, password: &str) -> Result<(), Error> {
+        let created_at = compute_timestamp_in_seconds();
+        self.db.execute(|conn| {
+            let _ = conn.execute(
+                "INSERT INTO tbl_users (user_id, user_password, created_at) VALUES (?1, ?2, ?3)",
+                params![user, password, created_at],
+            ).map_err(|e| DatabaseErrors::from_sqlite(format!("Signup failed for user: {}", user), e))?;
+            Ok(())
+        })
+    }
+
+    async fn resolve_account(&self, user: &str) -> Result<Account, Error> {
+        self.db.execute(|conn| {
+            let account = conn.query_row(
+                "SELECT user_id, user_password, disabled, locked, failed_attempts, created_at, last_login \
+                 FROM tbl_users WHERE user_id = ?1",
+                params![user],
+                |row| Ok(Account {
+                    user_id: row.get(0)?,
+                    password: row.get(1)?,
+                    disabled: row.get(2)?,
+                    locked: row.get(3)?,
+                    failed_attempts: row.get(4)?,
+                    created_at: row.get(5)?,
+                    last_login: row.get(6)?,
+                }),
+            ).map_err(|e| DatabaseErrors::from_sqlite(format!("Lookup failed for user: {}", user), e))?;
+            Ok(account)
+        })
+    }
+
+    async fn update_password(&self, user: &str, old_password: &str, new_password: &str) -> Result<bool, Error> {
+        self.db.execute(|conn| {
+            let updated = conn.execute(
+                "UPDATE tbl_users SET user_password = ?3 WHERE user_id = ?1 AND user_password = ?2",
+                params![user, old_password, new_password],
+            ).map_err(|e| DatabaseErrors::from_sqlite(format!("Password update failed for user: {}", user), e))?;
+            Ok(updated == 1)
+        })
+    }
+
+    async fn record_login_success(&self, user: &str) -> Result<(), Error> {
+        let now = compute_timestamp_in_seconds();
+        self.db.execute(|conn| {
+            let _ = conn.execute(
+                "UPDATE tbl_users SET failed_attempts = 0, last_login = ?2 WHERE user_id = ?1",
+                params![user, now],
+            ).map_err(|e| DatabaseErrors::from_sqlite(format!("Login bookkeeping failed for user: {}", user), e))?;
+            Ok(())
+        })
+    }
+
+    async fn record_login_failure(&self, user: &str, max_failed_attempts: i32) -> Result<bool, Error> {
+        self.db.execute(|conn| {
+            let failed = |e| DatabaseErrors::from_sqlite(format!("Login bookkeeping failed for user: {}", user), e);
+            let _ = conn.execute(
+                "UPDATE tbl_users SET failed_attempts = failed_attempts + 1, \
+                 locked = (locked OR failed_attempts + 1 >= ?2) WHERE user_id = ?1",
+                params![user, max_failed_attempts],
+            ).map_err(failed)?;
+            let locked = conn.query_row(
+                "SELECT locked FROM tbl_users WHERE user_id = ?1",
+                params![user],
+                |row| row.get(0),
+            ).map_err(failed)?;
+            Ok(locked)
+        })
+    }
+
+    async fn set_disabled(&self, user: &str, disabled: bool) -> Result<(), Error> {
+        self.update_user(user, "UPDATE tbl_users SET disabled = ?2 WHERE user_id = ?1", disabled)
+    }
+
+    async fn set_locked(&self, user: &str, locked: bool) -> Result<(), Error> {
+        self.update_user(
+            user,
+            "UPDATE tbl_users SET locked = ?2, \
+             failed_attempts = CASE WHEN ?2 THEN failed_attempts ELSE 0 END \
+             WHERE user_id = ?1",
+            locked,
+        )
+    }
+}
+
+impl SqliteUserRepository {
+    /// Run a write on roles, returning the rows changed
+    fn update_roles(&self, msg: String, sql: &str, params: &[&str]) -> Result<usize, Error> {
+        self.db.execute(|conn| {
+            let updated = conn.execute(sql, params)
+                .map_err(|e| DatabaseErrors::from_sqlite(msg, e))?;
+            Ok(updated)
+        })
+    }
+
+    /// Run a lookup returning a single text column
+    fn resolve_names(&self, conn: &Connection, msg: &str, sql: &str, user: &str) -> Result<Vec<String>, Error> {
+        let failed = |e| DatabaseErrors::from_sqlite(msg.to_string(), e);
+        let mut statement = conn.prepare(sql).map_err(failed)?;
+        let names = statement.query_map(params![user], |row| row.get(0)).map_err(failed)?;
+        let names = names.collect::<Result<Vec<String>, _>>().map_err(failed)?;
+        Ok(names)
+    }
+}
+
+const USER_ROLES_SQL: &str = "SELECT role_name FROM tbl_user_roles WHERE user_id = ?1 ORDER BY role_name";
+
+const USER_PERMISSIONS_SQL: &str = "SELECT DISTINCT p.permission FROM tbl_role_permissions p \
+     JOIN tbl_user_roles r ON r.role_name = p.role_name \
+     WHERE r.user_id = ?1 ORDER BY p.permission";
+
+#[async_trait]
+impl ProfileRepository for SqliteUserRepository {
+    async fn resolve_profile(&self, user: &str) -> Result<UserProfile, Error> {
+        self.db.execute(|conn| {
+            let msg = format!("Profile lookup failed for user: {}", user);
+            let (display_name, tenant) = conn.query_row(
+                "SELECT display_name, tenant FROM tbl_users WHERE user_id = ?1",
+                params![user],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            ).map_err(|e| DatabaseErrors::from_sqlite(msg.clone(), e))?;
+            let roles = self.resolve_names(conn, msg.as_str(), USER_ROLES_SQL, user)?;
+            let permissions = self.resolve_names(conn, msg.as_str(), USER_PERMISSIONS_SQL, user)?;
+            Ok(UserProfile { display_name, tenant, roles, permissions })
+        })
+    }
+
+    async fn update_profile(&self, user: &str, display_name: Option<&str>, tenant: Option<&str>) -> Result<(), Error> {
+        self.db.execute(|conn| {
+            let updated = conn.execute(
+                "UPDATE tbl_users SET display_name = ?2, tenant = ?3 WHERE user_id = ?1",
+                params![user, display_name, tenant],
+            ).map_err(|e| DatabaseErrors::from_sqlite(format!("Profile update failed for user: {}", user), e))?;
+            if updated == 0 {
+                let msg = format!("Profile update failed for user: {}", user);
+                let reason = "User does not exist".to_string();
+                return Err(NotFound(msg, reason).into());
+            };
+            Ok(())
+        })
+    }
+}
+
+#[async_trait]
+impl RoleRepository for SqliteUserRepository {
+    async fn create_role(&self, role: &str) -> Result<(), Error> {
+        let _ = self.update_roles(
+            format!("Unable to create role: {}", role),
+            "INSERT OR IGNORE INTO tbl_roles (role_name) VALUES (?1)",
+            &[role],
+        )?;
+        Ok(())
+    }
+
+    async fn delete_role(&self, role: &str) -> Result<bool, Error> {
+        let deleted = self.update_roles(
+            format!("Unable to delete role: {}", role),
+            "DELETE FROM tbl_roles WHERE role_name = ?1",
+            &[role],
+        )?;
+        Ok(deleted == 1)
+    }
+
+    async fn grant_permission(&self, role: &str, permission: &str) -> Result<(), Error> {
+        self.create_role(role).await?;
+        let _ = self.update_roles(
+            format!("Unable to grant permission: {} to role: {}", permission, role),
+            "INSERT OR IGNORE INTO tbl_role_permissions (role_name, permission) VALUES (?1, ?2)",
+            &[role, permission],
+        )?;
+        Ok(())
+    }
+
+    async fn revoke_permission(&self, role: &str, permission: &str) -> Result<bool, Error> {
+        let deleted = self.update_roles(
+            format!("Unable to revoke permission: {} from role: {}", permission, role),
+            "DELETE FROM tbl_role_permissions WHERE role_name = ?1 AND permission = ?2",
+            &[role, permission],
+        )?;
+        Ok(deleted == 1)
+    }
+
+    async fn assign_role(&self, user: &str, role: &str) -> Result<(), Error> {
+        // A missing user or role is a foreign key violation, reported as ConstraintViolation
+        let _ = self.update_roles(
+            format!("Unable to assign role: {} to user: {}", role, user),
+            "INSERT OR IGNORE INTO tbl_user_roles (user_id, role_name) VALUES (?1, ?2)",
+            &[user, role],
+        )?;
+        Ok(())
+    }
+
+    async fn revoke_role(&self, user: &str, role: &str) -> Result<bool, Error> {
+        let deleted = self.update_roles(
+            format!("Unable to revoke role: {} from user: {}", role, user),
+            "DELETE FROM tbl_user_roles WHERE user_id = ?1 AND role_name = ?2",
+            &[user, role],
+        )?;
+        Ok(deleted == 1)
+    }
+
+    async fn resolve_roles(&self, user: &str) -> Result<Vec<String>, Error> {
+        self.db.execute(|conn| {
+            self.resolve_names(conn, format!("Role lookup failed for user: {}", user).as_str(), USER_ROLES_SQL, user)
+        })
+    }
+
+    async fn resolve_permissions(&self, user: &str) -> Result<Vec<String>, Error> {
+        self.db.execute(|conn| {
+            self.resolve_names(conn, format!("Permission lookup failed for user: {}", user).as_str(), USER_PERMISSIONS_SQL, user)
+        })
+    }
+}
+
+/// Session storage backed by `tbl_sessions` in SQLite
+///
+/// Same as `PostgresPersistence`: every call goes to the database, values loaded are pinned
+/// until the next write or `sweep`, and a failed write is logged and leaves nothing behind.
+/// `purge_expired` (e.g. on startup) and `sweep` delete expired rows.
+/// A `clone` shares the database and starts without pins.
+pub struct SqlitePersistence {
+    db: SqliteDatabase,
+    expiry_in_seconds: Option<i64>,
+    pins: Pins,
+}
+
+impl SqlitePersistence {
+    /// `expiry_in_seconds` should match the refresh token expiry; `None` never expires
+    pub fn new(db: SqliteDatabase, expiry_in_seconds: Option<i64>) -> Self {
+        Self { db, expiry_in_seconds, pins: Pins::default() }
+    }
+
+    /// Delete expired sessions, returns how many there were
+    pub async fn purge_expired(&self) -> Result<u64, Error> {
+        let now = compute_timestamp_in_seconds();
+        self.db.execute(|conn| {
+            let purged = conn.execute("DELETE FROM tbl_sessions WHERE expires_at <= ?1", params![now])?;
+            Ok(purged as u64)
+        })
+    }
+
+    fn read(&self, key: u64) -> Result<Option<String>, Error> {
+        let now = compute_timestamp_in_seconds();
+        self.db.execute(|conn| {
+            let value = conn.query_row(
+                "SELECT session_value FROM tbl_sessions WHERE session_key = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+                params![key as i64, now],
+                |row| row.get(0),
+            ).optional()?;
+            Ok(value)
+        })
+    }
+}
+
+impl Clone for SqlitePersistence {
+    fn clone(&self) -> Self {
+        Self { db: self.db.clone(), expiry_in_seconds: self.expiry_in_seconds, pins: Pins::default() }
+    }
+}
+
+#[async_trait]
+impl Persistence for SqlitePersistence {
+    async fn store(&mut self, key: u64, value: String) {
+        let expires_at = expiry::expires_at(self.expiry_in_seconds);
+        self.store_until(key, value, expires_at).await
+    }
+
+    async fn load(&self, key: u64) -> Option<&String> {
+        match self.read(key) {
+            Ok(value) => value.map(|value| self.pins.pin(key, value)),
+            Err(e) => {
+                eprintln!("Unable to load session: {} Reason: {}", key, e.to_string());
+                None
+            }
+        }
+    }
+
+    async fn remove(&mut self, key: u64) -> Option<String> {
+        self.pins.clear();
+        let value = self.read(key);
+        let result = self.db.execute(|conn| {
+            let _ = conn.execute("DELETE FROM tbl_sessions WHERE session_key = ?1", params![key as i64])?;
+            Ok(())
+        });
+        match result.and(value) {
+            Ok(value) => value,
+            Err(e) => {
+                eprintln!("Unable to remove session: {} Reason: {}", key, e.to_string());
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StoreUntil for SqlitePersistence {
+    async fn store_until(&mut self, key: u64, value: String, expires_at: Option<i64>) {
+        self.pins.clear();
+        let result = self.db.execute(|conn| {
+            let _ = conn.execute(
+                "INSERT OR REPLACE INTO tbl_sessions (session_key, session_value, expires_at) VALUES (?1, ?2, ?3)",
+                params![key as i64, value, expires_at],
+            )?;
+            Ok(())
+        });
+        if let Err(e) = result {
+            eprintln!("Unable to store session: {} Reason: {}", key, e.to_string());
+        };
+    }
+}
+
+impl Sweep for SqlitePersistence {
+    /// Deletes the expired rows, which are never loaded anyway, and lets go of the values pinned by `load`
+    fn sweep(&mut self) -> usize {
+        self.pins.clear();
+        match block_on(self.purge_expired()) {
+            Ok(purged) => purged as usize,
+            Err(e) => {
+                eprintln!("{}", e.to_string());
+                0
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod sqlite {
+    use super::*;
+
+    #[test]
+    fn user_repository_validation() {
+        let db = SqliteDatabase::open(":memory:").unwrap();
+        let users = SqliteUserRepository::new(db);
+
+        block_on(users.signup_user("john_doe", "hash")).unwrap();
+        let result = block_on(users.signup_user("john_doe", "hash"));
+        let e = result.err().unwrap();
+        match e.downcast_ref::<DatabaseErrors>() {
+            Some(DatabaseErrors::UniqueViolation(_, _)) => {}
+            _ => panic!("Expected UniqueViolation, got: {}", e),
+        };
+
+        let password = block_on(users.resolve_password_for_user("john_doe")).unwrap();
+        assert_eq!(password, "hash");
+        let result = block_on(users.resolve_password_for_user("jane_doe"));
+        let e = result.err().unwrap();
+        match e.downcast_ref::<DatabaseErrors>() {
+            Some(DatabaseErrors::NotFound(_, _)) => {}
+            _ => panic!("Expected NotFound, got: {}", e),
+        };
+    }
+
+    /// Stores `$argon2id$...$<password>$<hash>` with current parameters, verifies legacy plaintext too
+    struct PlainHasher;
+
+    impl PasswordHasher<String> for PlainHasher {
+        fn hash_user_password<T: AsRef<str>>(&self, _: T, password: T) -> Result<String, Error> {
+            Ok(format!("$argon2id$v=19$m=4096,t=192,p=1${}${}", password.as_ref(), "A".repeat(43)))
+        }
+
+        fn verify_user_password<T: AsRef<str>>(&self, _: T, password: T, hash: T) -> Result<bool, Error> {
+            let password = password.as_ref();
+            let hash = hash.as_ref();
+            Ok(hash == password || hash.split('$').nth(4) == Some(password))
+        }
+    }
+
+    #[test]
+    fn account_state_validation() {
+        use crate::database::users_setup::{authenticate_user, MAX_FAILED_ATTEMPTS, REASON_ACCOUNT_DISABLED, REASON_ACCOUNT_LOCKED, REASON_INVALID_CREDENTIALS};
+        use crate::database::password::CurrentParams;
+
+        let reason = |result: Result<Account, Error>| match result.err().unwrap().downcast::<LoginFailed>() {
+            Ok(LoginFailed::InvalidPassword(_, reason)) => reason,
+            _ => panic!("Expected LoginFailed::InvalidPassword"),
+        };
+
+        let db = SqliteDatabase::open(":memory:").unwrap();
+        let users = SqliteUserRepository::new(db);
+        let hasher = PlainHasher;
+        let params = CurrentParams::default();
+        block_on(users.signup_user("john_doe", "john")).unwrap();
+
+        let account = block_on(authenticate_user(&users, &hasher, &params, "john_doe", "john")).unwrap();
+        assert!(!account.disabled && !account.locked);
+        assert!(block_on(users.resolve_account("john_doe")).unwrap().last_login.is_some());
+
+        block_on(users.set_disabled("john_doe", true)).unwrap();
+        let result = block_on(authenticate_user(&users, &hasher, &params, "john_doe", "john"));
+        assert_eq!(reason(result), REASON_ACCOUNT_DISABLED);
+        block_on(users.set_disabled("john_doe", false)).unwrap();
+
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            let _ = block_on(authenticate_user(&users, &hasher, &params, "john_doe", "jane"));
+        }
+        let account = block_on(users.resolve_account("john_doe")).unwrap();
+        assert!(account.locked);
+        assert_eq!(account.failed_attempts, MAX_FAILED_ATTEMPTS);
+        let result = block_on(authenticate_user(&users, &hasher, &params, "john_doe", "john"));
+        assert_eq!(reason(result), REASON_ACCOUNT_LOCKED);
+        let result = block_on(authenticate_user(&users, &hasher, &params, "john_doe", "jane"));
+        assert_eq!(reason(result), REASON_INVALID_CREDENTIALS);
+
+        block_on(users.set_locked("john_doe", false)).unwrap();
+        assert_eq!(block_on(users.resolve_account("john_doe")).unwrap().failed_attempts, 0);
+        assert!(block_on(authenticate_user(&users, &hasher, &params, "john_doe", "john")).is_ok());
+    }
+
+    #[test]
+    fn outdated_hash_is_replaced_on_login() {
+        use crate::database::users_setup::authenticate_user;
+        use crate::database::password::{needs_rehash, CurrentParams};
+
+        let db = SqliteDatabase::open(":memory:").unwrap();
+        let users = SqliteUserRepository::new(db);
+        let hasher = PlainHasher;
+        let params = CurrentParams::default();
+        block_on(users.signup_user("john_doe", "john")).unwrap();
+
+        let _ = block_on(authenticate_user(&users, &hasher, &params, "john_doe", "john")).unwrap();
+        let password = block_on(users.resolve_password_for_user("john_doe")).unwrap();
+        assert!(!needs_rehash(password.as_str(), params.get(&hasher).unwrap()));
+        assert!(block_on(authenticate_user(&users, &hasher, &params, "john_doe", "john")).is_ok());
+
+        // Lost the race against a concurrent password change
+        let updated = block_on(users.update_password("john_doe", "stale", "other")).unwrap();
+        assert!(!updated);
+        assert_eq!(block_on(users.resolve_password_for_user("john_doe")).unwrap(), password);
+    }
+
+    #[test]
+    fn roles_grant_permissions() {
+        let db = SqliteDatabase::open(":memory:").unwrap();
+        let users = SqliteUserRepository::new(db);
+        block_on(users.signup_user("john_doe", "john")).unwrap();
+        block_on(users.grant_permission("operator", "execute")).unwrap();
+        block_on(users.grant_permission("auditor", "status")).unwrap();
+        block_on(users.grant_permission("auditor", "execute")).unwrap();
+        block_on(users.create_role("guest")).unwrap();
+
+        block_on(users.assign_role("john_doe", "operator")).unwrap();
+        block_on(users.assign_role("john_doe", "auditor")).unwrap();
+        block_on(users.assign_role("john_doe", "auditor")).unwrap();
+        for (user, role) in [("jane_doe", "operator"), ("john_doe", "owner")].iter() {
+            let e = block_on(users.assign_role(user, role)).err().unwrap();
+            match e.downcast_ref::<DatabaseErrors>() {
+                Some(DatabaseErrors::ConstraintViolation(_, _)) => {}
+                _ => panic!("Expected ConstraintViolation, got: {}", e),
+            };
+        }
+        assert_eq!(block_on(users.resolve_roles("john_doe")).unwrap(), vec!["auditor", "operator"]);
+        assert_eq!(block_on(users.resolve_permissions("john_doe")).unwrap(), vec!["execute", "status"]);
+
+        block_on(users.update_profile("john_doe", Some("John Doe"), None)).unwrap();
+        let profile = block_on(users.resolve_profile("john_doe")).unwrap();
+        assert_eq!(profile.display_name, Some("John Doe".to_string()));
+        assert_eq!(profile.permissions, vec!["execute", "status"]);
+
+        assert!(block_on(users.revoke_permission("auditor", "status")).unwrap());
+        assert!(block_on(users.delete_role("operator")).unwrap());
+        assert!(!block_on(users.revoke_role("john_doe", "operator")).unwrap());
+        assert_eq!(block_on(users.resolve_permissions("john_doe")).unwrap(), vec!["execute"]);
+    }
+
+    #[test]
+    fn persistence_is_shared_through_the_database() {
+        let db = SqliteDatabase::open(":memory:").unwrap();
+        let mut store = SqlitePersistence::new(db.clone(), None);
+        let mut other = SqlitePersistence::new(db.clone(), None);
+        block_on(store.store(1, "one".to_string()));
+        block_on(store.store(2, "two".to_string()));
+        assert_eq!(block_on(other.load(2)), Some(&"two".to_string()));
+
+        // A session ended through one handle is gone for every handle
+        assert_eq!(block_on(other.remove(2)), Some("two".to_string()));
+        assert_eq!(block_on(store.load(2)), None);
+        assert_eq!(block_on(store.remove(2)), None);
+        assert_eq!(block_on(other.load(1)), Some(&"one".to_string()));
+
+        let now = compute_timestamp_in_seconds();
+        db.execute(|conn| {
+            let _ = conn.execute("INSERT INTO tbl_sessions VALUES (3, 'expired', ?1)", params![now - 1])?;
+            Ok(())
+        }).unwrap();
+        assert_eq!(block_on(store.load(3)), None);
+        assert_eq!(block_on(store.purge_expired()).unwrap(), 1);
+        assert_eq!(block_on(store.purge_expired()).unwrap(), 0);
+
+        db.execute(|conn| {
+            let _ = conn.execute("INSERT INTO tbl_sessions VALUES (4, 'expired', ?1)", params![now - 1])?;
+            Ok(())
+        }).unwrap();
+        assert_eq!(other.sweep(), 1);
+    }
+}
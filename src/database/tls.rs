@@ -0,0 +1,46 @@
+use failure::Error;
+
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode, SslFiletype};
+use postgres_openssl::MakeTlsConnector;
+
+use crate::database::db_common::{ConnectionConfig, SslMode};
+use crate::database::errors::DatabaseErrors::TlsConfigurationFailed;
+
+
+/// TLS connector honouring the sslmode, root CA and client certificate of the connection config
+///
+/// With `SslMode::Disable` the connector is never used for the handshake.
+pub(crate) fn tls_connector(conn_config: &ConnectionConfig) -> Result<MakeTlsConnector, Error> {
+    let mut builder = SslConnector::builder(SslMethod::tls()).map_err(|e| {
+        TlsConfigurationFailed("Unable to create TLS connector".to_string(), e.into())
+    })?;
+
+    if let Some(ssl_root_cert) = &conn_config.ssl_root_cert {
+        builder.set_ca_file(ssl_root_cert).map_err(|e| {
+            TlsConfigurationFailed(format!("Bad root certificate: {}", ssl_root_cert), e.into())
+        })?;
+    };
+
+    if let (Some(ssl_cert), Some(ssl_key)) = (&conn_config.ssl_cert, &conn_config.ssl_key) {
+        builder.set_certificate_chain_file(ssl_cert).map_err(|e| {
+            TlsConfigurationFailed(format!("Bad client certificate: {}", ssl_cert), e.into())
+        })?;
+        builder.set_private_key_file(ssl_key, SslFiletype::PEM).map_err(|e| {
+            TlsConfigurationFailed(format!("Bad client key: {}", ssl_key), e.into())
+        })?;
+    };
+
+    // libpq semantics: prefer/require encrypt but do not verify the server
+    if !conn_config.ssl_mode.verify_certificate() {
+        builder.set_verify(SslVerifyMode::NONE);
+    };
+
+    let mut connector = MakeTlsConnector::new(builder.build());
+    if conn_config.ssl_mode != SslMode::VerifyFull {
+        connector.set_callback(|config, _| {
+            config.set_verify_hostname(false);
+            Ok(())
+        });
+    };
+    Ok(connector)
+}
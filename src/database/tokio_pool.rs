@@ -0,0 +1,101 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use failure::Error;
+
+use tokio_postgres::Config;
+use postgres_openssl::MakeTlsConnector;
+
+use bb8::{Pool, PooledConnection};
+use bb8_postgres::PostgresConnectionManager;
+
+use crate::database::db_common::{ConnectionConfig, PoolConfig};
+use crate::database::db_pool::{create_async_pool, create_lazy_async_pool, PoolStatus, ReplicatedPool, WaitGuard};
+use crate::database::tls::tls_connector;
+use crate::database::errors::DatabaseErrors::{ConnectionFailed, PoolExhausted, ReplicaUnavailable};
+
+
+/// bb8 pool that keeps track of callers waiting for a connection
+#[derive(Clone)]
+pub struct TokioPool {
+    pool: Pool<PostgresConnectionManager<MakeTlsConnector>>,
+    max_size: u32,
+    waiting: Arc<AtomicU32>,
+}
+
+impl TokioPool {
+    pub async fn get(&self) -> Result<PooledConnection<'_, PostgresConnectionManager<MakeTlsConnector>>, Error> {
+        let _waiting = WaitGuard::new(&self.waiting);
+        self.pool.get().await.map_err(|e| match e {
+            bb8::RunError::TimedOut => {
+                let status = self.status();
+                let msg = "Timed out waiting for a connection".to_string();
+                let reason = format!("{} of {} connections in use", status.in_use, status.max_size);
+                PoolExhausted(msg, failure::err_msg(reason)).into()
+            }
+            bb8::RunError::User(e) => {
+                ConnectionFailed("Unable to open a connection".to_string(), e.into()).into()
+            }
+        })
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        let state = self.pool.state();
+        let waiting = self.waiting.load(Ordering::SeqCst);
+        PoolStatus::new(self.max_size, state.connections, state.idle_connections, waiting)
+    }
+}
+
+impl ReplicatedPool<TokioPool> {
+    /// A replica connection, falling back to the next replica and finally to the primary
+    pub async fn read(&self) -> Result<PooledConnection<'_, PostgresConnectionManager<MakeTlsConnector>>, Error> {
+        let pools = self.read_order();
+        let (&primary, replicas) = pools.split_last().unwrap();
+        for &replica in replicas {
+            match replica.get().await {
+                Ok(conn) => return Ok(conn),
+                Err(e) => {
+                    let msg = "Read replica unavailable, trying the next pool".to_string();
+                    eprintln!("{}", ReplicaUnavailable(msg, e));
+                }
+            };
+        }
+        primary.get().await
+    }
+
+    pub async fn write(&self) -> Result<PooledConnection<'_, PostgresConnectionManager<MakeTlsConnector>>, Error> {
+        self.primary().get().await
+    }
+}
+
+pub(crate) fn manager(conn_config: ConnectionConfig) -> Result<PostgresConnectionManager<MakeTlsConnector>, Error> {
+    let tls_connector = tls_connector(&conn_config)?;
+    let conn_config = Config::from(conn_config);
+    let tokio_manager = PostgresConnectionManager::<MakeTlsConnector>::new(
+        conn_config,
+        tls_connector,
+    );
+    Ok(tokio_manager)
+}
+
+pub async fn create_tokio_pool(conn_config: ConnectionConfig, pool_config: PoolConfig) -> Result<TokioPool, Error> {
+    let manager = manager(conn_config)?;
+    let max_size = pool_config.max_size.into();
+    let pool = create_async_pool(manager, pool_config).await?;
+    let waiting = Arc::new(AtomicU32::new(0));
+    Ok(TokioPool { pool, max_size, waiting })
+}
+
+/// Replica pools connect lazily: a replica that is down only costs its reads a fallback
+pub async fn create_replicated_tokio_pool(conn_config: ConnectionConfig, replica_configs: Vec<ConnectionConfig>, pool_config: PoolConfig) -> Result<ReplicatedPool<TokioPool>, Error> {
+    let primary = create_tokio_pool(conn_config, pool_config.clone()).await?;
+    let mut replicas = Vec::with_capacity(replica_configs.len());
+    for replica_config in replica_configs {
+        let manager = manager(replica_config)?;
+        let max_size = pool_config.max_size.into();
+        let pool = create_lazy_async_pool(manager, pool_config.clone());
+        let waiting = Arc::new(AtomicU32::new(0));
+        replicas.push(TokioPool { pool, max_size, waiting });
+    }
+    Ok(ReplicatedPool::new(primary, replicas))
+}
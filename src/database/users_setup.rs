@@ -1,65 +1,592 @@
 use jwtvault::prelude::*;
-use postgres::NoTls;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use tokio_postgres::Client;
 
+use crate::database::db_pool::ReplicatedPool;
+use crate::database::tokio_pool::TokioPool;
+use crate::database::password::{needs_rehash, CurrentParams};
+use crate::database::profiles::SessionEnricher;
 
-use r2d2::Pool;
-use r2d2_postgres::PostgresConnectionManager;
+use crate::database::errors::DatabaseErrors::{self, NotFound};
 
-use crate::database::setup::connection;
 
+/// Consecutive failed logins after which an account is locked
+pub const MAX_FAILED_ATTEMPTS: i32 = 5;
 
-pub async fn signup_app_users() {
-    let pool = connection().ok().unwrap();
-    let loader = CertificateManger::default();
-    let private_key = loader.password_hashing_secret();
-    let secret_key = private_key.as_str();
+/// `LoginFailed` reasons returned by `authenticate_user`
+pub const REASON_INVALID_CREDENTIALS: &str = "Invalid userid/password";
+pub const REASON_ACCOUNT_DISABLED: &str = "Account is disabled";
+pub const REASON_ACCOUNT_LOCKED: &str = "Account is locked";
 
-    // User: John Doe
-    let user_john = "john_doe";
-    let password_for_john = "john";
+/// A row of `tbl_users`; timestamps are seconds since epoch
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account {
+    pub user_id: String,
+    pub password: String,
+    pub disabled: bool,
+    pub locked: bool,
+    pub failed_attempts: i32,
+    pub created_at: i64,
+    pub last_login: Option<i64>,
+}
 
-    let hashed_password_for_john = hash_password_with_argon(
-        password_for_john,
-        secret_key,
-    ).unwrap();
-    let result = signup_user::<&str>(pool.clone(), &user_john, &hashed_password_for_john).await;
-    if let Err(e) = result {
-        let reason = e.to_string();
-        eprintln!("Signup failed for user: {} Reason: {}", user_john, reason);
-    };
+/// Storage for app users, their (argon hashed) password and account state
+#[async_trait]
+pub trait UserRepository {
+    /// Fails with `DatabaseErrors::UniqueViolation` if the user already exists
+    async fn signup_user(&self, user: &str, password: &str) -> Result<(), Error>;
+    /// Fails with `DatabaseErrors::NotFound` if the user does not exist
+    async fn resolve_account(&self, user: &str) -> Result<Account, Error>;
+    /// The account as last written, for the lock and disabled checks of a login;
+    /// repositories reading from replicas go to the primary
+    async fn resolve_account_state(&self, user: &str) -> Result<Account, Error> {
+        self.resolve_account(user).await
+    }
+    /// Fails with `DatabaseErrors::NotFound` if the user does not exist
+    async fn resolve_password_for_user(&self, user: &str) -> Result<String, Error> {
+        let account = self.resolve_account(user).await?;
+        Ok(account.password)
+    }
+    /// Replace the hash only if it is still `old_password`, so a concurrent password change wins.
+    /// Returns whether the hash was replaced
+    async fn update_password(&self, user: &str, old_password: &str, new_password: &str) -> Result<bool, Error>;
+    /// Reset the failed attempts and record the login time
+    async fn record_login_success(&self, user: &str) -> Result<(), Error>;
+    /// Count a failed attempt, locking the account once `max_failed_attempts` is reached.
+    /// Returns whether the account is locked
+    async fn record_login_failure(&self, user: &str, max_failed_attempts: i32) -> Result<bool, Error>;
+    /// Fails with `DatabaseErrors::NotFound` if the user does not exist
+    async fn set_disabled(&self, user: &str, disabled: bool) -> Result<(), Error>;
+    /// Unlocking also resets the failed attempts. Fails with `DatabaseErrors::NotFound` if the user does not exist
+    async fn set_locked(&self, user: &str, locked: bool) -> Result<(), Error>;
+}
 
-    // User: Jane Doe
-    let user_jane = "jane_doe";
-    let password_for_jane = "jane";
-
-    let hashed_password_for_jane = hash_password_with_argon(
-        password_for_jane,
-        loader.password_hashing_secret().as_str(),
-    ).unwrap();
-    let result = signup_user::<&str>(pool.clone(), &user_jane, &hashed_password_for_jane).await;
-    if let Err(e) = result {
-        let reason = e.to_string();
-        eprintln!("Signup failed for user: {} Reason: {}", user_jane, reason);
-    };
+/// Account lookups go to the read replicas, the account state of a login and every write to the primary
+#[derive(Clone)]
+pub struct PostgresUserRepository {
+    pool: ReplicatedPool<TokioPool>,
+}
+
+impl PostgresUserRepository {
+    pub fn new(pool: ReplicatedPool<TokioPool>) -> Self {
+        Self { pool }
+    }
+
+    /// The primary pool
+    pub fn pool(&self) -> &TokioPool {
+        self.pool.primary()
+    }
+
+    /// The primary and its replicas, for other repositories sharing these tables
+    pub(crate) fn replicated(&self) -> &ReplicatedPool<TokioPool> {
+        &self.pool
+    }
+
+    /// The account row, `None` if the user does not exist
+    async fn select_account(conn: &Client, user: &str) -> Result<Option<Account>, Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Lookup failed for user: {}", user), e);
+        let statement = conn.prepare(
+            "SELECT user_id, user_password, disabled, locked, failed_attempts, created_at, last_login \
+             FROM tbl_users WHERE user_id = $1"
+        ).await.map_err(failed)?;
+        let rs = conn.query(&statement, &[&user]).await.map_err(failed)?;
+        let account = rs.into_iter().next().map(|row| Account {
+            user_id: row.get(0),
+            password: row.get(1),
+            disabled: row.get(2),
+            locked: row.get(3),
+            failed_attempts: row.get(4),
+            created_at: row.get(5),
+            last_login: row.get(6),
+        });
+        Ok(account)
+    }
+
+    /// Run an UPDATE on a single user, `$1` is the user
+    async fn update_user(&self, user: &str, sql: &str, value: bool) -> Result<(), Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Update failed for user: {}", user), e);
+        let conn = self.pool.write().await?;
+        let statement = conn.prepare(sql).await.map_err(failed)?;
+        let updated = conn.execute(&statement, &[&user, &value]).await.map_err(failed)?;
+        if updated == 0 {
+            let msg = format!("Update failed for user: {}", user);
+            let reason = "User does not exist".to_string();
+            return Err(NotFound(msg, reason).into());
+        };
+        Ok(())
+    }
 }
 
-pub async fn resolve_password_for_user<T: AsRef<str>>(pool: Pool<PostgresConnectionManager<NoTls>>, user: T) -> Result<Option<String>, Error> {
-    let mut conn = pool.get()?;
-    let query = format!("SELECT user_password FROM tbl_users WHERE user_id = '{}' ", user.as_ref());
-    let rs = conn.query(query.as_str(), &[])?;
-    for row in rs {
-        let rs: Option<String> = row.get(0);
-        return Ok(rs);
+#[async_trait]
+impl UserRepository for PostgresUserRepository {
+    async fn signup_user(&self, user: &str, password: &str) -> Result<(), Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Signup failed for user: {}", user), e);
+        let created_at = compute_timestamp_in_seconds();
+        let conn = self.pool.write().await?;
+        let statement = conn.prepare(
+            "INSERT INTO tbl_users (user_id, user_password, created_at) VALUES ($1, $2, $3)"
+        ).await.map_err(failed)?;
+        let _ = conn.execute(&statement, &[&user, &password, &created_at]).await.map_err(failed)?;
+        Ok(())
+    }
+
+    async fn resolve_account(&self, user: &str) -> Result<Account, Error> {
+        let account = Self::select_account(&*self.pool.read().await?, user).await?;
+        // A user who just signed up may not be on the replica yet
+        let account = match account {
+            Some(account) => Some(account),
+            None => Self::select_account(&*self.pool.write().await?, user).await?,
+        };
+        account.ok_or_else(|| {
+            let msg = format!("Lookup failed for user: {}", user);
+            let reason = "User does not exist".to_string();
+            NotFound(msg, reason).into()
+        })
+    }
+
+    async fn resolve_account_state(&self, user: &str) -> Result<Account, Error> {
+        // A lagging replica would let a locked or disabled account in
+        let account = Self::select_account(&*self.pool.write().await?, user).await?;
+        account.ok_or_else(|| {
+            let msg = format!("Lookup failed for user: {}", user);
+            let reason = "User does not exist".to_string();
+            NotFound(msg, reason).into()
+        })
     }
-    return Ok(None);
+
+    async fn update_password(&self, user: &str, old_password: &str, new_password: &str) -> Result<bool, Error>;
+    /// Reset the failed attempts and record the login time
+    async fn record_login_success(&self, user: &str) -> Result<(), Error>;
+    /// Count a failed attempt, locking the account once `max_failed_attempts` is reached.
+    /// Returns whether the account is locked
+    async fn record_login_failure(&self, user: &str, max_failed_attempts: i32) -> Result<bool, Error>;
+    /// Fails with `DatabaseErrors::NotFound` if the user does not exist
+    async fn set_disabled(&self, user: &str, disabled: bool) -> Result<(), Error>;
+    /// Unlocking also resets the failed attempts. Fails with `DatabaseErrors::NotFound` if the user does not exist
+    async fn set_locked(&self, user: &str, locked: bool) -> Result<(), Error>;
+}
+
+/// Account lookups go to the read replicas, the account state of a login and every write to the primary
+#[derive(Clone)]
+pub struct PostgresUserRepository {
+    pool: ReplicatedPool<TokioPool>,
 }
 
-pub async fn signup_user<T: AsRef<str>>(pool: Pool<PostgresConnectionManager<NoTls>>, user: T, password: T) -> Result<(), Error> {
-    let mut conn = pool.get()?;
-    let user = user.as_ref();
-    let password = password.as_ref();
-    // Watch out for SQL Injection
-    let query = format!("INSERT INTO tbl_users VALUES ('{}', '{}')", user, password);
-    let _ = conn.execute(query.as_str(), &[])?;
+impl PostgresUserRepository {
+    pub fn new(pool: ReplicatedPool<TokioPool>) -> Self {
+        Self { pool }
+    }
+
+    /// The primary pool
+    pub fn pool(&self) -> &TokioPool {
+        self.pool.primary()
+    }
+
+    /// The primary and its replicas, for other repositories sharing these tables
+    pub(crate) fn replicated(&self) -> &ReplicatedPool<TokioPool> {
+        &self.pool
+    }
+
+    /// The account row, `None` if the user does not exist
+    async fn select_account(conn: &Client, user: &str) -> Result<Option<Account>, Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Lookup failed for user: {}", user), e);
+        let statement = conn.prepare(
+            "SELECT user_id, user_password, disabled, locked, failed_attempts, created_at, last_login \
+             FROM tbl_users WHERE user_id = $1"
+        ).await.map_err(failed)?;
+        let rs = conn.query(&statement, &[&user]).await.map_err(failed)?;
+        let account = rs.into_iter().next().map(|row| Account {
+            user_id: row.get(0),
+            password: row.get(1),
+            disabled: row.get(2),
+            locked: row.get(3),
+            failed_attempts: row.get(4),
+            created_at: row.get(5),
+            last_login: row.get(6),
+        });
+        Ok(account)
+    }
+
+    /// Run an UPDATE on a single user, `$1` is the user
+    async fn update_user(&self, user: &str, sql: &str, value: bool) -> Result<(), Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Update failed for user: {}", user), e);
+        let conn = self.pool.write().await?;
+        let statement = conn.prepare(sql).await.map_err(failed)?;
+        let updated = conn.execute(&statement, &[&user, &value]).await.map_err(failed)?;
+        if updated == 0 {
+            let msg = format!("Update failed for user: {}", user);
+            let reason = "User does not exist".to_string();
+            return Err(NotFound(msg, reason).into());
+        };
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserRepository for PostgresUserRepository {
+    async fn signup_user(&self, user: &str, password: &str) -> Result<(), Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Signup failed for user: {}", user), e);
+        let created_at = compute_timestamp_in_seconds();
+        let conn = self.pool.write().await?;
+        let statement = conn.prepare(
+            "INSERT INTO tbl_users (user_id, user_password, created_at) VALUES ($1, $2, $3)"
+        ).await.map_err(failed)?;
+        let _ = conn.execute(&statement, &[&user, &password, &created_at]).await.map_err(failed)?;
+        Ok(())
+    }
+
+    async fn resolve_account(&self, user: &str) -> Result<Account, Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Lookup failed for user: {}", user), e);
+        // Logins decide on the lock, the failed attempts and the hash, a lagging replica would let a locked account in
+        let conn = self.pool.write().await?;
+        let statement = conn.prepare(
+            "SELECT user_id, user_password, disabled, locked, failed_attempts, created_at, last_login \
+             FROM tbl_users WHERE user_id = $1"
+        ).await.map_err(failed)?;
+        let rs = conn.query(&statement, &[&user]).await.map_err(failed)?;
+        if let Some(row) = rs.into_iter().next() {
+            let account = Account {
+                user_id: row.get(0),
+                password: row.get(1),
+                disabled: row.get(2),
+                locked: row.get(3),
+                failed_attempts: row.get(4),
+                created_at: row.get(5),
+                last_login: row.get(6),
+            };
+            return Ok(account);
+        };
+        let msg = format!("Lookup failed for user: {}", user);
+        let reason = "User does not exist".to_string();
+        Err(NotFound(msg, reason).into())
+    }
+
+    async fn update_password(&self, user: &str, old_password: &str, new_password: &str) -> Result<bool, Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Password update failed for user: {}", user), e);
+        let conn = self.pool.write().await?;
+        let statement = conn.prepare(
+            "UPDATE tbl_users SET user_password = $3 WHERE user_id = $1 AND user_password = $2"
+        ).await.map_err(failed)?;
+        let updated = conn.execute(&statement, &[&user, &old_password, &new_password]).await.map_err(failed)?;
+        Ok(updated == 1)
+    }
+
+    async fn record_login_success(&self, user: &str) -> Result<(), Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Login bookkeeping failed for user: {}", user), e);
+        let now = compute_timestamp_in_seconds();
+        let conn = self.pool.write().await?;
+        let statement = conn.prepare(
+            "UPDATE tbl_users SET failed_attempts = 0, last_login = $2 WHERE user_id = $1"
+        ).await.map_err(failed)?;
+        let _ = conn.execute(&statement, &[&user, &now]).await.map_err(failed)?;
+        Ok(())
+    }
+
+    async fn record_login_failure(&self, user: &str, max_failed_attempts: i32) -> Result<bool, Error> {
+        let failed = |e| DatabaseErrors::from_postgres(format!("Login bookkeeping failed for user: {}", user), e);
+        let conn = self.pool.write().await?;
+        // Single statement, so concurrent failures can't lose a count
+        let statement = conn.prepare(
+            "UPDATE tbl_users SET failed_attempts = failed_attempts + 1, \
+             locked = locked OR failed_attempts + 1 >= $2 \
+             WHERE user_id = $1 RETURNING locked"
+        ).await.map_err(failed)?;
+        let rs = conn.query(&statement, &[&user, &max_failed_attempts]).await.map_err(failed)?;
+        let locked = rs.into_iter().next().map_or(false, |row| row.get(0));
+        Ok(locked)
+    }
+
+    async fn set_disabled(&self, user: &str, disabled: bool) -> Result<(), Error> {
+        self.update_user(user, "UPDATE tbl_users SET disabled = $2 WHERE user_id = $1", disabled).await
+    }
+
+    async fn set_locked(&self, user: &str, locked: bool) -> Result<(), Error> {
+        self.update_user(
+            user,
+            "UPDATE tbl_users SET locked = $2, \
+             failed_attempts = CASE WHEN $2 THEN failed_attempts ELSE 0 END \
+             WHERE user_id = $1",
+            locked,
+        ).await
+    }
+}
+
+/// Shared `UserAuthentication::check_user_valid` logic for vaults backed by a `UserRepository`
+///
+/// Locked and disabled accounts are only reported once the password verified,
+/// so the state of an account is not revealed to a guesser; that state is read with `resolve_account_state`.
+/// Failed attempts are counted and lock the account after `MAX_FAILED_ATTEMPTS`.
+/// A hash made with other parameters than `hasher` uses now (see `password::needs_rehash`) is replaced after a successful login,
+/// `params` keeps those parameters between logins.
+pub async fn authenticate_user<U, H, P>(repository: &U, hasher: &P, params: &CurrentParams, user: &str, password: &str) -> Result<Account, Error>
+    where U: UserRepository, H: Default, P: PasswordHasher<H>
+{
+    let msg = "Login Failed".to_string();
+    let account = repository.resolve_account(user).await;
+    if let Err(e) = account {
+        if let Some(NotFound(_, _)) = e.downcast_ref::<DatabaseErrors>() {
+            return Err(LoginFailed::InvalidPassword(msg, REASON_INVALID_CREDENTIALS.to_string()).into());
+        };
+        return Err(e);
+    };
+    let account = account.ok().unwrap();
+    if !hasher.verify_user_password(user, password, account.password.as_str())? {
+        if let Err(e) = repository.record_login_failure(user, MAX_FAILED_ATTEMPTS).await {
+            eprintln!("{}", e.to_string());
+        };
+        return Err(LoginFailed::InvalidPassword(msg, REASON_INVALID_CREDENTIALS.to_string()).into());
+    };
+    let state = repository.resolve_account_state(user).await?;
+    // The password changed since the account was read
+    if state.password != account.password && !hasher.verify_user_password(user, password, state.password.as_str())? {
+        return Err(LoginFailed::InvalidPassword(msg, REASON_INVALID_CREDENTIALS.to_string()).into());
+    };
+    if state.locked {
+        return Err(LoginFailed::InvalidPassword(msg, REASON_ACCOUNT_LOCKED.to_string()).into());
+    };
+    if state.disabled {
+        return Err(LoginFailed::InvalidPassword(msg, REASON_ACCOUNT_DISABLED.to_string()).into());
+    };
+    repository.record_login_success(user).await?;
+    // The old hash still verifies, so a failed upgrade must not fail the login
+    let rehashed = match params.get(hasher) {
+        Ok(current) if needs_rehash(account.password.as_str(), current) => {
+            rehash_password(repository, hasher, &account, password).await
+        }
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = rehashed {
+        eprintln!("Password rehash failed for user: {} Reason: {}", user, e.to_string());
+    };
+    Ok(account)
+}
+
+async fn rehash_password<U, H, P>(repository: &U, hasher: &P, account: &Account, password: &str) -> Result<(), Error>
+    where U: UserRepository, H: Default, P: PasswordHasher<H>
+{
+    let user = account.user_id.as_str();
+    let new_password = hasher.hash_user_password(user, password)?;
+    let _ = repository.update_password(user, account.password.as_str(), new_password.as_str()).await?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// `UserAuthentication` backed by a `UserRepository`, the user lookup of a `vault::ComposableVault`.
+/// The server side session holds the user, keyed by its digest, and whatever the enricher adds
+pub struct RepositoryAuthentication<U> {
+    repository: U,
+    hasher: ArgonPasswordHasher,
+    params: CurrentParams,
+    enricher: Option<Box<dyn SessionEnricher + Send + Sync>>,
+}
+
+impl<U> RepositoryAuthentication<U> {
+    /// `hasher` must use the vault's password hashing secret, see `vault::VaultBuilder::password_hasher`
+    pub fn new(repository: U, hasher: ArgonPasswordHasher) -> Self {
+        Self { repository, hasher, params: CurrentParams::default(), enricher: None }
+    }
+
+    /// Runs after the password verified; a failing enricher fails the login
+    pub fn with_enricher(mut self, enricher: Box<dyn SessionEnricher + Send + Sync>) -> Self {
+        self.enricher = Some(enricher);
+        self
+    }
+
+    pub fn repository(&self) -> &U {
+        &self.repository
+    }
+}
+
+#[async_trait]
+impl<U> UserAuthentication for RepositoryAuthentication<U>
+    where U: UserRepository + Send + Sync {
+    async fn check_user_valid(&mut self, user: &str, password: &str) -> Result<Option<Session>, Error> {
+        let _ = authenticate_user(&self.repository, &self.hasher, &self.params, user, password).await?;
+        let reference = digest::<_, DefaultHasher>(user.as_bytes());
+        let mut server = HashMap::new();
+        server.insert(reference, user.as_bytes().to_vec());
+        let mut client = HashMap::new();
+        if let Some(enricher) = &self.enricher {
+            enricher.enrich(user, &mut client, &mut server).await?;
+        };
+        let client = if client.is_empty() { None } else { Some(client) };
+        let session = Session::new(client, Some(server));
+        Ok(Some(session))
+    }
+}
+
+#[cfg(test)]
+mod users_setup {
+    use super::*;
+    use crate::database::errors::DatabaseErrors::UniqueViolation;
+    use std::sync::Mutex;
+
+    /// Keeps the accounts in a map, failing like `PostgresUserRepository` does
+    #[derive(Default)]
+    struct Users(Mutex<HashMap<String, Account>>);
+
+    fn missing(user: &str) -> Error {
+        NotFound(format!("Lookup failed for user: {}", user), "User does not exist".to_string()).into()
+    }
+
+    #[async_trait]
+    impl UserRepository for Users {
+        async fn signup_user(&self, user: &str, password: &str) -> Result<(), Error> {
+            let mut users = self.0.lock().unwrap();
+            if users.contains_key(user) {
+                let cause = failure::err_msg("duplicate key value violates unique constraint");
+                return Err(UniqueViolation(format!("Signup failed for user: {}", user), cause).into());
+            };
+            let account = Account {
+                user_id: user.to_string(),
+                password: password.to_string(),
+                disabled: false,
+                locked: false,
+                failed_attempts: 0,
+                created_at: compute_timestamp_in_seconds(),
+                last_login: None,
+            };
+            users.insert(user.to_string(), account);
+            Ok(())
+        }
+
+        async fn resolve_account(&self, user: &str) -> Result<Account, Error> {
+            self.0.lock().unwrap().get(user).cloned().ok_or_else(|| missing(user))
+        }
+
+        async fn update_password(&self, user: &str, old_password: &str, new_password: &str) -> Result<bool, Error> {
+            let mut users = self.0.lock().unwrap();
+            let account = users.get_mut(user).ok_or_else(|| missing(user))?;
+            if account.password != old_password {
+                return Ok(false);
+            };
+            account.password = new_password.to_string();
+            Ok(true)
+        }
+
+        async fn record_login_success(&self, _: &str) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn record_login_failure(&self, _: &str, _: i32) -> Result<bool, Error> {
+            Ok(false)
+        }
+
+        async fn set_disabled(&self, user: &str, disabled: bool) -> Result<(), Error> {
+            self.0.lock().unwrap().get_mut(user).ok_or_else(|| missing(user))?.disabled = disabled;
+            Ok(())
+        }
+
+        async fn set_locked(&self, user: &str, locked: bool) -> Result<(), Error> {
+            self.0.lock().unwrap().get_mut(user).ok_or_else(|| missing(user))?.locked = locked;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn user_errors_are_typed() {
+        let users = Users::default();
+        block_on(users.signup_user("john_doe", "hash")).unwrap();
+        let e = block_on(users.signup_user("john_doe", "other")).err().unwrap();
+        match e.downcast_ref::<DatabaseErrors>() {
+            Some(UniqueViolation(_, _)) => {}
+            _ => panic!("Expected UniqueViolation, got: {}", e),
+        };
+        assert_eq!(block_on(users.resolve_password_for_user("john_doe")).unwrap(), "hash");
+
+        let e = block_on(users.resolve_password_for_user("jane_doe")).err().unwrap();
+        match e.downcast_ref::<DatabaseErrors>() {
+            Some(NotFound(_, _)) => {}
+            _ => panic!("Expected NotFound, got: {}", e),
+        };
+    }
+
+    #[test]
+    fn missing_user_fails_the_login() {
+        let users = Users::default();
+        let hasher = ArgonPasswordHasher::default();
+        let params = CurrentParams::default();
+        let e = block_on(authenticate_user(&users, &hasher, &params, "jane_doe", "jane")).err().unwrap();
+        match e.downcast_ref::<LoginFailed>() {
+            Some(LoginFailed::InvalidPassword(_, reason)) => assert_eq!(reason, REASON_INVALID_CREDENTIALS),
+            _ => panic!("Expected LoginFailed::InvalidPassword, got: {}", e),
+        };
+    }
+
+    /// A primary and a replica that missed the latest writes
+    #[derive(Default)]
+    struct Lagging {
+        primary: Users,
+        replica: Users,
+    }
+
+    #[async_trait]
+    impl UserRepository for Lagging {
+        async fn signup_user(&self, user: &str, password: &str) -> Result<(), Error> {
+            self.primary.signup_user(user, password).await
+        }
+
+        async fn resolve_account(&self, user: &str) -> Result<Account, Error> {
+            self.replica.resolve_account(user).await
+        }
+
+        async fn resolve_account_state(&self, user: &str) -> Result<Account, Error> {
+            self.primary.resolve_account(user).await
+        }
+
+        async fn update_password(&self, user: &str, old_password: &str, new_password: &str) -> Result<bool, Error> {
+            self.primary.update_password(user, old_password, new_password).await
+        }
+
+        async fn record_login_success(&self, user: &str) -> Result<(), Error> {
+            self.primary.record_login_success(user).await
+        }
+
+        async fn record_login_failure(&self, user: &str, max_failed_attempts: i32) -> Result<bool, Error> {
+            self.primary.record_login_failure(user, max_failed_attempts).await
+        }
+
+        async fn set_disabled(&self, user: &str, disabled: bool) -> Result<(), Error> {
+            self.primary.set_disabled(user, disabled).await
+        }
+
+        async fn set_locked(&self, user: &str, locked: bool) -> Result<(), Error> {
+            self.primary.set_locked(user, locked).await
+        }
+    }
+
+    struct PlainHasher;
+
+    impl PasswordHasher<String> for PlainHasher {
+        fn hash_user_password<T: AsRef<str>>(&self, _: T, password: T) -> Result<String, Error> {
+            Ok(password.as_ref().to_string())
+        }
+
+        fn verify_user_password<T: AsRef<str>>(&self, _: T, password: T, hash: T) -> Result<bool, Error> {
+            Ok(password.as_ref() == hash.as_ref())
+        }
+    }
+
+    #[test]
+    fn account_state_comes_from_the_primary() {
+        let reason = |result: Result<Account, Error>| match result.err().unwrap().downcast::<LoginFailed>() {
+            Ok(LoginFailed::InvalidPassword(_, reason)) => reason,
+            _ => panic!("Expected LoginFailed::InvalidPassword"),
+        };
+        let users = Lagging::default();
+        let params = CurrentParams::default();
+        block_on(users.signup_user("john_doe", "john")).unwrap();
+        block_on(users.replica.signup_user("john_doe", "john")).unwrap();
+        assert!(block_on(authenticate_user(&users, &PlainHasher, &params, "john_doe", "john")).is_ok());
+
+        block_on(users.set_locked("john_doe", true)).unwrap();
+        let result = block_on(authenticate_user(&users, &PlainHasher, &params, "john_doe", "john"));
+        assert_eq!(reason(result), REASON_ACCOUNT_LOCKED);
+        block_on(users.set_locked("john_doe", false)).unwrap();
+
+        assert!(block_on(users.update_password("john_doe", "john", "other")).unwrap());
+        let result = block_on(authenticate_user(&users, &PlainHasher, &params, "john_doe", "john"));
+        assert_eq!(reason(result), REASON_INVALID_CREDENTIALS);
+    }
+}
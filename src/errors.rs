@@ -0,0 +1,164 @@
+use failure::{Error, Fail};
+use serde::Serialize;
+
+use actix_http::{Response, ResponseError};
+use actix_http::http::StatusCode;
+use jwtvault::errors::{LoginFailed, TokenErrors};
+
+use crate::database::errors::DatabaseErrors;
+
+#[derive(Debug, Fail)]
+pub enum FixtureErrors {
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    UnreadableFile(String, String),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    UnsupportedFormat(String, String),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    InvalidFile(String, String),
+}
+
+#[derive(Debug, Fail)]
+pub enum ConfigErrors {
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    UnreadableFile(String, String),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    InvalidFile(String, String),
+    /// A file key, flag or argument that does not name a setting
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    UnknownSetting(String, String),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    InvalidSetting(String, String),
+}
+
+#[derive(Debug, Fail)]
+pub enum StoreErrors {
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    UnreadableStore(String, String),
+    /// A record in the middle of a log or snapshot could not be parsed
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    CorruptStore(String, String),
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    WriteFailed(String, String),
+    /// The session server could not be reached, or the URL is invalid
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    Unreachable(String, String),
+    /// The session server answered with an error or an unexpected reply
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    CommandFailed(String, String),
+}
+
+#[derive(Debug, Fail)]
+pub enum AccessErrors {
+    /// The session lacks a permission the route requires
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    PermissionDenied(String, String),
+}
+
+#[derive(Debug, Fail)]
+pub enum SessionErrors {
+    /// The user holds as many sessions as allowed and the limit rejects new logins
+    #[fail(display = "{}. Reason: {}", 0, 1)]
+    LimitReached(String, String),
+}
+
+/// JSON body of every error response
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ErrorBody {
+    pub error: String,
+    pub message: String,
+}
+
+impl ErrorBody {
+    pub(crate) fn response(status: StatusCode, error: &str) -> Response {
+        let body = ErrorBody { error: error.to_string(), message: public_message(error).to_string() };
+        Response::build(status).json(body)
+    }
+}
+
+/// Message sent to clients for an error kind
+///
+/// Error details name users, tables, constraints and hosts, so clients only get a fixed text per kind.
+pub fn public_message(kind: &str) -> &'static str {
+    match kind {
+        "unique_violation" => "The resource already exists",
+        "not_found" => "The resource does not exist",
+        "constraint_violation" => "The request refers to a resource that does not exist",
+        "timeout" => "The database did not answer in time",
+        "connection_failed" | "connection_attempt_failed" | "connection_retries_exhausted"
+        | "pool_exhausted" | "replica_unavailable" => "The database is unavailable",
+        "permission_denied" => "The session lacks a permission this route requires",
+        "session_limit_reached" => "The user holds as many sessions as allowed",
+        "session_store_unavailable" => "The session storage is unavailable",
+        "login_failed" => "Login failed",
+        "invalid_token" => "The token is invalid or expired",
+        _ => "Internal server error",
+    }
+}
+
+/// Turn any error raised by the vault or the database into a JSON response
+///
+/// Clients get the kind and its `public_message`, the error itself is logged.
+pub fn error_response(e: &Error) -> Response {
+    if let Some(e) = e.downcast_ref::<DatabaseErrors>() {
+        return e.error_response();
+    };
+    let (status, error) = if let Some(AccessErrors::PermissionDenied(_, _)) = e.downcast_ref::<AccessErrors>() {
+        (StatusCode::FORBIDDEN, "permission_denied")
+    } else if let Some(SessionErrors::LimitReached(_, _)) = e.downcast_ref::<SessionErrors>() {
+        (StatusCode::CONFLICT, "session_limit_reached")
+    } else if e.downcast_ref::<StoreErrors>().is_some() {
+        (StatusCode::SERVICE_UNAVAILABLE, "session_store_unavailable")
+    } else if let Some(e) = e.downcast_ref::<LoginFailed>() {
+        match e {
+            LoginFailed::PasswordHashingFailed(_, _) | LoginFailed::PasswordVerificationFailed(_, _) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "password_hashing_failed")
+            }
+            _ => (StatusCode::UNAUTHORIZED, "login_failed"),
+        }
+    } else if let Some(e) = e.downcast_ref::<TokenErrors>() {
+        match e {
+            TokenErrors::TokenEncodingFailed(_, _) => (StatusCode::INTERNAL_SERVER_ERROR, "token_encoding_failed"),
+            _ => (StatusCode::UNAUTHORIZED, "invalid_token"),
+        }
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal_error")
+    };
+    eprintln!("{}", e);
+    ErrorBody::response(status, error)
+}
+
+#[cfg(test)]
+mod errors {
+    use super::*;
+
+    #[test]
+    fn error_response_validation() {
+        let e: Error = DatabaseErrors::NotFound("Lookup failed".to_string(), "missing".to_string()).into();
+        assert_eq!(error_response(&e).status(), StatusCode::NOT_FOUND);
+
+        let e: Error = LoginFailed::InvalidPassword("Login Failed".to_string(), "Invalid userid/password".to_string()).into();
+        assert_eq!(error_response(&e).status(), StatusCode::UNAUTHORIZED);
+
+        let e: Error = AccessErrors::PermissionDenied("Access denied".to_string(), "Missing permissions: execute".to_string()).into();
+        assert_eq!(error_response(&e).status(), StatusCode::FORBIDDEN);
+
+        let e: Error = SessionErrors::LimitReached("Login Failed".to_string(), "Sessions: 2 of 2".to_string()).into();
+        assert_eq!(error_response(&e).status(), StatusCode::CONFLICT);
+
+        let e: Error = StoreErrors::WriteFailed("Login Failed".to_string(), "Session could not be stored".to_string()).into();
+        assert_eq!(error_response(&e).status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let e = failure::err_msg("unexpected");
+        assert_eq!(error_response(&e).status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn public_message_validation() {
+        let e = DatabaseErrors::PoolExhausted("No connection to db.internal:5432".to_string(), failure::err_msg("busy"));
+        assert_eq!(public_message(e.kind()), "The database is unavailable");
+        assert!(!public_message(e.kind()).contains("db.internal"));
+        assert_eq!(public_message("constraint_violation"), "The request refers to a resource that does not exist");
+        assert_eq!(public_message("query_failed"), "Internal server error");
+        assert_eq!(public_message("migration_mismatch"), "Internal server error");
+    }
+}
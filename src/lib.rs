@@ -1 +1,7 @@
-pub mod database;
\ No newline at end of file
+pub mod database;
+pub mod config;
+pub mod vault;
+pub mod owner;
+pub mod stores;
+pub mod access;
+pub mod errors;
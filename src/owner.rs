@@ -0,0 +1,79 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Label mixed into the password hashing secret, so the MAC key is not the secret itself
+const OWNER_KEY_LABEL: &[u8] = b"jwtvault token owner";
+
+/// MACs the user ids a vault puts inside its tokens, see `vault::VaultBuilder::seal_token_owner`
+///
+/// A sealed id is `<user>.<hex HMAC-SHA256 of user>`: readable, but only this server can produce it,
+/// and `open` compares the MAC in constant time.
+#[derive(Clone)]
+pub struct TokenOwnerKey {
+    key: Vec<u8>,
+}
+
+impl TokenOwnerKey {
+    pub fn new<T: AsRef<[u8]>>(key: T) -> Self {
+        Self { key: key.as_ref().to_vec() }
+    }
+
+    /// Derive the key from a server secret, e.g. the password hashing secret
+    pub fn derive<T: AsRef<[u8]>>(secret: T) -> Self {
+        let mut mac = HmacSha256::new_varkey(secret.as_ref()).expect("HMAC accepts keys of any length");
+        mac.input(OWNER_KEY_LABEL);
+        Self::new(mac.result().code())
+    }
+
+    fn mac(&self, user: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_varkey(self.key.as_slice()).expect("HMAC accepts keys of any length");
+        mac.input(user.as_bytes());
+        mac
+    }
+
+    pub fn seal(&self, user: &str) -> String {
+        let code = self.mac(user).result().code();
+        let code = code.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        format!("{}.{}", user, code)
+    }
+
+    /// The user inside `sealed`, if this key sealed it
+    pub fn open<'a>(&self, sealed: &'a str) -> Option<&'a str> {
+        let index = sealed.rfind('.')?;
+        let (user, code) = (&sealed[..index], &sealed[index + 1..]);
+        if code.len() % 2 != 0 || !code.is_ascii() {
+            return None;
+        };
+        let code = (0..code.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&code[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        self.mac(user).verify(code.as_slice()).ok()?;
+        Some(user)
+    }
+}
+
+#[cfg(test)]
+mod owner {
+    use super::*;
+
+    #[test]
+    fn sealed_user_validation() {
+        let key = TokenOwnerKey::derive("secret");
+        let sealed = key.seal("john.doe");
+        assert!(sealed.starts_with("john.doe."));
+        assert_eq!(sealed.len(), "john.doe.".len() + 64);
+        assert_eq!(key.open(sealed.as_str()), Some("john.doe"));
+        assert_eq!(key.seal("john.doe"), sealed);
+
+        // Another user's MAC, another key, or a plain user do not open
+        let code = &sealed["john.doe.".len()..];
+        assert_eq!(key.open(format!("jane_doe.{}", code).as_str()), None);
+        assert_eq!(TokenOwnerKey::derive("other").open(sealed.as_str()), None);
+        assert_eq!(TokenOwnerKey::new("secret").open(sealed.as_str()), None);
+        assert_eq!(key.open("john.doe"), None);
+        assert_eq!(key.open("john_doe"), None);
+        assert_eq!(key.open(&sealed[..sealed.len() - 1]), None);
+    }
+}
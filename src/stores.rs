@@ -0,0 +1,8 @@
+pub mod memory;
+pub mod sweeper;
+pub mod file;
+pub mod sharded;
+pub mod redis;
+pub mod index;
+pub mod pins;
+pub mod expiry;
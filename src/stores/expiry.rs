@@ -0,0 +1,22 @@
+use jwtvault::prelude::*;
+
+
+/// Session storage which can keep an entry until a given time instead of for the expiry it was built with
+///
+/// The expiry passed to `Workflow::login` may differ from the one a store was built with; the vault keeps
+/// a session until the `exp` of the refresh token it issued (see `vault::ComposableVault`).
+#[async_trait]
+pub trait StoreUntil: Persistence {
+    /// `store` with an explicit expiry (seconds since epoch); `None` never expires
+    async fn store_until(&mut self, key: u64, value: String, expires_at: Option<i64>);
+}
+
+/// Expiry a store records for the `exp` of a refresh token, `None` for a token that never expires
+pub fn refresh_expiry(exp: i64) -> Option<i64> {
+    Some(exp).filter(|exp| *exp != DEFAULT_REFRESH_WITH_NO_EXPIRY)
+}
+
+/// Expiry a store built with `expiry_in_seconds` records for an entry stored now
+pub fn expires_at(expiry_in_seconds: Option<i64>) -> Option<i64> {
+    expiry_in_seconds.map(|n| compute_timestamp_in_seconds() + n)
+}
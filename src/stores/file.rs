@@ -0,0 +1,335 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use failure::Error;
+use jwtvault::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::StoreErrors::{UnreadableStore, CorruptStore, WriteFailed};
+use crate::stores::expiry::StoreUntil;
+use crate::stores::memory::MemoryPersistence;
+use crate::stores::sweeper::Sweep;
+
+pub const SNAPSHOT_FILE: &str = "sessions.snapshot";
+pub const LOG_FILE: &str = "sessions.log";
+
+/// When `FilePersistence` forces its log to disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsyncPolicy {
+    /// After every `store` and `remove`
+    Always,
+    /// After every n-th `store` or `remove`; a crash loses at most n - 1 of them
+    Every(usize),
+    /// Left to the operating system
+    Never,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    /// `always`, `never` or a number of writes
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "never" => Ok(FsyncPolicy::Never),
+            n => match n.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(FsyncPolicy::Every(n)),
+                _ => Err(format!("Expected always, never or a number of writes: {}", s)),
+            }
+        }
+    }
+}
+
+/// One line of the snapshot or the log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Record {
+    Store { key: u64, value: String, expires_at: Option<i64> },
+    Remove { key: u64 },
+}
+
+/// Session storage which survives restarts without a database
+///
+/// Every `store` and `remove` is appended to `sessions.log` in `dir` and kept in a `MemoryPersistence`
+/// that `load` reads from. After `compact_after` log records the live sessions are written to
+/// `sessions.snapshot` and the log starts over. `open` replays the snapshot, then the log.
+/// Replaying a record twice is harmless, so a crash between writing the snapshot and truncating the log loses nothing.
+pub struct FilePersistence {
+    dir: PathBuf,
+    log: File,
+    cache: MemoryPersistence,
+    fsync: FsyncPolicy,
+    compact_after: usize,
+    logged: usize,
+    unsynced: usize,
+}
+
+impl FilePersistence {
+    /// `expiry_in_seconds` should match the refresh token expiry; `None` never expires
+    pub fn open<P: AsRef<Path>>(dir: P, expiry_in_seconds: Option<i64>, fsync: FsyncPolicy, compact_after: usize) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|e| {
+            UnreadableStore(format!("Unable to create session store: {}", dir.display()), e.to_string())
+        })?;
+        let mut cache = MemoryPersistence::new(expiry_in_seconds);
+        let _ = replay(&dir.join(SNAPSHOT_FILE), &mut cache)?;
+        let (logged, terminated) = replay(&dir.join(LOG_FILE), &mut cache)?;
+        let log = OpenOptions::new().create(true).append(true).open(dir.join(LOG_FILE)).map_err(|e| {
+            UnreadableStore(format!("Unable to open session log: {}", dir.display()), e.to_string())
+        })?;
+        let mut store = Self { dir, log, cache, fsync, compact_after: compact_after.max(1), logged, unsynced: 0 };
+        // Appending after an unterminated line would glue two records together
+        if store.logged >= store.compact_after || !terminated {
+            store.compact()?;
+        };
+        Ok(store)
+    }
+
+    /// Write the live sessions to a new snapshot and empty the log
+    pub fn compact(&mut self) -> Result<(), Error> {
+        let failed = |e: std::io::Error| WriteFailed(format!("Unable to compact session store: {}", self.dir.display()), e.to_string());
+        let snapshot = self.dir.join(SNAPSHOT_FILE);
+        let staged = self.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        let mut data = String::new();
+        for (key, value, expires_at) in self.cache.entries() {
+            let record = Record::Store { key, value: value.clone(), expires_at };
+            data.push_str(serde_json::to_string(&record)?.as_str());
+            data.push('\n');
+        }
+        let mut file = File::create(&staged).map_err(failed)?;
+        file.write_all(data.as_bytes()).map_err(failed)?;
+        file.sync_all().map_err(failed)?;
+        fs::rename(&staged, &snapshot).map_err(failed)?;
+        // Make the rename itself durable before the log is emptied
+        if let Ok(dir) = File::open(&self.dir) {
+            let _ = dir.sync_all();
+        };
+        self.log.set_len(0).map_err(failed)?;
+        self.log.sync_all().map_err(failed)?;
+        self.logged = 0;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Force the log to disk regardless of the fsync policy
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.log.sync_data().map_err(|e| {
+            WriteFailed(format!("Unable to sync session log: {}", self.dir.display()), e.to_string())
+        })?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Sessions held, including expired ones not swept yet
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    fn append(&mut self, record: &Record) -> Result<(), Error> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        // One write per record, so a crash can only tear the last line
+        self.log.write_all(line.as_bytes()).map_err(|e| {
+            WriteFailed(format!("Unable to append to session log: {}", self.dir.display()), e.to_string())
+        })?;
+        self.logged += 1;
+        self.unsynced += 1;
+        let due = match self.fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Every(n) => self.unsynced >= n,
+            FsyncPolicy::Never => false,
+        };
+        if due {
+            self.sync()?;
+        };
+        Ok(())
+    }
+
+    /// Compacts once `compact_after` records are logged; the snapshot is taken from the cache,
+    /// so call it after the cache has the record just appended
+    fn compact_if_due(&mut self) {
+        if self.logged < self.compact_after {
+            return;
+        };
+        if let Err(e) = self.compact() {
+            eprintln!("{}", e.to_string());
+        };
+    }
+}
+
+/// Apply every record in `path` to `cache`, returning the number of records and whether the file ends with a newline.
+/// A torn last line is what a crash during `append` leaves behind and is skipped
+fn replay(path: &Path, cache: &mut MemoryPersistence) -> Result<(usize, bool), Error> {
+    if !path.exists() {
+        return Ok((0, true));
+    };
+    let data = fs::read_to_string(path).map_err(|e| {
+        UnreadableStore(format!("Unable to read session store: {}", path.display()), e.to_string())
+    })?;
+    let lines: Vec<&str> = data.lines().filter(|line| !line.trim().is_empty()).collect();
+    let terminated = data.is_empty() || data.ends_with('\n');
+    let mut count = 0;
+    for (index, line) in lines.iter().enumerate() {
+        let record = match serde_json::from_str::<Record>(line) {
+            Ok(record) => record,
+            Err(e) if index + 1 == lines.len() && !terminated => {
+                eprintln!("Skipping torn record in {} Reason: {}", path.display(), e.to_string());
+                break;
+            }
+            Err(e) => {
+                let msg = format!("Invalid record {} in {}", index + 1, path.display());
+                return Err(CorruptStore(msg, e.to_string()).into());
+            }
+        };
+        match record {
+            Record::Store { key, value, expires_at } => cache.insert(key, value, expires_at),
+            Record::Remove { key } => { let _ = cache.take(key); }
+        };
+        count += 1;
+    }
+    Ok((count, terminated))
+}
+
+impl Sweep for FilePersistence {
+    /// Expired sessions stay in the log until the next compaction
+    fn sweep(&mut self) -> usize {
+        self.cache.sweep()
+    }
+}
+
+impl Drop for FilePersistence {
+    fn drop(&mut self) {
+        if self.unsynced > 0 {
+            let _ = self.log.sync_data();
+        };
+    }
+}
+
+#[async_trait]
+impl Persistence for FilePersistence {
+    async fn store(&mut self, key: u64, value: String) {
+        let expires_at = self.cache.expires_at();
+        self.store_until(key, value, expires_at).await
+    }
+
+    async fn load(&self, key: u64) -> Option<&String> {
+        self.cache.load(key).await
+    }
+
+    async fn remove(&mut self, key: u64) -> Option<String> {
+        let value = self.cache.remove(key).await;
+        if let Err(e) = self.append(&Record::Remove { key }) {
+            eprintln!("Unable to remove session: {} Reason: {}", key, e.to_string());
+        };
+        self.compact_if_due();
+        value
+    }
+}
+
+#[async_trait]
+impl StoreUntil for FilePersistence {
+    async fn store_until(&mut self, key: u64, value: String, expires_at: Option<i64>) {
+        let record = Record::Store { key, value: value.clone(), expires_at };
+        // A session that is not in the log is not handed out either, the vault then fails the login
+        if let Err(e) = self.append(&record) {
+            eprintln!("Unable to store session: {} Reason: {}", key, e.to_string());
+            return;
+        };
+        self.cache.insert(key, value, expires_at);
+        self.compact_if_due();
+    }
+}
+
+#[cfg(test)]
+mod file {
+    use super::*;
+    use std::env;
+
+    fn store_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn sessions_survive_reopen() {
+        let dir = store_dir("jwtvault_examples_file_reopen");
+        {
+            let mut store = FilePersistence::open(&dir, None, FsyncPolicy::Always, 100).unwrap();
+            block_on(store.store(1, "one".to_string()));
+            block_on(store.store(2, "two".to_string()));
+            block_on(store.store(1, "uno".to_string()));
+            let _ = block_on(store.remove(2));
+        }
+        let store = FilePersistence::open(&dir, None, FsyncPolicy::Always, 100).unwrap();
+        assert_eq!(block_on(store.load(1)), Some(&"uno".to_string()));
+        assert_eq!(block_on(store.load(2)), None);
+        assert_eq!(store.logged, 4);
+    }
+
+    #[test]
+    fn log_is_compacted_into_snapshot() {
+        let dir = store_dir("jwtvault_examples_file_compact");
+        {
+            let mut store = FilePersistence::open(&dir, None, FsyncPolicy::Every(2), 3).unwrap();
+            block_on(store.store(1, "one".to_string()));
+            block_on(store.store(2, "two".to_string()));
+            let _ = block_on(store.remove(1));
+            assert_eq!(store.logged, 0);
+            block_on(store.store(3, "three".to_string()));
+        }
+        assert_eq!(fs::read_to_string(dir.join(SNAPSHOT_FILE)).unwrap().lines().count(), 1);
+        assert_eq!(fs::read_to_string(dir.join(LOG_FILE)).unwrap().lines().count(), 1);
+        let store = FilePersistence::open(&dir, None, FsyncPolicy::Never, 3).unwrap();
+        assert_eq!(block_on(store.load(1)), None);
+        assert_eq!(block_on(store.load(2)), Some(&"two".to_string()));
+        assert_eq!(block_on(store.load(3)), Some(&"three".to_string()));
+    }
+
+    #[test]
+    fn torn_last_record_is_skipped() {
+        let dir = store_dir("jwtvault_examples_file_torn");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(LOG_FILE), "{\"op\":\"store\",\"key\":1,\"value\":\"one\",\"expires_at\":null}\n{\"op\":\"sto").unwrap();
+        let mut store = FilePersistence::open(&dir, None, FsyncPolicy::Always, 100).unwrap();
+        assert_eq!(block_on(store.load(1)), Some(&"one".to_string()));
+        block_on(store.store(2, "two".to_string()));
+        drop(store);
+        let store = FilePersistence::open(&dir, None, FsyncPolicy::Always, 100).unwrap();
+        assert_eq!(block_on(store.load(1)), Some(&"one".to_string()));
+        assert_eq!(block_on(store.load(2)), Some(&"two".to_string()));
+        drop(store);
+
+        fs::write(dir.join(LOG_FILE), "{\"op\":\"sto\n{\"op\":\"remove\",\"key\":1}\n").unwrap();
+        let err = FilePersistence::open(&dir, None, FsyncPolicy::Always, 100).err().unwrap();
+        assert!(err.to_string().starts_with("Invalid record 1 in"));
+    }
+
+    #[test]
+    fn failed_append_is_not_cached() {
+        let dir = store_dir("jwtvault_examples_file_failed_append");
+        let mut store = FilePersistence::open(&dir, None, FsyncPolicy::Always, 100).unwrap();
+        block_on(store.store(1, "one".to_string()));
+        // Writes to a read only handle fail
+        store.log = File::open(dir.join(LOG_FILE)).unwrap();
+        block_on(store.store(2, "two".to_string()));
+        assert_eq!(block_on(store.load(1)), Some(&"one".to_string()));
+        assert_eq!(block_on(store.load(2)), None);
+        assert_eq!(store.logged, 1);
+    }
+
+    #[test]
+    fn fsync_policy_from_str() {
+        assert_eq!("always".parse::<FsyncPolicy>(), Ok(FsyncPolicy::Always));
+        assert_eq!("Never".parse::<FsyncPolicy>(), Ok(FsyncPolicy::Never));
+        assert_eq!("16".parse::<FsyncPolicy>(), Ok(FsyncPolicy::Every(16)));
+        assert!("0".parse::<FsyncPolicy>().is_err());
+        assert!("sometimes".parse::<FsyncPolicy>().is_err());
+    }
+}
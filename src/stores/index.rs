@@ -0,0 +1,200 @@
+use std::collections::hash_map::DefaultHasher;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures::lock::{Mutex, MutexGuard};
+use jwtvault::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::stores::expiry::{refresh_expiry, StoreUntil};
+
+/// What a login beyond `SessionLimit::max_sessions` does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LimitPolicy {
+    /// Ends the user's oldest sessions to make room
+    EvictOldest,
+    /// Fails the login with `SessionErrors::LimitReached`
+    Reject,
+}
+
+impl FromStr for LimitPolicy {
+    type Err = String;
+
+    /// `evict-oldest` or `reject`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "evict-oldest" => Ok(LimitPolicy::EvictOldest),
+            "reject" => Ok(LimitPolicy::Reject),
+            _ => Err(format!("Expected evict-oldest or reject: {}", s)),
+        }
+    }
+}
+
+/// Concurrent sessions a user may hold, see `vault::VaultBuilder::track_sessions`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionLimit {
+    /// `None` is unlimited
+    pub max_sessions: Option<usize>,
+    pub policy: LimitPolicy,
+}
+
+impl Default for SessionLimit {
+    fn default() -> Self {
+        Self { max_sessions: None, policy: LimitPolicy::EvictOldest }
+    }
+}
+
+/// One login; times are seconds since epoch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub id: String,
+    pub issued_at: i64,
+    /// Expiry of the refresh token
+    pub expires_at: i64,
+}
+
+/// The sessions of one user, kept as a JSON list in the session storage itself
+///
+/// Works on top of any `Persistence`, next to the sessions it lists. Updates are read-modify-write,
+/// so they are made under the user's lock from `IndexLocks`: the clones of a vault share their locks and don't lose
+/// each other's entries. Vaults in other processes sharing the storage (e.g. postgres or redis) don't see those locks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionIndex {
+    key: u64,
+    sessions: Vec<SessionRecord>,
+}
+
+impl SessionIndex {
+    /// Key of the index of `user`; the prefix keeps it apart from the session keys
+    pub fn key(user: &str) -> u64 {
+        digest::<_, DefaultHasher>(format!("session.index:{}", user))
+    }
+
+    /// Sessions whose refresh token expired are left out; an unreadable index is treated as empty
+    pub async fn load<P: Persistence>(store: &P, user: &str) -> Self {
+        let key = Self::key(user);
+        let now = compute_timestamp_in_seconds();
+        let sessions = store.load(key).await
+            .and_then(|value| serde_json::from_str::<Vec<SessionRecord>>(value.as_str()).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|session| session.expires_at > now)
+            .collect();
+        Self { key, sessions }
+    }
+
+    /// An empty index is removed, any other is kept until its last session expires
+    pub async fn save<P: StoreUntil>(&self, store: &mut P) {
+        if self.sessions.is_empty() {
+            let _ = store.remove(self.key).await;
+            return;
+        };
+        let value = serde_json::to_string(&self.sessions).unwrap();
+        let expires_at = self.sessions.iter().map(|session| session.expires_at).max().and_then(refresh_expiry);
+        store.store_until(self.key, value, expires_at).await;
+    }
+
+    /// Oldest first
+    pub fn sessions(&self) -> &[SessionRecord] {
+        self.sessions.as_slice()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    pub fn insert(&mut self, session: SessionRecord) {
+        let index = self.sessions.iter().position(|s| s.issued_at > session.issued_at).unwrap_or(self.sessions.len());
+        self.sessions.insert(index, session);
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<SessionRecord> {
+        let index = self.sessions.iter().position(|s| s.id == id)?;
+        Some(self.sessions.remove(index))
+    }
+
+    pub fn pop_oldest(&mut self) -> Option<SessionRecord> {
+        if self.sessions.is_empty() {
+            return None;
+        };
+        Some(self.sessions.remove(0))
+    }
+
+    pub fn clear(&mut self) -> Vec<SessionRecord> {
+        self.sessions.drain(..).collect()
+    }
+}
+
+pub const DEFAULT_INDEX_LOCKS: usize = 16;
+
+/// Locks serializing the updates of session indexes, shared by every clone; users are spread over them by index key
+#[derive(Clone)]
+pub struct IndexLocks {
+    locks: Arc<Vec<Mutex<()>>>,
+}
+
+impl IndexLocks {
+    pub fn new(locks: usize) -> Self {
+        let locks = (0..locks.max(1)).map(|_| Mutex::new(())).collect::<Vec<_>>();
+        Self { locks: Arc::new(locks) }
+    }
+
+    /// Hold the guard from `SessionIndex::load` until `SessionIndex::save`; waits without blocking the thread
+    pub async fn lock(&self, user: &str) -> MutexGuard<'_, ()> {
+        let key = SessionIndex::key(user);
+        self.locks[(key % self.locks.len() as u64) as usize].lock().await
+    }
+}
+
+impl Default for IndexLocks {
+    fn default() -> Self {
+        Self::new(DEFAULT_INDEX_LOCKS)
+    }
+}
+
+#[cfg(test)]
+mod index {
+    use super::*;
+    use crate::stores::memory::MemoryPersistence;
+
+    fn record(id: &str, issued_at: i64, expires_at: i64) -> SessionRecord {
+        SessionRecord { id: id.to_string(), issued_at, expires_at }
+    }
+
+    #[test]
+    fn index_validation() {
+        let now = compute_timestamp_in_seconds();
+        let mut store = MemoryPersistence::new(None);
+        let mut index = block_on(SessionIndex::load(&store, "john_doe"));
+        assert!(index.is_empty());
+
+        index.insert(record("b", now - 10, now + 60));
+        index.insert(record("a", now - 20, now + 60));
+        index.insert(record("expired", now - 30, now - 1));
+        block_on(index.save(&mut store));
+        assert!(block_on(SessionIndex::load(&store, "jane_doe")).is_empty());
+
+        let mut index = block_on(SessionIndex::load(&store, "john_doe"));
+        let ids = index.sessions().iter().map(|s| s.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_eq!(index.pop_oldest().unwrap().id, "a");
+        assert!(index.remove("a").is_none());
+        assert_eq!(index.remove("b").unwrap().id, "b");
+        block_on(index.save(&mut store));
+        assert!(block_on(store.load(SessionIndex::key("john_doe"))).is_none());
+
+        block_on(store.store(SessionIndex::key("john_doe"), "not json".to_string()));
+        assert!(block_on(SessionIndex::load(&store, "john_doe")).is_empty());
+    }
+
+    #[test]
+    fn policy_validation() {
+        assert_eq!(" Evict-Oldest".parse::<LimitPolicy>(), Ok(LimitPolicy::EvictOldest));
+        assert_eq!("reject".parse::<LimitPolicy>(), Ok(LimitPolicy::Reject));
+        assert!("oldest".parse::<LimitPolicy>().is_err());
+    }
+}
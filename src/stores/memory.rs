@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use jwtvault::prelude::*;
+
+use crate::stores::expiry::{self, StoreUntil};
+use crate::stores::sweeper::Sweep;
+
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    value: String,
+    expires_at: Option<i64>,
+}
+
+impl Entry {
+    fn is_live(&self, now: i64) -> bool {
+        self.expires_at.map_or(true, |expires_at| expires_at > now)
+    }
+}
+
+/// In-memory session storage which forgets a session once its refresh token has expired
+///
+/// Expired entries are absent on `load` right away but keep their memory until `sweep`
+/// (see `sweeper::Sweeper`), `remove` or a new `store` under the same key.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryPersistence {
+    expiry_in_seconds: Option<i64>,
+    entries: HashMap<u64, Entry>,
+    evictions: u64,
+}
+
+impl MemoryPersistence {
+    /// `expiry_in_seconds` should match the refresh token expiry; `None` never expires
+    pub fn new(expiry_in_seconds: Option<i64>) -> Self {
+        Self { expiry_in_seconds, ..Default::default() }
+    }
+
+    /// Store with an explicit expiry (seconds since epoch), e.g. when restoring sessions
+    pub fn insert(&mut self, key: u64, value: String, expires_at: Option<i64>) {
+        self.entries.insert(key, Entry { value, expires_at });
+    }
+
+    /// `remove` without the `async`, e.g. while holding a lock
+    pub fn take(&mut self, key: u64) -> Option<String> {
+        let now = compute_timestamp_in_seconds();
+        self.entries.remove(&key).filter(|entry| entry.is_live(now)).map(|entry| entry.value)
+    }
+
+    /// `load` without the `async`, e.g. while holding a lock
+    pub fn get(&self, key: u64) -> Option<&String> {
+        let now = compute_timestamp_in_seconds();
+        self.entries.get(&key).filter(|entry| entry.is_live(now)).map(|entry| &entry.value)
+    }
+
+    /// The expiry `store` records for a session stored now
+    pub fn expires_at(&self) -> Option<i64> {
+        expiry::expires_at(self.expiry_in_seconds)
+    }
+
+    /// Live entries with their expiry (seconds since epoch)
+    pub fn entries(&self) -> impl Iterator<Item=(u64, &String, Option<i64>)> {
+        let now = compute_timestamp_in_seconds();
+        self.entries.iter()
+            .filter(move |(_, entry)| entry.is_live(now))
+            .map(|(key, entry)| (*key, &entry.value, entry.expires_at))
+    }
+
+    /// Entries held, including expired ones not swept yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Expired entries dropped by `sweep` so far
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+}
+
+impl Sweep for MemoryPersistence {
+    fn sweep(&mut self) -> usize {
+        let now = compute_timestamp_in_seconds();
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| entry.is_live(now));
+        let evicted = before - self.entries.len();
+        self.evictions += evicted as u64;
+        evicted
+    }
+}
+
+#[async_trait]
+impl Persistence for MemoryPersistence {
+    async fn store(&mut self, key: u64, value: String) {
+        let expires_at = self.expires_at();
+        self.insert(key, value, expires_at);
+    }
+
+    async fn load(&self, key: u64) -> Option<&String> {
+        self.get(key)
+    }
+
+    async fn remove(&mut self, key: u64) -> Option<String> {
+        self.take(key)
+    }
+}
+
+#[async_trait]
+impl StoreUntil for MemoryPersistence {
+    async fn store_until(&mut self, key: u64, value: String, expires_at: Option<i64>) {
+        self.insert(key, value, expires_at);
+    }
+}
+
+#[cfg(test)]
+mod memory {
+    use super::*;
+
+    #[test]
+    fn expired_entries_are_absent_and_swept() {
+        let now = compute_timestamp_in_seconds();
+        let mut store = MemoryPersistence::new(Some(60));
+        block_on(store.store(1, "live".to_string()));
+        store.insert(2, "expired".to_string(), Some(now - 1));
+        store.insert(3, "forever".to_string(), None);
+
+        assert_eq!(block_on(store.load(1)), Some(&"live".to_string()));
+        assert_eq!(block_on(store.load(2)), None);
+        assert_eq!(block_on(store.load(3)), Some(&"forever".to_string()));
+        assert_eq!(store.len(), 3);
+
+        assert_eq!(store.sweep(), 1);
+        assert_eq!(store.sweep(), 0);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.evictions(), 1);
+
+        store.insert(4, "expired".to_string(), Some(now - 1));
+        assert_eq!(block_on(store.remove(4)), None);
+        assert_eq!(block_on(store.remove(1)), Some("live".to_string()));
+        assert_eq!(store.len(), 1);
+    }
+}
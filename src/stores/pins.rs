@@ -0,0 +1,118 @@
+use once_cell::sync::OnceCell;
+
+const PIN_BUCKETS: usize = 64;
+
+struct Pin {
+    key: u64,
+    value: String,
+    next: OnceCell<Box<Pin>>,
+}
+
+/// Values handed out by `Persistence::load`, which must outlive the call, for stores that can't lend
+/// them out of their own storage (a lock guard, a database row or a server reply)
+///
+/// Only ever appended to, so the references stay valid for as long as `&self` is borrowed;
+/// letting go of them takes `&mut self`, e.g. on the next write or `sweep`.
+pub struct Pins {
+    buckets: Vec<OnceCell<Box<Pin>>>,
+}
+
+impl Default for Pins {
+    fn default() -> Self {
+        Self { buckets: (0..PIN_BUCKETS).map(|_| OnceCell::new()).collect() }
+    }
+}
+
+impl Pins {
+    /// The pin holding `value` for `key`, added if there is none yet
+    pub fn pin(&self, key: u64, value: String) -> &String {
+        let mut slot = &self.buckets[(key % PIN_BUCKETS as u64) as usize];
+        let mut value = Some(value);
+        loop {
+            let pin = slot.get_or_init(|| {
+                Box::new(Pin { key, value: value.take().unwrap(), next: OnceCell::new() })
+            });
+            if value.is_none() || (pin.key == key && value.as_ref() == Some(&pin.value)) {
+                return &pin.value;
+            };
+            slot = &pin.next;
+        }
+    }
+
+    /// Let go of the pins of `key`, e.g. once it is written
+    pub fn unpin(&mut self, key: u64) {
+        let bucket = &mut self.buckets[(key % PIN_BUCKETS as u64) as usize];
+        let mut kept = Vec::new();
+        let mut next = bucket.take();
+        while let Some(mut pin) = next {
+            next = pin.next.take();
+            if pin.key != key {
+                kept.push(pin);
+            };
+        }
+        for mut pin in kept.into_iter().rev() {
+            pin.next = std::mem::take(bucket);
+            let _ = bucket.set(pin);
+        }
+    }
+
+    /// Let go of every pin
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl Drop for Pins {
+    /// One pin at a time, a long chain would otherwise be dropped recursively
+    fn drop(&mut self) {
+        for bucket in self.buckets.iter_mut() {
+            let mut next = bucket.take();
+            while let Some(mut pin) = next {
+                next = pin.next.take();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod pins {
+    use super::*;
+
+    #[test]
+    fn pins_are_shared_per_value() {
+        let mut pins = Pins::default();
+        let one = pins.pin(1, "one".to_string()) as *const String;
+        assert_eq!(pins.pin(1, "one".to_string()) as *const String, one);
+        assert_eq!(pins.pin(1, "uno".to_string()), "uno");
+        assert_eq!(pins.pin(1 + PIN_BUCKETS as u64, "other".to_string()), "other");
+        assert_eq!(pins.pin(1, "one".to_string()) as *const String, one);
+        pins.clear();
+        assert_eq!(pins.pin(1, "one".to_string()), "one");
+    }
+
+    #[test]
+    fn unpin_keeps_other_keys() {
+        let mut pins = Pins::default();
+        let other = 1 + PIN_BUCKETS as u64;
+        let _ = pins.pin(1, "one".to_string());
+        let _ = pins.pin(other, "other".to_string());
+        let _ = pins.pin(1, "uno".to_string());
+        let _ = pins.pin(2, "two".to_string());
+        pins.unpin(1);
+        let count = |pins: &Pins, bucket: usize| {
+            let mut count = 0;
+            let mut slot = &pins.buckets[bucket];
+            while let Some(pin) = slot.get() {
+                count += 1;
+                slot = &pin.next;
+            }
+            count
+        };
+        assert_eq!(count(&pins, 1), 1);
+        assert_eq!(count(&pins, 2), 1);
+        assert_eq!(pins.pin(other, "other".to_string()), "other");
+        assert_eq!(count(&pins, 1), 1);
+        pins.unpin(3);
+        assert_eq!(count(&pins, 2), 1);
+    }
+}
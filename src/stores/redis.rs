@@ -0,0 +1,514 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::time::Duration;
+
+use actix_threadpool::BlockingError;
+use failure::Error;
+use jwtvault::prelude::*;
+use r2d2::{ManageConnection, Pool};
+
+use crate::database::db_common::PoolConfig;
+use crate::database::db_pool::create_lazy_pool;
+use crate::errors::StoreErrors::{Unreachable, CommandFailed};
+use crate::stores::expiry::{self, StoreUntil};
+use crate::stores::pins::Pins;
+use crate::stores::sweeper::Sweep;
+
+pub const DEFAULT_PORT: u16 = 6379;
+/// Sessions are stored under `jwtvault:session:<digest>`
+pub const KEY_PREFIX: &str = "jwtvault:session:";
+
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the session server is: `redis://[[user]:password@]host[:port][/db]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedisConfig {
+    pub address: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub db: u32,
+}
+
+impl FromStr for RedisConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Expected redis://[[user]:password@]host[:port][/db]: {}", s);
+        let rest = s.trim();
+        if !rest.starts_with("redis://") {
+            return Err(invalid());
+        };
+        let rest = &rest["redis://".len()..];
+        let (credentials, rest) = match rest.rfind('@') {
+            Some(at) => (Some(&rest[..at]), &rest[at + 1..]),
+            None => (None, rest),
+        };
+        let (user, password) = match credentials.map(|c| (c, c.find(':'))) {
+            Some((c, Some(colon))) => (Some(&c[..colon]).filter(|u| !u.is_empty()), Some(&c[colon + 1..])),
+            Some((c, None)) => (Some(c), None),
+            None => (None, None),
+        };
+        let (host, db) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash + 1..]),
+            None => (rest, ""),
+        };
+        let db = if db.is_empty() { 0 } else { db.parse::<u32>().map_err(|_| invalid())? };
+        if host.is_empty() || host.starts_with(':') {
+            return Err(invalid());
+        };
+        let address = match host.rfind(':') {
+            Some(colon) => {
+                host[colon + 1..].parse::<u16>().map_err(|_| invalid())?;
+                host.to_string()
+            }
+            None => format!("{}:{}", host, DEFAULT_PORT),
+        };
+        let user = user.map(|u| u.to_string());
+        let password = password.map(|p| p.to_string());
+        Ok(Self { address, user, password, db })
+    }
+}
+
+/// A reply in the Redis serialization protocol (RESP)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+/// A command as a RESP array of bulk strings
+pub fn encode(args: &[&[u8]]) -> Vec<u8> {
+    let mut data = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        data.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        data.extend_from_slice(arg);
+        data.extend_from_slice(b"\r\n");
+    }
+    data
+}
+
+fn protocol_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed"));
+    };
+    if !line.ends_with("\r\n") {
+        return Err(protocol_error(format!("Unterminated line: {:?}", line)));
+    };
+    line.truncate(line.len() - 2);
+    Ok(line)
+}
+
+fn read_length(line: &str) -> io::Result<Option<usize>> {
+    match line.parse::<i64>() {
+        Ok(-1) => Ok(None),
+        Ok(n) if n >= 0 => Ok(Some(n as usize)),
+        _ => Err(protocol_error(format!("Invalid length: {}", line))),
+    }
+}
+
+/// Read one reply
+pub fn read_reply<R: BufRead>(reader: &mut R) -> io::Result<Reply> {
+    let line = read_line(reader)?;
+    if line.is_empty() {
+        return Err(protocol_error("Empty reply".to_string()));
+    };
+    let (kind, rest) = line.split_at(1);
+    match kind {
+        "+" => Ok(Reply::Status(rest.to_string())),
+        "-" => Ok(Reply::Error(rest.to_string())),
+        ":" => rest.parse::<i64>().map(Reply::Integer).map_err(|_| protocol_error(format!("Invalid integer: {}", rest))),
+        "$" => match read_length(rest)? {
+            None => Ok(Reply::Bulk(None)),
+            Some(n) => {
+                let mut data = vec![0; n + 2];
+                reader.read_exact(&mut data)?;
+                if !data.ends_with(b"\r\n") {
+                    return Err(protocol_error("Unterminated bulk string".to_string()));
+                };
+                data.truncate(n);
+                Ok(Reply::Bulk(Some(data)))
+            }
+        },
+        "*" => match read_length(rest)? {
+            None => Ok(Reply::Array(None)),
+            Some(n) => (0..n).map(|_| read_reply(reader)).collect::<io::Result<Vec<Reply>>>().map(|r| Reply::Array(Some(r))),
+        },
+        _ => Err(protocol_error(format!("Unknown reply: {}", line))),
+    }
+}
+
+/// One connection to the session server
+pub struct RedisConnection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    broken: bool,
+}
+
+impl RedisConnection {
+    /// Connect, authenticate and select the database
+    pub fn open(config: &RedisConfig) -> io::Result<Self> {
+        let stream = TcpStream::connect(config.address.as_str())?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let mut connection = Self { reader, writer: stream, broken: false };
+        // A user without a password has nothing to authenticate with
+        if let Some(password) = &config.password {
+            let _ = match &config.user {
+                Some(user) => connection.command(&[b"AUTH", user.as_bytes(), password.as_bytes()])?,
+                None => connection.command(&[b"AUTH", password.as_bytes()])?,
+            };
+        };
+        if config.db != 0 {
+            let _ = connection.command(&[b"SELECT", config.db.to_string().as_bytes()])?;
+        };
+        Ok(connection)
+    }
+
+    /// Send the commands in one write and read their replies in order
+    pub fn pipeline(&mut self, commands: &[Vec<&[u8]>]) -> io::Result<Vec<Reply>> {
+        let data = commands.iter().flat_map(|args| encode(args.as_slice())).collect::<Vec<u8>>();
+        let result = self.writer.write_all(data.as_slice())
+            .and_then(|_| commands.iter().map(|_| read_reply(&mut self.reader)).collect());
+        // Replies still in flight would be read as the answer to the next command
+        if result.is_err() {
+            self.broken = true;
+        };
+        result
+    }
+
+    /// An error reply fails the command, the connection stays usable
+    pub fn command(&mut self, args: &[&[u8]]) -> io::Result<Reply> {
+        let reply = self.pipeline(&[args.to_vec()])?.remove(0);
+        if let Reply::Error(e) = reply {
+            return Err(io::Error::new(io::ErrorKind::Other, e));
+        };
+        Ok(reply)
+    }
+}
+
+/// r2d2 manager for `RedisConnection`s
+#[derive(Debug, Clone)]
+pub struct RedisConnectionManager {
+    config: RedisConfig,
+}
+
+impl RedisConnectionManager {
+    pub fn new(config: RedisConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl ManageConnection for RedisConnectionManager {
+    type Connection = RedisConnection;
+    type Error = io::Error;
+
+    fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        RedisConnection::open(&self.config)
+    }
+
+    /// Any reply will do, even an error means the server is there
+    fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
+        conn.pipeline(&[vec![b"PING"]]).map(|_| ())
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        conn.broken
+    }
+}
+
+
+
+/// Session storage on a Redis (or Redis protocol) server, shared by every instance of the webserver
+///
+/// `store` is a `SET` with `EX` set to the refresh token expiry, so the server expires sessions itself;
+/// `load` is a `GET` and `remove` a `GET` and `DEL` in one round trip, all keyed by `KEY_PREFIX` and the digest.
+/// Connections come from an r2d2 pool and block, so every command runs on the actix thread pool
+/// (see `actix_threadpool::run`) rather than on the worker awaiting it.
+///
+/// `Persistence::load` hands out a reference, so every value loaded is pinned in memory
+/// until its key is written or removed, or until `sweep`. Each `load` still asks the server, pins only keep the values alive.
+/// A `clone` shares the pool and starts without pins.
+pub struct RedisPersistence {
+    pool: Pool<RedisConnectionManager>,
+    expiry_in_seconds: Option<i64>,
+    pins: Pins,
+}
+
+impl RedisPersistence {
+    /// Does not connect yet, an unreachable server only fails on use.
+    /// `expiry_in_seconds` should match the refresh token expiry; `None` never expires
+    pub fn new(config: RedisConfig, pool_config: PoolConfig, expiry_in_seconds: Option<i64>) -> Self {
+        let pool = create_lazy_pool(RedisConnectionManager::new(config), pool_config);
+        Self { pool, expiry_in_seconds, pins: Pins::default() }
+    }
+
+    pub fn key(key: u64) -> String {
+        format!("{}{}", KEY_PREFIX, key)
+    }
+
+    /// Fails unless the server answers
+    pub async fn ping(&self) -> Result<(), Error> {
+        let _ = self.execute(vec![vec![b"PING".to_vec()]]).await?;
+        Ok(())
+    }
+
+    async fn execute(&self, commands: Vec<Vec<Vec<u8>>>) -> Result<Vec<Reply>, Error> {
+        let pool = self.pool.clone();
+        actix_threadpool::run(move || execute(&pool, commands)).await.map_err(|e| match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => {
+                Unreachable("Unable to connect to session server".to_string(), "Thread pool is gone".to_string()).into()
+            }
+        })
+    }
+
+    async fn get(&self, key: u64) -> Result<Option<String>, Error> {
+        let name = Self::key(key).into_bytes();
+        let reply = self.execute(vec![vec![b"GET".to_vec(), name]]).await?.remove(0);
+        value_from(reply)
+    }
+}
+
+/// Blocks on the pool and the socket
+fn execute(pool: &Pool<RedisConnectionManager>, commands: Vec<Vec<Vec<u8>>>) -> Result<Vec<Reply>, Error> {
+    let mut conn = pool.get().map_err(|e| {
+        Unreachable("Unable to connect to session server".to_string(), e.to_string())
+    })?;
+    let commands = commands.iter()
+        .map(|args| args.iter().map(|arg| arg.as_slice()).collect())
+        .collect::<Vec<Vec<&[u8]>>>();
+    let command = String::from_utf8_lossy(commands[0][0]).to_string();
+    let replies = conn.pipeline(commands.as_slice()).map_err(|e| {
+        CommandFailed(format!("Session server failed on {}", command), e.to_string())
+    })?;
+    if let Some(Reply::Error(e)) = replies.iter().find(|reply| matches!(reply, Reply::Error(_))) {
+        return Err(CommandFailed(format!("Session server rejected {}", command), e.clone()).into());
+    };
+    Ok(replies)
+}
+
+fn value_from(reply: Reply) -> Result<Option<String>, Error> {
+    match reply {
+        Reply::Bulk(None) => Ok(None),
+        Reply::Bulk(Some(data)) => String::from_utf8(data).map(Some).map_err(|e| {
+            CommandFailed("Session is not valid UTF-8".to_string(), e.to_string()).into()
+        }),
+        reply => Err(CommandFailed("Unexpected reply from session server".to_string(), format!("{:?}", reply)).into()),
+    }
+}
+
+impl Clone for RedisPersistence {
+    fn clone(&self) -> Self {
+        Self { pool: self.pool.clone(), expiry_in_seconds: self.expiry_in_seconds, pins: Pins::default() }
+    }
+}
+
+impl Sweep for RedisPersistence {
+    /// The server expires sessions itself, this only lets go of the values pinned by `load`
+    fn sweep(&mut self) -> usize {
+        self.pins.clear();
+        0
+    }
+}
+
+#[async_trait]
+impl Persistence for RedisPersistence {
+    async fn store(&mut self, key: u64, value: String) {
+        let expires_at = expiry::expires_at(self.expiry_in_seconds);
+        self.store_until(key, value, expires_at).await
+    }
+
+    async fn load(&self, key: u64) -> Option<&String> {
+        match self.get(key).await {
+            Ok(value) => value.map(|value| self.pins.pin(key, value)),
+            Err(e) => {
+                eprintln!("Unable to load session: {} Reason: {}", key, e.to_string());
+                None
+            }
+        }
+    }
+
+    async fn remove(&mut self, key: u64) -> Option<String> {
+        self.pins.unpin(key);
+        let name = Self::key(key).into_bytes();
+        let result = self.execute(vec![vec![b"GET".to_vec(), name.clone()], vec![b"DEL".to_vec(), name]]).await;
+        match result.and_then(|mut replies| value_from(replies.remove(0))) {
+            Ok(value) => value,
+            Err(e) => {
+                eprintln!("Unable to remove session: {} Reason: {}", key, e.to_string());
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StoreUntil for RedisPersistence {
+    async fn store_until(&mut self, key: u64, value: String, expires_at: Option<i64>) {
+        self.pins.unpin(key);
+        let name = Self::key(key).into_bytes();
+        let mut command = vec![b"SET".to_vec(), name, value.into_bytes()];
+        if let Some(expires_at) = expires_at {
+            let expiry = expires_at - compute_timestamp_in_seconds();
+            command.extend(vec![b"EX".to_vec(), expiry.max(1).to_string().into_bytes()]);
+        };
+        if let Err(e) = self.execute(vec![command]).await {
+            eprintln!("Unable to store session: {} Reason: {}", key, e.to_string());
+        };
+    }
+}
+
+#[cfg(test)]
+mod redis {
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    type Data = Arc<Mutex<HashMap<Vec<u8>, (Vec<u8>, Option<i64>)>>>;
+
+    /// Speaks just enough RESP for `RedisPersistence`: AUTH, PING, SET (with EX), GET and DEL.
+    /// Keeps the EX seconds instead of expiring anything
+    fn stand_in(password: &'static str) -> (String, Data) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let data = Data::default();
+        let shared = data.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let data = shared.clone();
+                thread::spawn(move || serve(stream, password, data));
+            }
+        });
+        (address, data)
+    }
+
+    fn serve(stream: TcpStream, password: &str, data: Data) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut authenticated = false;
+        while let Ok(Reply::Array(Some(args))) = read_reply(&mut reader) {
+            let args = args.into_iter().map(|arg| match arg {
+                Reply::Bulk(Some(arg)) => arg,
+                _ => Vec::new(),
+            }).collect::<Vec<Vec<u8>>>();
+            let command = String::from_utf8_lossy(&args[0]).to_uppercase();
+            let mut data = data.lock().unwrap();
+            let reply: Vec<u8> = match command.as_str() {
+                "AUTH" if args.last().unwrap() == password.as_bytes() => {
+                    authenticated = true;
+                    b"+OK\r\n".to_vec()
+                }
+                "AUTH" => b"-WRONGPASS invalid password\r\n".to_vec(),
+                _ if !authenticated => b"-NOAUTH Authentication required.\r\n".to_vec(),
+                "PING" => b"+PONG\r\n".to_vec(),
+                "SET" => {
+                    let ex = args.get(4).map(|n| String::from_utf8_lossy(n).parse::<i64>().unwrap());
+                    data.insert(args[1].clone(), (args[2].clone(), ex));
+                    b"+OK\r\n".to_vec()
+                }
+                "GET" => match data.get(&args[1]) {
+                    Some((value, _)) => {
+                        let mut reply = format!("${}\r\n", value.len()).into_bytes();
+                        reply.extend_from_slice(value);
+                        reply.extend_from_slice(b"\r\n");
+                        reply
+                    }
+                    None => b"$-1\r\n".to_vec(),
+                },
+                "DEL" => format!(":{}\r\n", data.remove(&args[1]).map_or(0, |_| 1)).into_bytes(),
+                _ => format!("-ERR unknown command '{}'\r\n", command).into_bytes(),
+            };
+            if writer.write_all(reply.as_slice()).is_err() {
+                return;
+            };
+        }
+    }
+
+    fn pool_config() -> PoolConfig {
+        PoolConfig::new(0, 4).with_timeouts(Duration::from_secs(2), None, None)
+    }
+
+    #[test]
+    fn sessions_are_shared_through_the_server() {
+        let (address, data) = stand_in("secret");
+        let config: RedisConfig = format!("redis://:secret@{}", address).parse().unwrap();
+        let mut first = RedisPersistence::new(config.clone(), pool_config(), Some(60));
+        let mut second = RedisPersistence::new(config, pool_config(), None);
+        block_on(first.ping()).unwrap();
+
+        block_on(first.store(1, "one".to_string()));
+        block_on(second.store(2, "two".to_string()));
+        assert_eq!(block_on(second.load(1)), Some(&"one".to_string()));
+        assert_eq!(block_on(first.load(2)), Some(&"two".to_string()));
+        assert_eq!(data.lock().unwrap().get(RedisPersistence::key(1).as_bytes()).unwrap().1, Some(60));
+        assert_eq!(data.lock().unwrap().get(RedisPersistence::key(2).as_bytes()).unwrap().1, None);
+
+        // Every load asks the server, a pinned value is never served stale
+        let handle = second.clone();
+        assert_eq!(block_on(handle.load(1)), Some(&"one".to_string()));
+        block_on(first.store(1, "uno".to_string()));
+        assert_eq!(block_on(handle.load(1)), Some(&"uno".to_string()));
+        assert_eq!(block_on(handle.load(1)), Some(&"uno".to_string()));
+
+        assert_eq!(block_on(second.remove(1)), Some("uno".to_string()));
+        assert_eq!(block_on(second.remove(1)), None);
+        assert_eq!(block_on(handle.load(1)), None);
+        assert_eq!(first.sweep(), 0);
+        assert_eq!(data.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn failures_are_reported() {
+        let (address, _) = stand_in("secret");
+        let wrong = RedisPersistence::new(format!("redis://:wrong@{}", address).parse().unwrap(), pool_config(), None);
+        let err = block_on(wrong.ping()).err().unwrap();
+        assert!(err.to_string().starts_with("Unable to connect to session server"));
+
+        let anonymous = RedisPersistence::new(format!("redis://{}", address).parse().unwrap(), pool_config(), None);
+        let err = block_on(anonymous.ping()).err().unwrap();
+        assert_eq!(err.to_string(), "Session server rejected PING. Reason: NOAUTH Authentication required.");
+        assert_eq!(block_on(anonymous.load(1)), None);
+    }
+
+    #[test]
+    fn replies_are_parsed() {
+        let data = b"*3\r\n+OK\r\n$5\r\nhello\r\n*2\r\n:42\r\n$-1\r\n-ERR bad\r\n";
+        let mut reader = BufReader::new(&data[..]);
+        assert_eq!(read_reply(&mut reader).unwrap(), Reply::Array(Some(vec![
+            Reply::Status("OK".to_string()),
+            Reply::Bulk(Some(b"hello".to_vec())),
+            Reply::Array(Some(vec![Reply::Integer(42), Reply::Bulk(None)])),
+        ])));
+        assert_eq!(read_reply(&mut reader).unwrap(), Reply::Error("ERR bad".to_string()));
+        assert!(read_reply(&mut reader).is_err());
+        assert_eq!(encode(&[b"GET", b"key"]), b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n".to_vec());
+    }
+
+    #[test]
+    fn redis_config_from_str() {
+        let config: RedisConfig = "redis://127.0.0.1".parse().unwrap();
+        assert_eq!(config, RedisConfig { address: "127.0.0.1:6379".to_string(), user: None, password: None, db: 0 });
+        let config: RedisConfig = "redis://app:p@ss@cache:6380/2".parse().unwrap();
+        assert_eq!(config, RedisConfig { address: "cache:6380".to_string(), user: Some("app".to_string()), password: Some("p@ss".to_string()), db: 2 });
+        let config: RedisConfig = "redis://:secret@cache".parse().unwrap();
+        assert_eq!((config.user, config.password), (None, Some("secret".to_string())));
+        assert!("http://cache".parse::<RedisConfig>().is_err());
+        assert!("redis://cache:port".parse::<RedisConfig>().is_err());
+        assert!("redis://cache/db".parse::<RedisConfig>().is_err());
+        assert!("redis://".parse::<RedisConfig>().is_err());
+    }
+}
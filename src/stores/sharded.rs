@@ -0,0 +1,155 @@
+use std::sync::{Arc, RwLock};
+
+use jwtvault::prelude::*;
+
+use crate::stores::expiry::StoreUntil;
+use crate::stores::memory::MemoryPersistence;
+use crate::stores::pins::Pins;
+use crate::stores::sweeper::Sweep;
+
+pub const DEFAULT_SHARDS: usize = 16;
+
+type Shard = RwLock<MemoryPersistence>;
+
+/// Session storage shared by every worker without a vault wide lock
+///
+/// Sessions are spread over `MemoryPersistence` shards by key, each behind its own `RwLock`:
+/// loads run in parallel, a write only locks its shard and changes it in place.
+/// `Persistence::load` hands out a borrow, which a lock guard can't outlive, so a handle copies
+/// the session under the read lock and keeps the copy (see `pins::Pins`) until its next write or sweep.
+///
+/// A `clone` is a new handle on the same shards; take one per request, so the copies go with it.
+pub struct ShardedPersistence {
+    shards: Arc<Vec<Shard>>,
+    pins: Pins,
+}
+
+impl ShardedPersistence {
+    /// `expiry_in_seconds` should match the refresh token expiry; `None` never expires
+    pub fn new(expiry_in_seconds: Option<i64>, shards: usize) -> Self {
+        let shards = (0..shards.max(1))
+            .map(|_| RwLock::new(MemoryPersistence::new(expiry_in_seconds)))
+            .collect::<Vec<Shard>>();
+        Self { shards: Arc::new(shards), pins: Pins::default() }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Sessions held across all shards, including expired ones not swept yet
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Expired sessions dropped by `sweep` so far, across all shards
+    pub fn evictions(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.read().unwrap().evictions()).sum()
+    }
+
+    fn shard(&self, key: u64) -> &Shard {
+        &self.shards[(key % self.shards.len() as u64) as usize]
+    }
+
+    fn write<T, F: FnOnce(&mut MemoryPersistence) -> T>(&mut self, key: u64, f: F) -> T {
+        self.pins.clear();
+        let mut shard = self.shard(key).write().unwrap();
+        f(&mut shard)
+    }
+}
+
+impl Clone for ShardedPersistence {
+    fn clone(&self) -> Self {
+        Self { shards: self.shards.clone(), pins: Pins::default() }
+    }
+}
+
+impl Sweep for ShardedPersistence {
+    /// Locks one shard at a time
+    fn sweep(&mut self) -> usize {
+        self.pins.clear();
+        self.shards.iter().map(|shard| shard.write().unwrap().sweep()).sum()
+    }
+}
+
+#[async_trait]
+impl Persistence for ShardedPersistence {
+    async fn store(&mut self, key: u64, value: String) {
+        self.write(key, |shard| {
+            let expires_at = shard.expires_at();
+            shard.insert(key, value, expires_at)
+        })
+    }
+
+    async fn load(&self, key: u64) -> Option<&String> {
+        let value = self.shard(key).read().unwrap().get(key).cloned()?;
+        Some(self.pins.pin(key, value))
+    }
+
+    async fn remove(&mut self, key: u64) -> Option<String> {
+        self.write(key, |shard| shard.take(key))
+    }
+}
+
+#[async_trait]
+impl StoreUntil for ShardedPersistence {
+    async fn store_until(&mut self, key: u64, value: String, expires_at: Option<i64>) {
+        self.write(key, |shard| shard.insert(key, value, expires_at))
+    }
+}
+
+#[cfg(test)]
+mod sharded {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn handles_share_sessions() {
+        let now = compute_timestamp_in_seconds();
+        let mut first = ShardedPersistence::new(Some(60), 4);
+        let mut second = first.clone();
+        block_on(first.store(1, "one".to_string()));
+        block_on(second.store(2, "two".to_string()));
+        assert_eq!(block_on(first.load(2)), Some(&"two".to_string()));
+        assert_eq!(block_on(second.load(1)), Some(&"one".to_string()));
+
+        // Every handle sees the latest writes
+        assert_eq!(block_on(second.remove(1)), Some("one".to_string()));
+        assert_eq!(block_on(first.load(1)), None);
+        block_on(second.store(5, "five".to_string()));
+        assert_eq!(block_on(first.load(5)), Some(&"five".to_string()));
+        block_on(second.store(5, "cinq".to_string()));
+        assert_eq!(block_on(first.load(5)), Some(&"cinq".to_string()));
+
+        first.write(3, |shard| shard.insert(3, "expired".to_string(), Some(now - 1)));
+        assert_eq!(block_on(first.load(3)), None);
+        assert_eq!(second.len(), 3);
+        assert_eq!(second.sweep(), 1);
+        assert_eq!(first.evictions(), 1);
+        assert_eq!(first.len(), 2);
+    }
+
+    #[test]
+    fn workers_write_in_parallel() {
+        let store = ShardedPersistence::new(None, 8);
+        let workers = (0..4u64).map(|worker| {
+            let mut store = store.clone();
+            thread::spawn(move || {
+                for n in 0..250 {
+                    let key = worker * 1000 + n;
+                    block_on(store.store(key, key.to_string()));
+                    assert_eq!(block_on(store.load(key)), Some(&key.to_string()));
+                }
+            })
+        }).collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(store.len(), 1000);
+        assert_eq!(block_on(store.clone().load(3249)), Some(&"3249".to_string()));
+    }
+}
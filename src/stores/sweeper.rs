@@ -0,0 +1,91 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+
+/// Storage which can drop its expired entries
+pub trait Sweep {
+    /// Returns the number of entries evicted
+    fn sweep(&mut self) -> usize;
+}
+
+/// Runs a sweep on a background thread every `interval` until stopped or dropped
+///
+/// The sweep is a closure so it can take whatever lock guards the storage, e.g.
+/// `Sweeper::spawn(interval, move || vault.lock().unwrap().sweep())`
+pub struct Sweeper {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+    sweeps: Arc<AtomicU64>,
+    evictions: Arc<AtomicU64>,
+}
+
+impl Sweeper {
+    pub fn spawn<F>(interval: Duration, mut sweep: F) -> Self
+        where F: FnMut() -> usize + Send + 'static {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let sweeps = Arc::new(AtomicU64::new(0));
+        let evictions = Arc::new(AtomicU64::new(0));
+        let handle = {
+            let sweeps = sweeps.clone();
+            let evictions = evictions.clone();
+            thread::spawn(move || {
+                // Anything but a timeout means the sweeper was stopped
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let evicted = sweep();
+                    evictions.fetch_add(evicted as u64, Ordering::SeqCst);
+                    sweeps.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        };
+        Self { stop: Some(stop), handle: Some(handle), sweeps, evictions }
+    }
+
+    /// Sweeps run so far
+    pub fn sweeps(&self) -> u64 {
+        self.sweeps.load(Ordering::SeqCst)
+    }
+
+    /// Entries evicted by all sweeps so far
+    pub fn evictions(&self) -> u64 {
+        self.evictions.load(Ordering::SeqCst)
+    }
+
+    /// Stops sweeping, waiting for a sweep in progress to finish
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                eprintln!("Session sweeper panicked");
+            };
+        };
+    }
+}
+
+#[cfg(test)]
+mod sweeper {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn sweeps_until_stopped() {
+        let pending = Arc::new(Mutex::new(vec![3, 0, 2]));
+        let sweeper = {
+            let pending = pending.clone();
+            Sweeper::spawn(Duration::from_millis(5), move || pending.lock().unwrap().pop().unwrap_or(0))
+        };
+        while sweeper.sweeps() < 3 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(sweeper.evictions(), 5);
+        sweeper.stop();
+    }
+}
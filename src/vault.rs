@@ -0,0 +1,829 @@
+use jwtvault::prelude::*;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::sync::Arc;
+
+use crate::errors::SessionErrors;
+use crate::errors::StoreErrors::WriteFailed;
+use crate::owner::TokenOwnerKey;
+use crate::stores::expiry::{refresh_expiry, StoreUntil};
+use crate::stores::index::{IndexLocks, LimitPolicy, SessionIndex, SessionLimit, SessionRecord};
+use crate::stores::sweeper::Sweep;
+
+
+/// Static dispatch vault: keys, password hashing and the token workflow are shared,
+/// the user lookup (`U`), session storage (`P`) and token owner check (`I`) are plugged in.
+///
+/// Keys, hasher and identity are shared, so cloning costs a clone of `U` and `P`: with both cheap to clone
+/// (e.g. `SharedUsers` and `stores::sharded::ShardedPersistence`) every request can work on its own vault
+pub struct ComposableVault<U, P, I = DefaultIdentity> {
+    trust_token_bearer: bool,
+    certificate_store: Arc<CertificateStore>,
+    password_hasher: Arc<ArgonPasswordHasher>,
+    users: U,
+    store: P,
+    identity: Arc<I>,
+    owner_key: Option<Arc<TokenOwnerKey>>,
+    sessions: Option<SessionLimit>,
+    index_locks: IndexLocks,
+}
+
+impl<U, P> ComposableVault<U, P>
+    where U: UserAuthentication + Send + Sync, P: Persistence + Send + Sync {
+    pub fn new<T: Keys>(loader: T, users: U, store: P) -> Self {
+        VaultBuilder::new(loader).build(users, store)
+    }
+}
+
+impl<U: Clone, P: Clone, I> Clone for ComposableVault<U, P, I> {
+    fn clone(&self) -> Self {
+        Self {
+            trust_token_bearer: self.trust_token_bearer,
+            certificate_store: self.certificate_store.clone(),
+            password_hasher: self.password_hasher.clone(),
+            users: self.users.clone(),
+            store: self.store.clone(),
+            identity: self.identity.clone(),
+            owner_key: self.owner_key.clone(),
+            sessions: self.sessions,
+            index_locks: self.index_locks.clone(),
+        }
+    }
+}
+
+impl<U, P, I> ComposableVault<U, P, I> {
+    pub fn users(&self) -> &U {
+        &self.users
+    }
+
+    pub fn users_mut(&mut self) -> &mut U {
+        &mut self.users
+    }
+
+    pub fn store(&self) -> &P {
+        &self.store
+    }
+
+    pub fn store_mut(&mut self) -> &mut P {
+        &mut self.store
+    }
+
+    /// The user id this vault puts inside tokens, `<user>#<session id>` for a tracked session
+    fn token_owner<'a>(&self, user: &'a str, session: Option<&str>) -> Cow<'a, str> {
+        let owner = match session {
+            Some(id) => Cow::Owned(format!("{}#{}", user, id)),
+            None => Cow::Borrowed(user),
+        };
+        match &self.owner_key {
+            Some(key) => Cow::Owned(key.seal(owner.as_ref())),
+            None => owner,
+        }
+    }
+
+    /// User and session id behind the user id inside a token of a tracked session
+    fn session_of<'a>(&self, owner: &'a str) -> Option<(&'a str, &'a str)> {
+        let owner = match &self.owner_key {
+            Some(key) => key.open(owner)?,
+            None => owner,
+        };
+        let user = session_user(owner);
+        if user.len() == owner.len() {
+            return None;
+        };
+        Some((user, &owner[user.len() + 1..]))
+    }
+}
+
+impl<U, P, I> ComposableVault<U, P, I> where P: StoreUntil + Send + Sync {
+    /// Sessions of `user`, oldest first; empty when the vault does not track sessions
+    pub async fn list_sessions(&self, user: &str) -> Vec<SessionRecord> {
+        if self.sessions.is_none() {
+            return Vec::new();
+        };
+        self.live_sessions(user).await.sessions().to_vec()
+    }
+
+    /// End every session of `user`, e.g. after a password change, and return how many there were
+    ///
+    /// A vault which does not track sessions ends the single session jwtvault keeps per user
+    pub async fn revoke_sessions(&mut self, user: &str) -> usize {
+        if self.sessions.is_none() {
+            return self.end_session(user, None).await as usize;
+        };
+        let locks = self.index_locks.clone();
+        let _guard = locks.lock(user).await;
+        let mut index = self.live_sessions(user).await;
+        let sessions = index.clear();
+        for session in sessions.iter() {
+            self.end_session(user, Some(session.id.as_str())).await;
+        }
+        index.save(&mut self.store).await;
+        sessions.len()
+    }
+
+    /// The index of `user` without the sessions which ended outside of it, e.g. revoked or expired in the store
+    async fn live_sessions(&self, user: &str) -> SessionIndex {
+        let mut index = SessionIndex::load(&self.store, user).await;
+        let mut ended = Vec::new();
+        for session in index.sessions() {
+            let owner = self.token_owner(user, Some(session.id.as_str()));
+            let reference = resolve_refresh_reference::<_, DefaultHasher>(owner.as_bytes());
+            if self.store.load(reference).await.is_none() {
+                ended.push(session.id.clone());
+            };
+        }
+        for id in ended.iter() {
+            index.remove(id.as_str());
+        }
+        index
+    }
+
+    /// Remove the server side token and the authentication digest of a session, true if it was live
+    async fn end_session(&mut self, user: &str, session: Option<&str>) -> bool {
+        let owner = self.token_owner(user, session);
+        let reference = resolve_refresh_reference::<_, DefaultHasher>(owner.as_bytes());
+        let digest_reference = resolve_authentication_reference::<_, DefaultHasher>(owner.as_bytes());
+        let _ = self.store.remove(digest_reference).await;
+        self.store.remove(reference).await.is_some()
+    }
+}
+
+/// `Persistence::store` can't fail, a store which could not write a session logs it and carries on;
+/// a login checks that its session is there instead of handing out tokens for nothing.
+///
+/// The store kept the session for the expiry it was built with, the login may have asked for another one:
+/// the session and its authentication digest are stored again until the `exp` of the refresh token, which is returned
+async fn keep_stored<P: StoreUntil>(store: &mut P, certificate: &PublicKey, user: &str) -> Result<i64, Error> {
+    let reference = resolve_refresh_reference::<_, DefaultHasher>(user.as_bytes());
+    let digest_reference = resolve_authentication_reference::<_, DefaultHasher>(user.as_bytes());
+    let server_token = store.load(reference).await.cloned();
+    let server_token = match server_token {
+        Some(server_token) => server_token,
+        None => {
+            let _ = store.remove(digest_reference).await;
+            let msg = "Login Failed".to_string();
+            let reason = "Session could not be stored".to_string();
+            return Err(WriteFailed(msg, reason).into());
+        }
+    };
+    let exp = *decode_server_token(certificate, server_token.as_str())?.exp();
+    store.store_until(reference, server_token, refresh_expiry(exp)).await;
+    keep_until(store, digest_reference, exp).await;
+    Ok(exp)
+}
+
+/// Store the entry under `key` again until `exp` of the refresh token it belongs to, if it is there
+async fn keep_until<P: StoreUntil>(store: &mut P, key: u64, exp: i64) {
+    let value = store.load(key).await.cloned();
+    if let Some(value) = value {
+        store.store_until(key, value, refresh_expiry(exp)).await;
+    };
+}
+
+/// `owner` without the `#<session id>` a tracked session adds to it
+pub fn session_user(owner: &str) -> &str {
+    match owner.rfind('#') {
+        Some(index) if owner.len() - index == 17 && owner[index + 1..].bytes().all(|b| b.is_ascii_hexdigit()) => &owner[..index],
+        _ => owner,
+    }
+}
+
+
+/// Collects everything but the user lookup and session storage, which are handed to `build`
+pub struct VaultBuilder<I = DefaultIdentity> {
+    trust_token_bearer: bool,
+    certificate_store: CertificateStore,
+    password_hashing_secret: PrivateKey,
+    identity: I,
+    owner_key: Option<TokenOwnerKey>,
+    sessions: Option<SessionLimit>,
+}
+
+impl VaultBuilder {
+    pub fn new<T: Keys>(loader: T) -> Self {
+        let password_hashing_secret = loader.password_hashing_secret();
+        let certificate_store = CertificateStore::from(loader);
+        Self {
+            trust_token_bearer: false,
+            certificate_store,
+            password_hashing_secret,
+            identity: DefaultIdentity,
+            owner_key: None,
+            sessions: None,
+        }
+    }
+}
+
+impl<I> VaultBuilder<I> {
+    pub fn trust_token_bearer(mut self, trust_token_bearer: bool) -> Self {
+        self.trust_token_bearer = trust_token_bearer;
+        self
+    }
+
+    /// Replace the default check that the user on the request is the user on the token
+    pub fn identity<J>(self, identity: J) -> VaultBuilder<J> {
+        VaultBuilder {
+            trust_token_bearer: self.trust_token_bearer,
+            certificate_store: self.certificate_store,
+            password_hashing_secret: self.password_hashing_secret,
+            identity,
+            owner_key: self.owner_key,
+            sessions: self.sessions,
+        }
+    }
+
+    /// MAC the user ids inside tokens with a key derived from the password hashing secret (see `owner::TokenOwnerKey`)
+    ///
+    /// The user on a request must then match the sealed user on the token before `identity` sees the two,
+    /// a token whose user was not sealed by this server fails with `LoginFailed::InvalidTokenOwner`
+    pub fn seal_token_owner(mut self) -> Self {
+        self.owner_key = Some(TokenOwnerKey::derive(self.password_hashing_secret.as_str()));
+        self
+    }
+
+    /// Give every login its own session and keep an index of them per user (see `stores::index::SessionIndex`)
+    ///
+    /// Without it jwtvault keeps one session per user and a login replaces the previous one.
+    /// With it the user id inside tokens carries a session id, `limit` caps the sessions a user holds,
+    /// and `ComposableVault::list_sessions` / `revoke_sessions` work on the index.
+    /// The vault and its clones update a user's index one at a time (see `stores::index::IndexLocks`)
+    pub fn track_sessions(mut self, limit: SessionLimit) -> Self {
+        self.sessions = Some(limit);
+        self
+    }
+
+    /// A hasher with the vault's secret, for user lookups which verify passwords themselves
+    pub fn password_hasher(&self) -> ArgonPasswordHasher {
+        ArgonPasswordHasher::from(self.password_hashing_secret.clone())
+    }
+
+    pub fn build<U, P>(self, users: U, store: P) -> ComposableVault<U, P, I> {
+        let password_hasher = self.password_hasher();
+        ComposableVault {
+            trust_token_bearer: self.trust_token_bearer,
+            certificate_store: Arc::new(self.certificate_store),
+            password_hasher: Arc::new(password_hasher),
+            users,
+            store,
+            identity: Arc::new(self.identity),
+            owner_key: self.owner_key.map(Arc::new),
+            sessions: self.sessions,
+            index_locks: IndexLocks::default(),
+        }
+    }
+}
+
+
+impl<U, P: Sweep, I> Sweep for ComposableVault<U, P, I> {
+    fn sweep(&mut self) -> usize {
+        self.store.sweep()
+    }
+}
+
+impl<U, P, I> PersistenceHasher<DefaultHasher> for ComposableVault<U, P, I> {}
+
+impl<U, P, I> TrustToken for ComposableVault<U, P, I> {
+    fn trust_token_bearer(&self) -> bool {
+        self.trust_token_bearer
+    }
+}
+
+impl<U, P, I> PasswordHasher<ArgonPasswordHasher> for ComposableVault<U, P, I> {
+    fn hash_user_password<T: AsRef<str>>(&self, user: T, password: T) -> Result<String, Error> {
+        self.password_hasher.hash_user_password(user, password)
+    }
+
+    fn verify_user_password<T: AsRef<str>>(&self, user: T, password: T, hash: T) -> Result<bool, Error> {
+        self.password_hasher.verify_user_password(user, password, hash)
+    }
+}
+
+impl<U, P, I> Store for ComposableVault<U, P, I> {
+    fn public_authentication_certificate(&self) -> &PublicKey {
+        self.certificate_store.public_authentication_certificate()
+    }
+
+    fn private_authentication_certificate(&self) -> &PrivateKey {
+        self.certificate_store.private_authentication_certificate()
+    }
+
+    fn public_refresh_certificate(&self) -> &PublicKey {
+        self.certificate_store.public_refresh_certificate()
+    }
+
+    fn private_refresh_certificate(&self) -> &PrivateKey {
+        self.certificate_store.private_refresh_certificate()
+    }
+}
+
+#[async_trait]
+impl<U, P, I> Persistence for ComposableVault<U, P, I>
+    where U: UserAuthentication + Send + Sync, P: Persistence + Send + Sync, I: UserIdentity + Send + Sync {
+    async fn store(&mut self, key: u64, value: String) {
+        self.store.store(key, value).await
+    }
+
+    async fn load(&self, key: u64) -> Option<&String> {
+        self.store.load(key).await
+    }
+
+    async fn remove(&mut self, key: u64) -> Option<String> {
+        self.store.remove(key).await
+    }
+}
+
+#[async_trait]
+impl<U, P, I> UserIdentity for ComposableVault<U, P, I>
+    where U: UserAuthentication + Send + Sync, P: Persistence + Send + Sync, I: UserIdentity + Send + Sync {
+    /// `user` is sealed when it comes through `Workflow`, plain when it comes from a request
+    async fn check_same_user(&self, user: &str, user_from_token: &str) -> Result<(), Error> {
+        let (user, owner) = match &self.owner_key {
+            Some(key) => {
+                let owner = key.open(user_from_token);
+                if owner.is_none() {
+                    let msg = "Login Failed".to_string();
+                    let reason = "Token owner is not sealed by this server".to_string();
+                    return Err(LoginFailed::InvalidTokenOwner(msg, reason).into());
+                };
+                (key.open(user).unwrap_or(user), owner.unwrap())
+            }
+            None => (user, user_from_token),
+        };
+        // Only the token carries the session id, a request names the user
+        let owner = if self.sessions.is_some() && user != owner { session_user(owner) } else { owner };
+        self.identity.check_same_user(user, owner).await
+    }
+}
+
+#[async_trait]
+impl<U, P, I> UserAuthentication for ComposableVault<U, P, I>
+    where U: UserAuthentication + Send + Sync, P: Persistence + Send + Sync, I: UserIdentity + Send + Sync {
+    /// `user` is sealed by `Workflow::login` when the vault seals token owners, the user lookup sees it plain
+    /// and without the session id of a tracked session
+    async fn check_user_valid(&mut self, user: &str, password: &str) -> Result<Option<Session>, Error> {
+        let user = match &self.owner_key {
+            Some(key) => key.open(user),
+            None => Some(user),
+        };
+        if user.is_none() {
+            let msg = "Login Failed".to_string();
+            let reason = "Token owner is not sealed by this server".to_string();
+            return Err(LoginFailed::InvalidTokenOwner(msg, reason).into());
+        };
+        let user = user.unwrap();
+        let user = if self.sessions.is_some() { session_user(user) } else { user };
+        self.users.check_user_valid(user, password).await
+    }
+}
+
+#[async_trait]
+impl<U, P, I> Workflow<DefaultHasher, ArgonPasswordHasher> for ComposableVault<U, P, I>
+    where U: UserAuthentication + Send + Sync, P: StoreUntil + Send + Sync, I: UserIdentity + Send + Sync {
+    async fn login(&mut self, user: &str, pass: &str, authentication_token_expiry_in_seconds: Option<i64>, refresh_token_expiry_in_seconds: Option<i64>) -> Result<Token, Error> {
+        let limit = match self.sessions {
+            Some(limit) => limit,
+            None => {
+                let user = self.token_owner(user, None);
+                let token = continue_login(self, user.as_ref(), pass, authentication_token_expiry_in_seconds, refresh_token_expiry_in_seconds).await?;
+                keep_stored(&mut self.store, self.certificate_store.public_refresh_certificate(), user.as_ref()).await?;
+                return Ok(token);
+            }
+        };
+        let issued_at = compute_timestamp_in_seconds();
+        let id = format!("{:016x}", rand::random::<u64>());
+        let owner = self.token_owner(user, Some(id.as_str()));
+        let token = continue_login(self, owner.as_ref(), pass, authentication_token_expiry_in_seconds, refresh_token_expiry_in_seconds).await?;
+        let expires_at = keep_stored(&mut self.store, self.certificate_store.public_refresh_certificate(), owner.as_ref()).await?;
+
+        let locks = self.index_locks.clone();
+        let _guard = locks.lock(user).await;
+        let mut index = self.live_sessions(user).await;
+        if let Some(max_sessions) = limit.max_sessions {
+            if index.len() >= max_sessions && limit.policy == LimitPolicy::Reject {
+                self.end_session(user, Some(id.as_str())).await;
+                let msg = "Login Failed".to_string();
+                let reason = format!("User: {} holds {} of {} sessions", user, index.len(), max_sessions);
+                return Err(SessionErrors::LimitReached(msg, reason).into());
+            };
+            while index.len() >= max_sessions {
+                match index.pop_oldest() {
+                    Some(oldest) => self.end_session(user, Some(oldest.id.as_str())).await,
+                    None => break,
+                };
+            }
+        };
+        index.insert(SessionRecord { id, issued_at, expires_at });
+        index.save(&mut self.store).await;
+        Ok(token)
+    }
+
+    /// A tracked session is renewed under the user id inside its token, which carries the session id
+    async fn renew(&mut self, user: &str, client_refresh_token: &String, authentication_token_expiry_in_seconds: Option<i64>) -> Result<String, Error> {
+        let claims = resolve_session_from_client_refresh_token(self, user, client_refresh_token).await?;
+        let owner = match self.sessions {
+            Some(_) => String::from_utf8_lossy(claims.sub()).to_string(),
+            None => self.token_owner(user, None).into_owned(),
+        };
+        let token = continue_renew(self, owner.as_str(), client_refresh_token, authentication_token_expiry_in_seconds).await?;
+        // continue_renew stored the new authentication digest for the store's expiry, it lasts as long as the session
+        let digest_reference = resolve_authentication_reference::<_, DefaultHasher>(owner.as_bytes());
+        keep_until(&mut self.store, digest_reference, *claims.exp()).await;
+        Ok(token)
+    }
+
+    async fn logout(&mut self, user: &str, client_authentication_token: &String) -> Result<(), Error> {
+        if self.sessions.is_none() {
+            let user = self.token_owner(user, None);
+            return continue_logout(self, user.as_ref(), client_authentication_token).await;
+        };
+        let claims = resolve_session_from_client_authentication_token(self, user, client_authentication_token).await?;
+        let owner = String::from_utf8_lossy(claims.sub()).to_string();
+        continue_logout(self, owner.as_str(), client_authentication_token).await?;
+        // continue_logout leaves the authentication digest behind, and the session in the index
+        if let Some((user, id)) = self.session_of(owner.as_str()) {
+            self.end_session(user, Some(id)).await;
+            let locks = self.index_locks.clone();
+            let _guard = locks.lock(user).await;
+            let mut index = SessionIndex::load(&self.store, user).await;
+            if index.remove(id).is_some() {
+                index.save(&mut self.store).await;
+            };
+        };
+        Ok(())
+    }
+
+    async fn revoke(&mut self, client_refresh_token: &String) -> Result<(), Error> {
+        continue_revoke(self, client_refresh_token).await
+    }
+}
+
+
+/// `UserAuthentication` over users and their (argon hashed) password held in memory, cheap to clone.
+/// The server side session holds the user, keyed by its digest
+#[derive(Clone)]
+pub struct SharedUsers {
+    users: Arc<HashMap<String, String>>,
+    hasher: Arc<ArgonPasswordHasher>,
+}
+
+impl SharedUsers {
+    /// `hasher` must use the vault's password hashing secret, see `VaultBuilder::password_hasher`
+    pub fn new(users: HashMap<String, String>, hasher: ArgonPasswordHasher) -> Self {
+        Self { users: Arc::new(users), hasher: Arc::new(hasher) }
+    }
+}
+
+#[async_trait]
+impl UserAuthentication for SharedUsers {
+    async fn check_user_valid(&mut self, user: &str, password: &str) -> Result<Option<Session>, Error> {
+        let password_from_disk = self.users.get(user);
+        if password_from_disk.is_none() {
+            let msg = "Login Failed".to_string();
+            let reason = "Invalid userid/password".to_string();
+            return Err(LoginFailed::InvalidPassword(msg, reason).into());
+        };
+        let password_from_disk = password_from_disk.unwrap();
+        if !self.hasher.verify_user_password(user, password, password_from_disk.as_str())? {
+            let msg = "Login Failed".to_string();
+            let reason = "Invalid userid/password".to_string();
+            return Err(LoginFailed::InvalidPassword(msg, reason).into());
+        };
+        let reference = digest::<_, DefaultHasher>(user.as_bytes());
+        let mut server = HashMap::new();
+        server.insert(reference, user.as_bytes().to_vec());
+        let session = Session::new(None, Some(server));
+        Ok(Some(session))
+    }
+}
+
+
+/// Session storage that can be swept, as a trait object
+pub trait SessionStore: StoreUntil + Sweep + Send + Sync {}
+
+impl<T: StoreUntil + Sweep + Send + Sync> SessionStore for T {}
+
+/// Lets a `ComposableVault` pick its session storage at runtime
+#[async_trait]
+impl Persistence for Box<dyn SessionStore> {
+    async fn store(&mut self, key: u64, value: String) {
+        (**self).store(key, value).await
+    }
+
+    async fn load(&self, key: u64) -> Option<&String> {
+        (**self).load(key).await
+    }
+
+    async fn remove(&mut self, key: u64) -> Option<String> {
+        (**self).remove(key).await
+    }
+}
+
+#[async_trait]
+impl StoreUntil for Box<dyn SessionStore> {
+    async fn store_until(&mut self, key: u64, value: String, expires_at: Option<i64>) {
+        (**self).store_until(key, value, expires_at).await
+    }
+}
+
+impl Sweep for Box<dyn SessionStore> {
+    fn sweep(&mut self) -> usize {
+        (**self).sweep()
+    }
+}
+
+/// `DynamicVault` keeps sessions in a private `HashMap`, this vault keeps them in `store` instead
+/// and leaves keys, password hashing and the user checks with the `DynamicVault`
+pub struct DynamicStoreVault {
+    vault: DynamicVault,
+    store: Box<dyn SessionStore>,
+}
+
+impl DynamicStoreVault {
+    pub fn new(vault: DynamicVault, store: Box<dyn SessionStore>) -> Self {
+        Self { vault, store }
+    }
+
+    pub fn store(&self) -> &dyn SessionStore {
+        self.store.as_ref()
+    }
+}
+
+impl Sweep for DynamicStoreVault {
+    fn sweep(&mut self) -> usize {
+        self.store.sweep()
+    }
+}
+
+impl PersistenceHasher<DefaultHasher> for DynamicStoreVault {}
+
+impl TrustToken for DynamicStoreVault {
+    fn trust_token_bearer(&self) -> bool {
+        self.vault.trust_token_bearer()
+    }
+}
+
+impl PasswordHasher<ArgonPasswordHasher> for DynamicStoreVault {
+    fn hash_user_password<T: AsRef<str>>(&self, user: T, password: T) -> Result<String, Error> {
+        self.vault.hash_user_password(user, password)
+    }
+
+    fn verify_user_password<T: AsRef<str>>(&self, user: T, password: T, hash: T) -> Result<bool, Error> {
+        self.vault.verify_user_password(user, password, hash)
+    }
+}
+
+impl Store for DynamicStoreVault {
+    fn public_authentication_certificate(&self) -> &PublicKey {
+        self.vault.public_authentication_certificate()
+    }
+
+    fn private_authentication_certificate(&self) -> &PrivateKey {
+        self.vault.private_authentication_certificate()
+    }
+
+    fn public_refresh_certificate(&self) -> &PublicKey {
+        self.vault.public_refresh_certificate()
+    }
+
+    fn private_refresh_certificate(&self) -> &PrivateKey {
+        self.vault.private_refresh_certificate()
+    }
+}
+
+#[async_trait]
+impl Persistence for DynamicStoreVault {
+    async fn store(&mut self, key: u64, value: String) {
+        self.store.store(key, value).await
+    }
+
+    async fn load(&self, key: u64) -> Option<&String> {
+        self.store.load(key).await
+    }
+
+    async fn remove(&mut self, key: u64) -> Option<String> {
+        self.store.remove(key).await
+    }
+}
+
+#[async_trait]
+impl UserIdentity for DynamicStoreVault {
+    async fn check_same_user(&self, user: &str, user_from_token: &str) -> Result<(), Error> {
+        self.vault.check_same_user(user, user_from_token).await
+    }
+}
+
+#[async_trait]
+impl UserAuthentication for DynamicStoreVault {
+    async fn check_user_valid(&mut self, user: &str, password: &str) -> Result<Option<Session>, Error> {
+        self.vault.check_user_valid(user, password).await
+    }
+}
+
+#[async_trait]
+impl Workflow<DefaultHasher, ArgonPasswordHasher> for DynamicStoreVault {
+    async fn login(&mut self, user: &str, pass: &str, authentication_token_expiry_in_seconds: Option<i64>, refresh_token_expiry_in_seconds: Option<i64>) -> Result<Token, Error> {
+        let token = continue_login(self, user, pass, authentication_token_expiry_in_seconds, refresh_token_expiry_in_seconds).await?;
+        keep_stored(&mut self.store, self.vault.public_refresh_certificate(), user).await?;
+        Ok(token)
+    }
+
+    async fn renew(&mut self, user: &str, client_refresh_token: &String, authentication_token_expiry_in_seconds: Option<i64>) -> Result<String, Error> {
+        let claims = resolve_session_from_client_refresh_token(self, user, client_refresh_token).await?;
+        let token = continue_renew(self, user, client_refresh_token, authentication_token_expiry_in_seconds).await?;
+        let digest_reference = resolve_authentication_reference::<_, DefaultHasher>(user.as_bytes());
+        keep_until(&mut self.store, digest_reference, *claims.exp()).await;
+        Ok(token)
+    }
+
+    async fn logout(&mut self, user: &str, client_authentication_token: &String) -> Result<(), Error> {
+        continue_logout(self, user, client_authentication_token).await
+    }
+
+    async fn revoke(&mut self, client_refresh_token: &String) -> Result<(), Error> {
+        continue_revoke(self, client_refresh_token).await
+    }
+}
+
+#[cfg(test)]
+mod vault {
+    use super::*;
+    use crate::stores::memory::MemoryPersistence;
+    use crate::stores::sharded::ShardedPersistence;
+    use std::thread;
+
+    fn users(builder: &VaultBuilder) -> SharedUsers {
+        let hasher = builder.password_hasher();
+        let mut users = HashMap::new();
+        for user in ["john_doe", "jane_doe"].iter() {
+            let hash = hasher.hash_user_password(*user, *user).unwrap();
+            users.insert(user.to_string(), hash);
+        }
+        SharedUsers::new(users, hasher)
+    }
+
+    fn is_invalid_token_owner(result: Result<ServerClaims, Error>) -> bool {
+        match result.err().unwrap().downcast_ref::<LoginFailed>() {
+            Some(LoginFailed::InvalidTokenOwner(_, _)) => true,
+            _ => false,
+        }
+    }
+
+    /// Logs nothing and keeps nothing, like a database that is down
+    struct FailingStore;
+
+    #[async_trait]
+    impl Persistence for FailingStore {
+        async fn store(&mut self, _: u64, _: String) {}
+
+        async fn load(&self, _: u64) -> Option<&String> {
+            None
+        }
+
+        async fn remove(&mut self, _: u64) -> Option<String> {
+            None
+        }
+    }
+
+    #[async_trait]
+    impl StoreUntil for FailingStore {
+        async fn store_until(&mut self, _: u64, _: String, _: Option<i64>) {}
+    }
+
+    #[test]
+    fn login_fails_when_the_session_is_not_stored() {
+        let builder = VaultBuilder::new(CertificateManger::default());
+        let users = users(&builder);
+        let mut vault = builder.build(users, FailingStore);
+        let e = block_on(vault.login("john_doe", "john_doe", None, None)).err().unwrap();
+        assert_eq!(e.to_string(), "Login Failed. Reason: Session could not be stored");
+    }
+
+    #[test]
+    fn sessions_expire_with_the_refresh_token() {
+        let builder = VaultBuilder::new(CertificateManger::default());
+        let users = users(&builder);
+        let mut vault = builder.track_sessions(SessionLimit::default()).build(users, MemoryPersistence::new(Some(30)));
+
+        // continue_login takes the refresh expiry first, see `config::TokenConfig::login_expiries`
+        let token = block_on(vault.login("john_doe", "john_doe", Some(600), Some(60))).unwrap();
+        let exp = *decode_client_token(vault.public_refresh_certificate(), token.refresh()).unwrap().exp();
+        assert!(exp >= compute_timestamp_in_seconds() + 590);
+        assert_eq!(block_on(vault.list_sessions("john_doe"))[0].expires_at, exp);
+        // The server side token, the authentication digest and the index
+        assert_eq!(vault.store().len(), 3);
+        assert!(vault.store().entries().all(|(_, _, expires_at)| expires_at == Some(exp)));
+
+        let _ = block_on(vault.renew("john_doe", token.refresh(), Some(60))).unwrap();
+        assert_eq!(vault.store().len(), 3);
+        assert!(vault.store().entries().all(|(_, _, expires_at)| expires_at == Some(exp)));
+    }
+
+    #[test]
+    fn token_of_another_user_is_rejected() {
+        let builder = VaultBuilder::new(CertificateManger::default());
+        let users = users(&builder);
+        let store = ShardedPersistence::new(None, 4);
+        let mut vault = builder.seal_token_owner().build(users.clone(), store.clone());
+
+        let john = block_on(vault.login("john_doe", "john_doe", None, None)).unwrap();
+        let jane = block_on(vault.login("jane_doe", "jane_doe", None, None)).unwrap();
+
+        let claims = block_on(resolve_session_from_client_authentication_token(&mut vault, "john_doe", john.authentication())).unwrap();
+        let owner = String::from_utf8_lossy(claims.sub()).to_string();
+        assert!(owner.starts_with("john_doe.") && owner.len() > "john_doe.".len());
+
+        // John's tokens on Jane's requests
+        let result = block_on(resolve_session_from_client_authentication_token(&mut vault, "jane_doe", john.authentication()));
+        assert!(is_invalid_token_owner(result));
+        let result = block_on(resolve_session_from_client_refresh_token(&mut vault, "jane_doe", john.refresh()));
+        assert!(is_invalid_token_owner(result));
+        assert!(block_on(vault.renew("jane_doe", john.refresh(), None)).is_err());
+        assert!(block_on(vault.logout("jane_doe", john.authentication())).is_err());
+
+        let renewed = block_on(vault.renew("jane_doe", jane.refresh(), None)).unwrap();
+        assert!(block_on(resolve_session_from_client_authentication_token(&mut vault, "jane_doe", renewed.as_str())).is_ok());
+        assert!(block_on(vault.logout("john_doe", john.authentication())).is_ok());
+
+        // A session stored by a vault that does not seal its token owners
+        let builder = VaultBuilder::new(CertificateManger::default());
+        let mut unsealed = builder.build(users, store);
+        let john = block_on(unsealed.login("john_doe", "john_doe", None, None)).unwrap();
+        assert!(block_on(resolve_session_from_client_authentication_token(&mut unsealed, "john_doe", john.authentication())).is_ok());
+        let result = block_on(resolve_session_from_client_authentication_token(&mut vault, "john_doe", john.authentication()));
+        assert!(is_invalid_token_owner(result));
+    }
+
+    fn login(vault: &mut ComposableVault<SharedUsers, ShardedPersistence>, user: &str) -> Token {
+        block_on(vault.login(user, user, None, None)).unwrap()
+    }
+
+    #[test]
+    fn sessions_are_capped_listed_and_revoked() {
+        let builder = VaultBuilder::new(CertificateManger::default());
+        let users = users(&builder);
+        let limit = SessionLimit { max_sessions: Some(2), policy: LimitPolicy::EvictOldest };
+        let mut vault = builder.seal_token_owner().track_sessions(limit).build(users.clone(), ShardedPersistence::new(None, 4));
+
+        let first = login(&mut vault, "john_doe");
+        let second = login(&mut vault, "john_doe");
+        let third = login(&mut vault, "john_doe");
+        let jane = login(&mut vault, "jane_doe");
+
+        // The oldest of John's sessions made room for the third
+        let sessions = block_on(vault.list_sessions("john_doe"));
+        assert_eq!(sessions.len(), 2);
+        assert!(block_on(resolve_session_from_client_authentication_token(&mut vault, "john_doe", first.authentication())).is_err());
+        assert!(block_on(resolve_session_from_client_authentication_token(&mut vault, "john_doe", second.authentication())).is_ok());
+        assert!(block_on(resolve_session_from_client_authentication_token(&mut vault, "jane_doe", third.authentication())).is_err());
+
+        let renewed = block_on(vault.renew("john_doe", third.refresh(), None)).unwrap();
+        assert!(block_on(resolve_session_from_client_authentication_token(&mut vault, "john_doe", renewed.as_str())).is_ok());
+        assert!(block_on(vault.logout("john_doe", second.authentication())).is_ok());
+        assert!(block_on(resolve_session_from_client_authentication_token(&mut vault, "john_doe", second.authentication())).is_err());
+        assert_eq!(block_on(vault.list_sessions("john_doe")).len(), 1);
+
+        // e.g. after a password change
+        assert_eq!(block_on(vault.revoke_sessions("john_doe")), 1);
+        assert!(block_on(vault.list_sessions("john_doe")).is_empty());
+        assert!(block_on(resolve_session_from_client_authentication_token(&mut vault, "john_doe", renewed.as_str())).is_err());
+        assert!(block_on(vault.renew("john_doe", third.refresh(), None)).is_err());
+        assert!(block_on(resolve_session_from_client_authentication_token(&mut vault, "jane_doe", jane.authentication())).is_ok());
+
+        let builder = VaultBuilder::new(CertificateManger::default());
+        let limit = SessionLimit { max_sessions: Some(1), policy: LimitPolicy::Reject };
+        let mut vault = builder.track_sessions(limit).build(users, ShardedPersistence::new(None, 4));
+        let first = login(&mut vault, "john_doe");
+        let e = block_on(vault.login("john_doe", "john_doe", None, None)).err().unwrap();
+        assert!(e.downcast_ref::<SessionErrors>().is_some());
+        assert!(block_on(resolve_session_from_client_authentication_token(&mut vault, "john_doe", first.authentication())).is_ok());
+        assert_eq!(block_on(vault.list_sessions("john_doe")).len(), 1);
+    }
+
+    #[test]
+    fn clones_logging_in_together_keep_every_session() {
+        let builder = VaultBuilder::new(CertificateManger::default());
+        let users = users(&builder);
+        let vault = builder.track_sessions(SessionLimit::default()).build(users, ShardedPersistence::new(None, 4));
+        let workers = (0..2).map(|_| {
+            let mut vault = vault.clone();
+            thread::spawn(move || {
+                for _ in 0..8 {
+                    login(&mut vault, "john_doe");
+                }
+            })
+        }).collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(block_on(vault.list_sessions("john_doe")).len(), 16);
+    }
+
+    #[test]
+    fn session_id_is_stripped_from_token_owner() {
+        assert_eq!(session_user("john_doe#00ff00ff00ff00ff"), "john_doe");
+        assert_eq!(session_user("john#doe"), "john#doe");
+        assert_eq!(session_user("john_doe#00ff00ff00ff00fg"), "john_doe#00ff00ff00ff00fg");
+        assert_eq!(session_user("john_doe"), "john_doe");
+    }
+}